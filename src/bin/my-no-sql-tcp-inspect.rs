@@ -0,0 +1,647 @@
+use std::{collections::BTreeMap, io::Read};
+
+use my_no_sql_tcp_shared::{
+    capture::{CaptureDirection, CaptureReader},
+    tcp_packets::*,
+    CompressionDictionaries, DecodeLimits, MyNoSqlTcpContract, RecordingSocketReader,
+};
+use my_tcp_sockets::socket_reader::SocketReaderInMem;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+enum InputFormat {
+    Capture,
+    Hex,
+    Raw,
+}
+
+struct Settings {
+    format: InputFormat,
+    path: Option<String>,
+    stats: bool,
+    limits: DecodeLimits,
+}
+
+struct InspectFrame {
+    capture_info: Option<(CaptureDirection, DateTimeAsMicroseconds, i32)>,
+    payload: Vec<u8>,
+}
+
+#[derive(Default)]
+struct PacketStats {
+    packets: usize,
+    wire_bytes: usize,
+    uncompressed_bytes: usize,
+}
+
+const USAGE: &str =
+    "Usage: my-no-sql-tcp-inspect [--stats] [--limits strict|unlimited] [--capture <file> | --hex [file] | --raw [file]]
+
+Without a file hex and raw input is read from stdin. Default input is raw bytes from stdin.
+Input is decoded with strict limits by default, so a damaged length is reported instead of being allocated.";
+
+#[tokio::main]
+async fn main() {
+    let settings = match parse_args() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let frames = match read_frames(&settings).await {
+        Ok(frames) => frames,
+        Err(err) => {
+            eprintln!("Can not read input. Err: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let dictionaries = CompressionDictionaries::new();
+
+    if settings.stats {
+        print_stats(frames, &settings.limits, &dictionaries).await;
+    } else {
+        for frame in frames {
+            print_frame(frame, &settings.limits, &dictionaries).await;
+        }
+    }
+}
+
+fn parse_args() -> Result<Settings, String> {
+    let mut result = Settings {
+        format: InputFormat::Raw,
+        path: None,
+        stats: false,
+        limits: DecodeLimits::strict(),
+    };
+
+    let mut args = std::env::args().skip(1).peekable();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stats" => result.stats = true,
+            "--limits" => {
+                result.limits = match args.next().as_deref() {
+                    Some("strict") => DecodeLimits::strict(),
+                    Some("unlimited") => DecodeLimits::unlimited(),
+                    _ => return Err("--limits requires strict or unlimited".to_string()),
+                };
+            }
+            "--capture" => {
+                result.format = InputFormat::Capture;
+                result.path = Some(args.next().ok_or("--capture requires a file name")?);
+            }
+            "--hex" | "--raw" => {
+                result.format = if arg == "--hex" {
+                    InputFormat::Hex
+                } else {
+                    InputFormat::Raw
+                };
+
+                if let Some(next) = args.peek() {
+                    if !next.starts_with("--") && next != "-" {
+                        result.path = args.next();
+                    }
+                }
+            }
+            "-" => {}
+            "--help" | "-h" => return Err("MyNoSql TCP protocol inspector".to_string()),
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    Ok(result)
+}
+
+fn read_input(path: &Option<String>) -> std::io::Result<Vec<u8>> {
+    match path {
+        Some(path) => std::fs::read(path),
+        None => {
+            let mut result = Vec::new();
+            std::io::stdin().read_to_end(&mut result)?;
+            Ok(result)
+        }
+    }
+}
+
+async fn read_frames(settings: &Settings) -> Result<Vec<InspectFrame>, String> {
+    match settings.format {
+        InputFormat::Capture => {
+            let path = settings.path.as_ref().unwrap();
+            let mut reader = CaptureReader::open(path).map_err(|err| format!("{}", err))?;
+            reader.set_limits(settings.limits.clone());
+
+            let mut result = Vec::new();
+
+            while let Some(frame) = reader.read_next_frame().map_err(|err| format!("{}", err))? {
+                result.push(InspectFrame {
+                    capture_info: Some((frame.direction, frame.timestamp, frame.connection_id)),
+                    payload: frame.payload,
+                });
+            }
+
+            Ok(result)
+        }
+        InputFormat::Hex => {
+            let input = read_input(&settings.path).map_err(|err| format!("{}", err))?;
+            let bytes = parse_hex(String::from_utf8_lossy(&input).as_ref())?;
+            Ok(split_raw_stream(bytes, &settings.limits).await)
+        }
+        InputFormat::Raw => {
+            let bytes = read_input(&settings.path).map_err(|err| format!("{}", err))?;
+            Ok(split_raw_stream(bytes, &settings.limits).await)
+        }
+    }
+}
+
+fn parse_hex(src: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = src
+        .split_whitespace()
+        .map(|token| token.trim_start_matches("0x"))
+        .flat_map(|token| token.bytes())
+        .collect();
+
+    if digits.len() % 2 != 0 {
+        return Err("Hex input has odd amount of digits".to_string());
+    }
+
+    let mut result = Vec::with_capacity(digits.len() / 2);
+
+    for pair in digits.chunks(2) {
+        let pair = std::str::from_utf8(pair).map_err(|err| format!("{}", err))?;
+        let byte =
+            u8::from_str_radix(pair, 16).map_err(|_| format!("Invalid hex byte: {}", pair))?;
+        result.push(byte);
+    }
+
+    Ok(result)
+}
+
+// Raw stream has no framing, so we deserialize packets one by one and use the amount of bytes
+// the reader has consumed to find the beginning of the next one.
+async fn split_raw_stream(bytes: Vec<u8>, limits: &DecodeLimits) -> Vec<InspectFrame> {
+    let mut result = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        if bytes[pos] == ERROR {
+            result.push(InspectFrame {
+                capture_info: None,
+                payload: bytes[pos..].to_vec(),
+            });
+            break;
+        }
+
+        let mut socket_reader = SocketReaderInMem::new(bytes[pos..].to_vec());
+        let mut reader = RecordingSocketReader::new(&mut socket_reader, false);
+
+        match MyNoSqlTcpContract::deserialize_with_limits(&mut reader, limits).await {
+            Ok(_) => {
+                let size = reader.get_consumed();
+                result.push(InspectFrame {
+                    capture_info: None,
+                    payload: bytes[pos..pos + size].to_vec(),
+                });
+                pos += size;
+            }
+            Err(err) => {
+                eprintln!("Can not decode packet at offset {}. Err: {:?}", pos, err);
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+fn get_packet_version(payload: &[u8]) -> Option<u8> {
+    match *payload.first()? {
+        ERROR
+        | GREETING_FROM_NODE
+        | SUBSCRIBE_AS_NODE
+        | TABLES_NOT_FOUND
+        | UNSUBSCRIBE
+        | UPDATE_PARTITIONS_LAST_READ_TIME
+        | UPDATE_ROWS_LAST_READ_TIME
+        | UPDATE_PARTITIONS_EXPIRATION_TIME
+        | UPDATE_ROWS_EXPIRATION_TIME
+        | CONFIRMATION
+        | COMPRESSED_PAYLOAD_WITH_ALGORITHM
+        | COMPRESSION_DICTIONARY
+        | CHECKSUMMED_PAYLOAD
+        | LARGE_INIT_TABLE
+        | LARGE_COMPRESSED_PAYLOAD
+        | ENCRYPTED_PAYLOAD
+        | UPDATE_LAST_READ_TIME_BATCH
+        | UPDATE_EXPIRATION_TIME_BATCH
+        | CAPABILITIES => payload.get(1).copied(),
+        _ => None,
+    }
+}
+
+// Dictionaries are shipped in-band, so we pick them up while walking through the frames
+fn register_dictionary(contract: &MyNoSqlTcpContract, dictionaries: &CompressionDictionaries) {
+    if let MyNoSqlTcpContract::CompressionDictionary {
+        dictionary_id,
+        table_name,
+        dictionary,
+    } = contract
+    {
+        dictionaries.register(*dictionary_id, table_name, dictionary.clone());
+    }
+}
+
+async fn print_frame(
+    frame: InspectFrame,
+    limits: &DecodeLimits,
+    dictionaries: &CompressionDictionaries,
+) {
+    if let Some((direction, timestamp, connection_id)) = &frame.capture_info {
+        print!(
+            "[{}] conn:{} {:?} ",
+            timestamp.unix_microseconds, connection_id, direction
+        );
+    }
+
+    if frame.payload.first() == Some(&ERROR) {
+        println!("Error ({} bytes)", frame.payload.len());
+        return;
+    }
+
+    let mut reader = SocketReaderInMem::new(frame.payload.clone());
+
+    let contract = match MyNoSqlTcpContract::deserialize_with_limits(&mut reader, limits).await {
+        Ok(contract) => contract,
+        Err(err) => {
+            println!("Can not decode packet. Err: {:?}", err);
+            return;
+        }
+    };
+
+    register_dictionary(&contract, dictionaries);
+
+    print!("{}", contract.get_packet_name());
+
+    if let Some(version) = get_packet_version(&frame.payload) {
+        print!(" v{}", version);
+    }
+
+    println!(" ({} bytes)", frame.payload.len());
+
+    if let MyNoSqlTcpContract::ChecksummedPayload { algorithm, .. } = &contract {
+        println!("  checksum: {} (verified)", algorithm.as_str());
+    }
+
+    if contract.is_compressed() || is_checksummed(&contract) {
+        if let MyNoSqlTcpContract::CompressedPayloadWithAlgorithm {
+            algorithm,
+            dictionary_id,
+            ..
+        } = &contract
+        {
+            println!("  algorithm: {}", algorithm.as_str());
+
+            if let Some(dictionary_id) = dictionary_id {
+                println!("  dictionary_id: {}", dictionary_id);
+            }
+        }
+
+        match contract
+            .decompress_if_compressed_with_dictionaries(limits, dictionaries, None)
+            .await
+        {
+            Ok(contract) => {
+                let uncompressed_size = contract.serialize().len();
+                println!(
+                    "  uncompressed: {} bytes, compression ratio: {:.2}",
+                    uncompressed_size,
+                    uncompressed_size as f64 / frame.payload.len() as f64
+                );
+                println!("  > {}", contract.get_packet_name());
+                print_contract_details(&contract);
+            }
+            Err(err) => println!("  Can not decompress payload. Err: {:?}", err),
+        }
+    } else {
+        print_contract_details(&contract);
+    }
+}
+
+fn print_contract_details(contract: &MyNoSqlTcpContract) {
+    if let Some(table_name) = contract.get_table_name() {
+        println!("  table: {}", table_name);
+    }
+
+    match contract {
+        MyNoSqlTcpContract::Greeting { name } => println!("  name: {}", name),
+        MyNoSqlTcpContract::GreetingFromNode {
+            node_location,
+            node_version,
+            compress,
+        } => println!(
+            "  location: {}, version: {}, compress: {}",
+            node_location, node_version, compress
+        ),
+        MyNoSqlTcpContract::Capabilities(capabilities) => println!(
+            "  algorithms: {:?}, checksums: {:?}, large frames: {}, sync batches: {}, read moments: {}, row expirations: {}",
+            capabilities.compression_algorithms,
+            capabilities.checksum_algorithms,
+            capabilities.supports_large_frames,
+            capabilities.supports_sync_batches,
+            capabilities.supports_read_moments,
+            capabilities.supports_row_expirations
+        ),
+        MyNoSqlTcpContract::InitTable { data, .. }
+        | MyNoSqlTcpContract::UpdateRows { data, .. } => {
+            println!("  data: {} bytes", data.len());
+            println!("  {}", String::from_utf8_lossy(data));
+        }
+        MyNoSqlTcpContract::InitPartition {
+            partition_key,
+            data,
+            ..
+        } => {
+            println!("  partition: {}", partition_key);
+            println!("  data: {} bytes", data.len());
+        }
+        MyNoSqlTcpContract::DeleteRows { rows, .. } => {
+            for row in rows {
+                println!("  delete: {}/{}", row.partition_key, row.row_key);
+            }
+        }
+        MyNoSqlTcpContract::UpdatePartitionsLastReadTime {
+            confirmation_id,
+            partitions,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            for partition_key in partitions {
+                println!("  partition: {}", partition_key);
+            }
+        }
+        MyNoSqlTcpContract::UpdateRowsLastReadTime {
+            confirmation_id,
+            partition_key,
+            row_keys,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            println!("  partition: {}", partition_key);
+            for row_key in row_keys {
+                println!("    row: {}", row_key);
+            }
+        }
+        MyNoSqlTcpContract::UpdatePartitionsReadMoments {
+            confirmation_id,
+            partitions,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            for (partition_key, read_moment) in partitions {
+                println!(
+                    "  partition: {}, read: {:?}",
+                    partition_key,
+                    read_moment.map(|itm| itm.unix_microseconds)
+                );
+            }
+        }
+        MyNoSqlTcpContract::UpdateRowsReadMoments {
+            confirmation_id,
+            partition_key,
+            rows,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            println!("  partition: {}", partition_key);
+            for (row_key, read_moment) in rows {
+                println!(
+                    "    row: {}, read: {:?}",
+                    row_key,
+                    read_moment.map(|itm| itm.unix_microseconds)
+                );
+            }
+        }
+        MyNoSqlTcpContract::UpdatePartitionsExpirationTime {
+            confirmation_id,
+            partitions,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            for (partition_key, expiration_time) in partitions {
+                println!(
+                    "  partition: {}, expires: {:?}",
+                    partition_key,
+                    expiration_time.map(|itm| itm.unix_microseconds)
+                );
+            }
+        }
+        MyNoSqlTcpContract::UpdateRowsExpirationTime {
+            confirmation_id,
+            partition_key,
+            row_keys,
+            expiration_time,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            println!("  partition: {}", partition_key);
+            println!(
+                "  expires: {:?}",
+                expiration_time.map(|itm| itm.unix_microseconds)
+            );
+            for row_key in row_keys {
+                println!("    row: {}", row_key);
+            }
+        }
+        MyNoSqlTcpContract::UpdateRowsExpirationTimePerRow {
+            confirmation_id,
+            partition_key,
+            rows,
+            ..
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            println!("  partition: {}", partition_key);
+            for (row_key, expiration_time) in rows {
+                println!(
+                    "    row: {}, expires: {:?}",
+                    row_key,
+                    expiration_time.map(|itm| itm.unix_microseconds)
+                );
+            }
+        }
+        MyNoSqlTcpContract::Confirmation { confirmation_id } => {
+            println!("  confirmation_id: {}", confirmation_id);
+        }
+        MyNoSqlTcpContract::UpdateLastReadTimeBatch {
+            confirmation_id,
+            tables,
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            for table in tables {
+                for partition in &table.partitions {
+                    println!(
+                        "  table: {}, partition: {}, read: {:?}",
+                        table.table_name,
+                        partition.partition_key,
+                        partition
+                            .partition_read
+                            .map(|itm| itm.map(|itm| itm.unix_microseconds))
+                    );
+
+                    for (row_key, read_moment) in &partition.rows {
+                        println!(
+                            "    row: {}, read: {:?}",
+                            row_key,
+                            read_moment.map(|itm| itm.unix_microseconds)
+                        );
+                    }
+                }
+            }
+        }
+        MyNoSqlTcpContract::UpdateExpirationTimeBatch {
+            confirmation_id,
+            tables,
+        } => {
+            println!("  confirmation_id: {}", confirmation_id);
+            for table in tables {
+                for partition in &table.partitions {
+                    println!(
+                        "  table: {}, partition: {}, expires: {:?}",
+                        table.table_name,
+                        partition.partition_key,
+                        partition
+                            .partition_expiration
+                            .map(|itm| itm.map(|itm| itm.unix_microseconds))
+                    );
+
+                    for (row_key, expiration_time) in &partition.rows {
+                        println!(
+                            "    row: {}, expires: {:?}",
+                            row_key,
+                            expiration_time.map(|itm| itm.unix_microseconds)
+                        );
+                    }
+                }
+            }
+        }
+        MyNoSqlTcpContract::CompressionDictionary {
+            dictionary_id,
+            dictionary,
+            ..
+        } => {
+            println!("  dictionary_id: {}", dictionary_id);
+            println!("  dictionary: {} bytes", dictionary.len());
+        }
+        MyNoSqlTcpContract::EncryptedPayload {
+            key_id, ciphertext, ..
+        } => {
+            println!("  key_id: {}", key_id);
+            println!("  ciphertext: {} bytes", ciphertext.len());
+        }
+        _ => {}
+    }
+}
+
+fn is_checksummed(contract: &MyNoSqlTcpContract) -> bool {
+    matches!(contract, MyNoSqlTcpContract::ChecksummedPayload { .. })
+}
+
+async fn print_stats(
+    frames: Vec<InspectFrame>,
+    limits: &DecodeLimits,
+    dictionaries: &CompressionDictionaries,
+) {
+    let mut stats: BTreeMap<(String, String), PacketStats> = BTreeMap::new();
+
+    for frame in frames {
+        if frame.payload.first() == Some(&ERROR) {
+            continue;
+        }
+
+        let mut reader = SocketReaderInMem::new(frame.payload.clone());
+
+        let contract = match MyNoSqlTcpContract::deserialize_with_limits(&mut reader, limits).await
+        {
+            Ok(contract) => contract,
+            Err(_) => continue,
+        };
+
+        register_dictionary(&contract, dictionaries);
+
+        let contract = match contract
+            .decompress_if_compressed_with_dictionaries(limits, dictionaries, None)
+            .await
+        {
+            Ok(contract) => contract,
+            Err(_) => continue,
+        };
+
+        let key = (
+            contract.get_table_name().unwrap_or("-").to_string(),
+            contract.get_packet_name().to_string(),
+        );
+
+        let item = stats.entry(key).or_default();
+        item.packets += 1;
+        item.wire_bytes += frame.payload.len();
+        item.uncompressed_bytes += contract.serialize().len();
+    }
+
+    println!(
+        "{:<32} {:<32} {:>10} {:>14} {:>14} {:>8}",
+        "table", "packet", "packets", "wire bytes", "raw bytes", "ratio"
+    );
+
+    for ((table_name, packet_name), item) in stats {
+        println!(
+            "{:<32} {:<32} {:>10} {:>14} {:>14} {:>8.2}",
+            table_name,
+            packet_name,
+            item.packets,
+            item.wire_bytes,
+            item.uncompressed_bytes,
+            item.uncompressed_bytes as f64 / item.wire_bytes as f64
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn raw_stream_is_split_by_consumed_bytes() {
+        // Greeting of version 0 is shorter than the one we would serialize today
+        let mut bytes = vec![GREETING_FROM_NODE, 0];
+        my_no_sql_tcp_shared::common_serializers::serialize_pascal_string(&mut bytes, "loc");
+        my_no_sql_tcp_shared::common_serializers::serialize_pascal_string(&mut bytes, "1.0");
+        let greeting_len = bytes.len();
+
+        bytes.extend_from_slice(MyNoSqlTcpContract::Ping.serialize().as_slice());
+
+        let frames = split_raw_stream(bytes, &DecodeLimits::strict()).await;
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload.len(), greeting_len);
+        assert_eq!(frames[1].payload, MyNoSqlTcpContract::Ping.serialize());
+    }
+
+    #[tokio::test]
+    async fn damaged_length_is_reported_as_limit_violation() {
+        let mut bytes = vec![INIT_TABLE];
+        my_no_sql_tcp_shared::common_serializers::serialize_pascal_string(&mut bytes, "table");
+        my_no_sql_tcp_shared::common_serializers::serialize_i32(&mut bytes, i32::MAX);
+        bytes.extend_from_slice(&[0; 16]);
+
+        let mut reader = SocketReaderInMem::new(bytes.clone());
+        let err = MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &DecodeLimits::strict())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.is_limit_violation());
+
+        assert!(split_raw_stream(bytes, &DecodeLimits::strict())
+            .await
+            .is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// Keeps intermediate serialization/compression buffers between packets.
+// Buffers which grew above max_buffer_capacity are dropped, so one huge InitTable
+// does not pin its memory for the lifetime of the pool.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_buffer_capacity: usize,
+    allocated: AtomicUsize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize, max_buffer_capacity: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+            max_buffer_capacity,
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, capacity: usize) -> Vec<u8> {
+        let buffer = self.buffers.lock().unwrap().pop();
+
+        match buffer {
+            Some(mut buffer) => {
+                buffer.reserve(capacity);
+                buffer
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(capacity)
+            }
+        }
+    }
+
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > self.max_buffer_capacity {
+            return;
+        }
+
+        buffer.clear();
+
+        let mut buffers = self.buffers.lock().unwrap();
+
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    pub fn get_pooled_amount(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    // Buffers which were allocated because the pool was empty
+    pub fn get_allocated_amount(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+// Buffer which goes back to the pool when it is dropped, e.g. after the frame is written to the socket
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl PooledBuffer {
+    pub fn new(buffer: Vec<u8>, pool: Arc<BufferPool>) -> Self {
+        Self { buffer, pool }
+    }
+
+    // Buffer is given away and does not come back to the pool
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if self.buffer.capacity() > 0 {
+            self.pool.put(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(16, 4 * 1024 * 1024)
+    }
+}
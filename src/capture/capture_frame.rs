@@ -0,0 +1,49 @@
+use my_tcp_sockets::socket_reader::SocketReaderInMem;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::{DecodeError, DecodeLimits, MyNoSqlTcpContract};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Incoming,
+    Outgoing,
+}
+
+impl CaptureDirection {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            CaptureDirection::Incoming => 0,
+            CaptureDirection::Outgoing => 1,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CaptureDirection::Incoming),
+            1 => Some(CaptureDirection::Outgoing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureFrame {
+    pub direction: CaptureDirection,
+    pub timestamp: DateTimeAsMicroseconds,
+    pub connection_id: i32,
+    pub payload: Vec<u8>,
+}
+
+impl CaptureFrame {
+    pub fn serialize(&self, buffer: &mut Vec<u8>) {
+        crate::common_serializers::serialize_byte(buffer, self.direction.as_u8());
+        crate::common_serializers::serialize_i64(buffer, self.timestamp.unix_microseconds);
+        crate::common_serializers::serialize_i32(buffer, self.connection_id);
+        crate::common_serializers::serialize_byte_array(buffer, self.payload.as_slice());
+    }
+
+    pub async fn decode(&self, limits: &DecodeLimits) -> Result<MyNoSqlTcpContract, DecodeError> {
+        let mut reader = SocketReaderInMem::new(self.payload.clone());
+        MyNoSqlTcpContract::deserialize_with_limits(&mut reader, limits).await
+    }
+}
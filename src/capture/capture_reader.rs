@@ -0,0 +1,197 @@
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+    path::Path,
+};
+
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::{DecodeError, DecodeLimits, MyNoSqlTcpContract};
+
+use super::{CaptureDirection, CaptureFrame, CAPTURE_FILE_MAGIC, CAPTURE_FILE_VERSION};
+
+#[async_trait::async_trait]
+pub trait CaptureConsumer {
+    async fn on_frame(&self, frame: &CaptureFrame, contract: MyNoSqlTcpContract);
+}
+
+pub struct CaptureReader {
+    reader: Box<dyn Read + Send>,
+    limits: DecodeLimits,
+}
+
+impl CaptureReader {
+    pub fn new(mut reader: Box<dyn Read + Send>) -> std::io::Result<Self> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != CAPTURE_FILE_MAGIC {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "Not a MyNoSql capture file",
+            ));
+        }
+
+        if header[4] != CAPTURE_FILE_VERSION {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported capture file version {}", header[4]),
+            ));
+        }
+
+        Ok(Self {
+            reader,
+            limits: DecodeLimits::default(),
+        })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        Self::new(Box::new(BufReader::new(file)))
+    }
+
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    pub fn read_next_frame(&mut self) -> std::io::Result<Option<CaptureFrame>> {
+        let mut direction = [0u8; 1];
+
+        match self.reader.read_exact(&mut direction) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        let direction = CaptureDirection::from_u8(direction[0]).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid capture direction {}", direction[0]),
+            )
+        })?;
+
+        let mut buf = [0u8; 8];
+        self.reader.read_exact(&mut buf)?;
+        let timestamp = DateTimeAsMicroseconds::new(i64::from_le_bytes(buf));
+
+        let mut buf = [0u8; 4];
+        self.reader.read_exact(&mut buf)?;
+        let connection_id = i32::from_le_bytes(buf);
+
+        self.reader.read_exact(&mut buf)?;
+        let payload_size = self
+            .limits
+            .check_byte_array_size(i32::from_le_bytes(buf))
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, format!("{:?}", err)))?;
+
+        // Payload grows as it is read, so damaged size of the frame ends up as UnexpectedEof
+        let mut payload = Vec::new();
+        (&mut self.reader)
+            .take(payload_size as u64)
+            .read_to_end(&mut payload)?;
+
+        if payload.len() < payload_size {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Capture frame is cut off",
+            ));
+        }
+
+        Ok(Some(CaptureFrame {
+            direction,
+            timestamp,
+            connection_id,
+            payload,
+        }))
+    }
+
+    pub async fn replay(
+        &mut self,
+        consumer: &(impl CaptureConsumer + Send + Sync),
+    ) -> Result<usize, DecodeError> {
+        let mut frames = 0;
+
+        while let Some(frame) = self.read_next_frame().map_err(DecodeError::Io)? {
+            let contract = frame.decode(&self.limits).await?;
+            consumer.on_frame(&frame, contract).await;
+            frames += 1;
+        }
+
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureWriter;
+
+    struct CountingConsumer(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl CaptureConsumer for CountingConsumer {
+        async fn on_frame(&self, _frame: &CaptureFrame, _contract: MyNoSqlTcpContract) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    async fn write_capture(path: &std::path::Path) {
+        let writer = CaptureWriter::create(path).unwrap();
+        let connection_id = writer.register_connection();
+
+        writer.write_payload(
+            connection_id,
+            CaptureDirection::Outgoing,
+            MyNoSqlTcpContract::Ping.serialize(),
+        );
+
+        writer.write_payload(
+            connection_id,
+            CaptureDirection::Incoming,
+            MyNoSqlTcpContract::Subscribe {
+                table_name: "test".to_string(),
+            }
+            .serialize(),
+        );
+
+        writer.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn frames_are_replayed_as_written() {
+        let path = std::env::temp_dir().join(format!("capture-ok-{}.mnsc", std::process::id()));
+        write_capture(&path).await;
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+
+        let frame = reader.read_next_frame().unwrap().unwrap();
+        assert_eq!(frame.direction, CaptureDirection::Outgoing);
+        assert_eq!(frame.payload, MyNoSqlTcpContract::Ping.serialize());
+
+        let frame = reader.read_next_frame().unwrap().unwrap();
+        assert_eq!(frame.direction, CaptureDirection::Incoming);
+        assert!(reader.read_next_frame().unwrap().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_capture_is_an_error() {
+        let path = std::env::temp_dir().join(format!("capture-cut-{}.mnsc", std::process::id()));
+        write_capture(&path).await;
+
+        let mut content = std::fs::read(&path).unwrap();
+        content.truncate(content.len() - 2);
+        std::fs::write(&path, content).unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let consumer = CountingConsumer(std::sync::atomic::AtomicUsize::new(0));
+
+        let err = reader.replay(&consumer).await.unwrap_err();
+
+        assert!(matches!(err, DecodeError::Io(_)));
+        assert_eq!(consumer.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc, Mutex,
+    },
+};
+
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use super::{CaptureDirection, CaptureFrame, CAPTURE_FILE_MAGIC, CAPTURE_FILE_VERSION};
+
+enum CaptureCommand {
+    Frame(CaptureFrame),
+    Flush(tokio::sync::oneshot::Sender<std::io::Result<()>>),
+}
+
+// Frames are written by a dedicated thread, so socket tasks only enqueue them
+pub struct CaptureWriter {
+    sender: Mutex<mpsc::Sender<CaptureCommand>>,
+    next_connection_id: AtomicI32,
+}
+
+impl CaptureWriter {
+    pub fn new(mut writer: Box<dyn Write + Send>) -> std::io::Result<Self> {
+        writer.write_all(CAPTURE_FILE_MAGIC)?;
+        writer.write_all(&[CAPTURE_FILE_VERSION])?;
+
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::Builder::new()
+            .name("my-no-sql-capture".to_string())
+            .spawn(move || write_loop(writer, receiver))?;
+
+        Ok(Self {
+            sender: Mutex::new(sender),
+            next_connection_id: AtomicI32::new(0),
+        })
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        Self::new(Box::new(BufWriter::new(file)))
+    }
+
+    pub fn register_connection(&self) -> i32 {
+        self.next_connection_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    // Payload must be the frame exactly as it was sent or received
+    pub fn write_payload(&self, connection_id: i32, direction: CaptureDirection, payload: Vec<u8>) {
+        let frame = CaptureFrame {
+            direction,
+            timestamp: DateTimeAsMicroseconds::now(),
+            connection_id,
+            payload,
+        };
+
+        self.send(CaptureCommand::Frame(frame));
+    }
+
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        if !self.send(CaptureCommand::Flush(sender)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "Capture writer is stopped",
+            ));
+        }
+
+        match receiver.await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "Capture writer is stopped",
+            )),
+        }
+    }
+
+    fn send(&self, command: CaptureCommand) -> bool {
+        self.sender.lock().unwrap().send(command).is_ok()
+    }
+}
+
+// Loop ends when the writer is dropped. Everything which is queued is written and flushed before that
+fn write_loop(mut writer: Box<dyn Write + Send>, receiver: mpsc::Receiver<CaptureCommand>) {
+    let mut buffer = Vec::new();
+
+    while let Ok(command) = receiver.recv() {
+        match command {
+            CaptureCommand::Frame(frame) => {
+                buffer.clear();
+                frame.serialize(&mut buffer);
+
+                if let Err(err) = writer.write_all(buffer.as_slice()) {
+                    println!("Can not write frame to capture file. Err: {:?}", err);
+                }
+            }
+            CaptureCommand::Flush(result) => {
+                let _ = result.send(writer.flush());
+            }
+        }
+    }
+
+    if let Err(err) = writer.flush() {
+        println!("Can not flush capture file. Err: {:?}", err);
+    }
+}
@@ -0,0 +1,9 @@
+mod capture_frame;
+mod capture_reader;
+mod capture_writer;
+pub use capture_frame::*;
+pub use capture_reader::*;
+pub use capture_writer::*;
+
+pub const CAPTURE_FILE_MAGIC: &[u8; 4] = b"MNSC";
+pub const CAPTURE_FILE_VERSION: u8 = 0;
@@ -0,0 +1,100 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumAlgorithm {
+    Crc32c,
+    XxHash64,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            ChecksumAlgorithm::Crc32c => 0,
+            ChecksumAlgorithm::XxHash64 => 1,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ChecksumAlgorithm::Crc32c),
+            1 => Some(ChecksumAlgorithm::XxHash64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32c => "crc32c",
+            ChecksumAlgorithm::XxHash64 => "xxhash64",
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            ChecksumAlgorithm::Crc32c => true,
+            ChecksumAlgorithm::XxHash64 => cfg!(feature = "xxhash"),
+        }
+    }
+
+    // Ordered by preference
+    pub fn get_supported() -> Vec<Self> {
+        [ChecksumAlgorithm::XxHash64, ChecksumAlgorithm::Crc32c]
+            .into_iter()
+            .filter(|itm| itm.is_supported())
+            .collect()
+    }
+
+    // None means peer does not support checksums and packets are sent without them
+    pub fn negotiate(ours: &[Self], theirs: &[Self]) -> Option<Self> {
+        ours.iter()
+            .find(|itm| itm.is_supported() && theirs.contains(itm))
+            .copied()
+    }
+
+    // Returns None if algorithm is not compiled in
+    pub fn calculate(&self, payload: &[u8]) -> Option<u64> {
+        match self {
+            ChecksumAlgorithm::Crc32c => Some(crc32c(payload) as u64),
+            #[cfg(feature = "xxhash")]
+            ChecksumAlgorithm::XxHash64 => Some(xxhash_rust::xxh64::xxh64(payload, 0)),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+const CRC32C_POLY: u32 = 0x82F63B78;
+
+const CRC32C_TABLE: [u32; 256] = build_crc32c_table();
+
+const fn build_crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+fn crc32c(payload: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for b in payload {
+        crc = CRC32C_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
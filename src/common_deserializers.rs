@@ -1,6 +1,17 @@
 use my_tcp_sockets::socket_reader::{ReadingTcpContractFail, SocketReader};
 use rust_extensions::date_time::DateTimeAsMicroseconds;
 
+use crate::{DecodeError, DecodeLimits};
+
+// Amounts come off the wire and limits may be off, so lists and byte arrays grow as the items actually arrive.
+// Declared amount only helps up to these
+const MAX_INITIAL_LIST_CAPACITY: usize = 1024;
+const MAX_BYTES_READ_AT_ONCE: usize = 64 * 1024;
+
+pub(crate) fn list_with_capacity<T>(amount: usize) -> Vec<T> {
+    Vec::with_capacity(amount.min(MAX_INITIAL_LIST_CAPACITY))
+}
+
 pub async fn read_pascal_string(
     reader: &mut impl SocketReader,
 ) -> Result<String, ReadingTcpContractFail> {
@@ -17,9 +28,17 @@ pub async fn read_pascal_string(
 pub async fn read_list_of_pascal_strings(
     reader: &mut impl SocketReader,
 ) -> Result<Vec<String>, ReadingTcpContractFail> {
-    let amount = reader.read_i32().await? as usize;
+    let result = read_list_of_pascal_strings_with_limits(reader, &DecodeLimits::default()).await?;
+    Ok(result)
+}
+
+pub async fn read_list_of_pascal_strings_with_limits(
+    reader: &mut impl SocketReader,
+    limits: &DecodeLimits,
+) -> Result<Vec<String>, DecodeError> {
+    let amount = limits.check_list_len(reader.read_i32().await?)?;
 
-    let mut result = Vec::with_capacity(amount);
+    let mut result = list_with_capacity(amount);
 
     for _ in 0..amount {
         result.push(read_pascal_string(reader).await?);
@@ -28,6 +47,81 @@ pub async fn read_list_of_pascal_strings(
     Ok(result)
 }
 
+// Without moments only the keys are on the wire
+pub async fn read_list_of_keys_with_moments(
+    reader: &mut impl SocketReader,
+    limits: &DecodeLimits,
+    with_moments: bool,
+) -> Result<Vec<(String, Option<DateTimeAsMicroseconds>)>, DecodeError> {
+    let amount = limits.check_list_len(reader.read_i32().await?)?;
+
+    let mut result = list_with_capacity(amount);
+
+    for _ in 0..amount {
+        let key = read_pascal_string(reader).await?;
+
+        let moment = if with_moments {
+            read_date_time_opt(reader).await?
+        } else {
+            None
+        };
+
+        result.push((key, moment));
+    }
+
+    Ok(result)
+}
+
+pub async fn read_byte_array(
+    reader: &mut impl SocketReader,
+    limits: &DecodeLimits,
+) -> Result<Vec<u8>, DecodeError> {
+    let size = limits.check_byte_array_size(reader.read_i32().await?)?;
+    read_bytes(reader, size).await
+}
+
+pub async fn read_var_u64(reader: &mut impl SocketReader) -> Result<u64, DecodeError> {
+    let mut result = 0u64;
+
+    for i in 0..10 {
+        let b = reader.read_byte().await?;
+
+        if i == 9 && b > 1 {
+            return Err(DecodeError::VarIntOverflow);
+        }
+
+        result |= ((b & 0x7F) as u64) << (i * 7);
+
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+
+    Err(DecodeError::VarIntOverflow)
+}
+
+pub async fn read_large_byte_array(
+    reader: &mut impl SocketReader,
+    limits: &DecodeLimits,
+) -> Result<Vec<u8>, DecodeError> {
+    let size = limits.check_large_byte_array_size(read_var_u64(reader).await?)?;
+    read_bytes(reader, size).await
+}
+
+async fn read_bytes(reader: &mut impl SocketReader, size: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut result: Vec<u8> = Vec::with_capacity(size.min(MAX_BYTES_READ_AT_ONCE));
+
+    while result.len() < size {
+        let pos = result.len();
+        let chunk_size = (size - pos).min(MAX_BYTES_READ_AT_ONCE);
+
+        result.resize(pos + chunk_size, 0);
+        reader.read_buf(&mut result[pos..]).await?;
+    }
+
+    Ok(result)
+}
+
 pub async fn read_date_time_opt(
     reader: &mut impl SocketReader,
 ) -> Result<Option<DateTimeAsMicroseconds>, ReadingTcpContractFail> {
@@ -41,3 +135,108 @@ pub async fn read_date_time_opt(
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::socket_reader::SocketReaderInMem;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn var_u64_round_trip_on_boundaries() {
+        for value in [
+            0,
+            0x7F,
+            0x80,
+            0x3FFF,
+            0x4000,
+            i32::MAX as u64,
+            i32::MAX as u64 + 1,
+            u64::MAX,
+        ] {
+            let mut payload = Vec::new();
+            crate::common_serializers::serialize_var_u64(&mut payload, value);
+            assert_eq!(
+                payload.len(),
+                crate::common_serializers::get_var_u64_len(value)
+            );
+
+            let mut reader = SocketReaderInMem::new(payload);
+            assert_eq!(read_var_u64(&mut reader).await.unwrap(), value);
+        }
+    }
+
+    #[tokio::test]
+    async fn var_u64_over_64_bits_is_rejected() {
+        let mut payload = vec![0xFF; 9];
+        payload.push(0x02);
+
+        let mut reader = SocketReaderInMem::new(payload);
+        assert!(matches!(
+            read_var_u64(&mut reader).await,
+            Err(DecodeError::VarIntOverflow)
+        ));
+
+        let mut reader = SocketReaderInMem::new(vec![0x80; 11]);
+        assert!(matches!(
+            read_var_u64(&mut reader).await,
+            Err(DecodeError::VarIntOverflow)
+        ));
+    }
+
+    #[tokio::test]
+    async fn large_byte_array_over_limit_is_rejected() {
+        let mut payload = Vec::new();
+        crate::common_serializers::serialize_large_byte_array(&mut payload, &[1, 2, 3]);
+
+        let mut reader = SocketReaderInMem::new(payload.clone());
+        let result = read_large_byte_array(&mut reader, &DecodeLimits::default()).await;
+        assert_eq!(result.unwrap(), vec![1, 2, 3]);
+
+        let limits = DecodeLimits {
+            max_byte_array_size: 2,
+            ..DecodeLimits::default()
+        };
+
+        let mut reader = SocketReaderInMem::new(payload);
+        assert!(read_large_byte_array(&mut reader, &limits).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn declared_amounts_are_not_allocated_up_front() {
+        let limits = DecodeLimits::unlimited();
+
+        let mut payload = Vec::new();
+        crate::common_serializers::serialize_i32(&mut payload, i32::MAX);
+
+        let mut reader = SocketReaderInMem::new(payload.clone());
+        assert!(
+            read_list_of_pascal_strings_with_limits(&mut reader, &limits)
+                .await
+                .is_err()
+        );
+
+        let mut reader = SocketReaderInMem::new(payload);
+        assert!(read_byte_array(&mut reader, &limits).await.is_err());
+
+        let mut payload = Vec::new();
+        crate::common_serializers::serialize_var_u64(&mut payload, u64::MAX >> 1);
+
+        let mut reader = SocketReaderInMem::new(payload);
+        assert!(read_large_byte_array(&mut reader, &limits).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn byte_array_bigger_than_one_read_is_read_whole() {
+        let data: Vec<u8> = (0..MAX_BYTES_READ_AT_ONCE * 2 + 3)
+            .map(|itm| itm as u8)
+            .collect();
+
+        let mut payload = Vec::new();
+        crate::common_serializers::serialize_large_byte_array(&mut payload, &data);
+
+        let mut reader = SocketReaderInMem::new(payload);
+        let result = read_large_byte_array(&mut reader, &DecodeLimits::unlimited()).await;
+        assert_eq!(result.unwrap(), data);
+    }
+}
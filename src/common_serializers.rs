@@ -1,12 +1,88 @@
-use std::str;
+use std::{io::Write, str};
 
 use rust_extensions::date_time::DateTimeAsMicroseconds;
 
-pub fn serialize_byte(data: &mut Vec<u8>, v: u8) {
+pub trait SerializerBuffer {
+    fn push(&mut self, v: u8);
+    fn extend_from_slice(&mut self, v: &[u8]);
+    fn reserve(&mut self, additional: usize);
+}
+
+impl SerializerBuffer for Vec<u8> {
+    fn push(&mut self, v: u8) {
+        Vec::push(self, v);
+    }
+
+    fn extend_from_slice(&mut self, v: &[u8]) {
+        Vec::extend_from_slice(self, v);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl SerializerBuffer for bytes::BytesMut {
+    fn push(&mut self, v: u8) {
+        bytes::BufMut::put_u8(self, v);
+    }
+
+    fn extend_from_slice(&mut self, v: &[u8]) {
+        bytes::BytesMut::extend_from_slice(self, v);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        bytes::BytesMut::reserve(self, additional);
+    }
+}
+
+// Adapter to serialize straight into io::Write sink. First error is kept and the rest of the
+// writes are skipped, since serializers can not fail in the middle of a packet.
+pub struct WriteSerializerBuffer<'s, TWrite: Write> {
+    writer: &'s mut TWrite,
+    error: Option<std::io::Error>,
+}
+
+impl<'s, TWrite: Write> WriteSerializerBuffer<'s, TWrite> {
+    pub fn new(writer: &'s mut TWrite) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'s, TWrite: Write> SerializerBuffer for WriteSerializerBuffer<'s, TWrite> {
+    fn push(&mut self, v: u8) {
+        self.extend_from_slice(&[v]);
+    }
+
+    fn extend_from_slice(&mut self, v: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Err(err) = self.writer.write_all(v) {
+            self.error = Some(err);
+        }
+    }
+
+    fn reserve(&mut self, _additional: usize) {}
+}
+
+pub fn serialize_byte(data: &mut impl SerializerBuffer, v: u8) {
     data.push(v);
 }
 
-pub fn serialize_bool(data: &mut Vec<u8>, v: bool) {
+pub fn serialize_bool(data: &mut impl SerializerBuffer, v: bool) {
     if v {
         data.push(1);
     } else {
@@ -14,15 +90,18 @@ pub fn serialize_bool(data: &mut Vec<u8>, v: bool) {
     }
 }
 
-pub fn serialize_i32(data: &mut Vec<u8>, v: i32) {
-    data.extend(&v.to_le_bytes());
+pub fn serialize_i32(data: &mut impl SerializerBuffer, v: i32) {
+    data.extend_from_slice(&v.to_le_bytes());
 }
 
-pub fn serialize_i64(data: &mut Vec<u8>, v: i64) {
-    data.extend(&v.to_le_bytes());
+pub fn serialize_i64(data: &mut impl SerializerBuffer, v: i64) {
+    data.extend_from_slice(&v.to_le_bytes());
 }
 
-pub fn serialize_date_time_opt(data: &mut Vec<u8>, v: Option<DateTimeAsMicroseconds>) {
+pub fn serialize_date_time_opt(
+    data: &mut impl SerializerBuffer,
+    v: Option<DateTimeAsMicroseconds>,
+) {
     if let Some(v) = v {
         serialize_i64(data, v.unix_microseconds);
     } else {
@@ -30,13 +109,13 @@ pub fn serialize_date_time_opt(data: &mut Vec<u8>, v: Option<DateTimeAsMicroseco
     }
 }
 
-pub fn serialize_pascal_string(data: &mut Vec<u8>, str: &str) {
+pub fn serialize_pascal_string(data: &mut impl SerializerBuffer, str: &str) {
     let str_len = str.len() as u8;
     data.push(str_len);
-    data.extend(str.as_bytes());
+    data.extend_from_slice(str.as_bytes());
 }
 
-pub fn serialize_list_of_arrays(data: &mut Vec<u8>, v: &Vec<Vec<u8>>) {
+pub fn serialize_list_of_arrays(data: &mut impl SerializerBuffer, v: &Vec<Vec<u8>>) {
     let array_len = v.len() as i32;
     serialize_i32(data, array_len);
 
@@ -45,7 +124,23 @@ pub fn serialize_list_of_arrays(data: &mut Vec<u8>, v: &Vec<Vec<u8>>) {
     }
 }
 
-pub fn serialize_list_of_pascal_strings(data: &mut Vec<u8>, v: &Vec<String>) {
+pub fn serialize_list_of_keys_with_moments(
+    data: &mut impl SerializerBuffer,
+    v: &[(String, Option<DateTimeAsMicroseconds>)],
+    with_moments: bool,
+) {
+    serialize_i32(data, v.len() as i32);
+
+    for (key, moment) in v {
+        serialize_pascal_string(data, key);
+
+        if with_moments {
+            serialize_date_time_opt(data, *moment);
+        }
+    }
+}
+
+pub fn serialize_list_of_pascal_strings(data: &mut impl SerializerBuffer, v: &Vec<String>) {
     let array_len = v.len() as i32;
     serialize_i32(data, array_len);
 
@@ -54,8 +149,39 @@ pub fn serialize_list_of_pascal_strings(data: &mut Vec<u8>, v: &Vec<String>) {
     }
 }
 
-pub fn serialize_byte_array(data: &mut Vec<u8>, v: &[u8]) {
+pub fn serialize_byte_array(data: &mut impl SerializerBuffer, v: &[u8]) {
     let array_len = v.len() as i32;
     serialize_i32(data, array_len);
-    data.extend(v);
+    data.extend_from_slice(v);
+}
+
+pub fn serialize_var_u64(data: &mut impl SerializerBuffer, mut v: u64) {
+    loop {
+        let b = (v & 0x7F) as u8;
+        v >>= 7;
+
+        if v == 0 {
+            data.push(b);
+            return;
+        }
+
+        data.push(b | 0x80);
+    }
+}
+
+pub fn get_var_u64_len(mut v: u64) -> usize {
+    let mut result = 1;
+
+    while v >= 0x80 {
+        v >>= 7;
+        result += 1;
+    }
+
+    result
+}
+
+// Length is written as varint, so arrays over 2 GiB are supported
+pub fn serialize_large_byte_array(data: &mut impl SerializerBuffer, v: &[u8]) {
+    serialize_var_u64(data, v.len() as u64);
+    data.extend_from_slice(v);
 }
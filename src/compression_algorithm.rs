@@ -0,0 +1,70 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CompressionAlgorithm {
+    #[default]
+    Zip,
+    Deflate,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionAlgorithm {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            CompressionAlgorithm::Zip => 0,
+            CompressionAlgorithm::Deflate => 1,
+            CompressionAlgorithm::Zstd => 2,
+            CompressionAlgorithm::Lz4 => 3,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CompressionAlgorithm::Zip),
+            1 => Some(CompressionAlgorithm::Deflate),
+            2 => Some(CompressionAlgorithm::Zstd),
+            3 => Some(CompressionAlgorithm::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zip => "zip",
+            CompressionAlgorithm::Deflate => "deflate",
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            CompressionAlgorithm::Zip => true,
+            CompressionAlgorithm::Deflate => cfg!(feature = "deflate"),
+            CompressionAlgorithm::Zstd => cfg!(feature = "zstd"),
+            CompressionAlgorithm::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    // Ordered by preference. Zip is always the last one since it is kept for legacy peers
+    pub fn get_supported() -> Vec<Self> {
+        [
+            CompressionAlgorithm::Zstd,
+            CompressionAlgorithm::Lz4,
+            CompressionAlgorithm::Deflate,
+            CompressionAlgorithm::Zip,
+        ]
+        .into_iter()
+        .filter(|itm| itm.is_supported())
+        .collect()
+    }
+
+    pub fn negotiate(ours: &[Self], theirs: &[Self]) -> Self {
+        for algorithm in ours {
+            if theirs.contains(algorithm) {
+                return *algorithm;
+            }
+        }
+
+        CompressionAlgorithm::Zip
+    }
+}
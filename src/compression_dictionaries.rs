@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::{DecodeError, DecodeLimits, MyNoSqlTcpContract};
+use tokio_util::sync::CancellationToken;
+
+struct PreparedDictionary {
+    table_name: String,
+    data: Vec<u8>,
+    #[cfg(feature = "zstd")]
+    encoder: zstd::dict::EncoderDictionary<'static>,
+    #[cfg(feature = "zstd")]
+    decoder: zstd::dict::DecoderDictionary<'static>,
+}
+
+impl PreparedDictionary {
+    fn new(table_name: String, data: Vec<u8>) -> Self {
+        Self {
+            #[cfg(feature = "zstd")]
+            encoder: zstd::dict::EncoderDictionary::copy(
+                data.as_slice(),
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            ),
+            #[cfg(feature = "zstd")]
+            decoder: zstd::dict::DecoderDictionary::copy(data.as_slice()),
+            table_name,
+            data,
+        }
+    }
+}
+
+struct CompressionDictionariesInner {
+    by_id: HashMap<u32, Arc<PreparedDictionary>>,
+    by_table: HashMap<String, u32>,
+    next_id: u32,
+}
+
+pub struct CompressionDictionaries {
+    inner: RwLock<CompressionDictionariesInner>,
+}
+
+impl CompressionDictionaries {
+    pub fn new() -> Self {
+        Self {
+            inner: RwLock::new(CompressionDictionariesInner {
+                by_id: HashMap::new(),
+                by_table: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn train<TSample: AsRef<[u8]>>(
+        &self,
+        table_name: &str,
+        samples: &[TSample],
+        max_dictionary_size: usize,
+    ) -> std::io::Result<u32> {
+        #[cfg(feature = "zstd")]
+        {
+            let data = zstd::dict::from_samples(samples, max_dictionary_size)?;
+
+            let mut inner = self.inner.write().unwrap();
+            inner.next_id += 1;
+            let dictionary_id = inner.next_id;
+
+            inner.by_id.insert(
+                dictionary_id,
+                Arc::new(PreparedDictionary::new(table_name.to_string(), data)),
+            );
+            inner.by_table.insert(table_name.to_string(), dictionary_id);
+
+            Ok(dictionary_id)
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = (table_name, samples, max_dictionary_size);
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Dictionaries require zstd feature",
+            ))
+        }
+    }
+
+    pub fn register(&self, dictionary_id: u32, table_name: &str, data: Vec<u8>) {
+        let mut inner = self.inner.write().unwrap();
+
+        if inner.next_id < dictionary_id {
+            inner.next_id = dictionary_id;
+        }
+
+        inner.by_id.insert(
+            dictionary_id,
+            Arc::new(PreparedDictionary::new(table_name.to_string(), data)),
+        );
+        inner.by_table.insert(table_name.to_string(), dictionary_id);
+    }
+
+    // For dictionaries received from the peer. Dictionary with the same id replaces the previous one
+    pub fn register_with_limits(
+        &self,
+        dictionary_id: u32,
+        table_name: &str,
+        data: Vec<u8>,
+        limits: &DecodeLimits,
+    ) -> Result<(), DecodeError> {
+        let mut inner = self.inner.write().unwrap();
+
+        let mut amount = 1;
+        let mut size = data.len();
+
+        for (id, dictionary) in inner.by_id.iter() {
+            if *id != dictionary_id {
+                amount += 1;
+                size += dictionary.data.len();
+            }
+        }
+
+        if amount > limits.max_dictionaries {
+            return Err(DecodeError::TooManyCompressionDictionaries {
+                max_amount: limits.max_dictionaries,
+            });
+        }
+
+        if size > limits.max_dictionaries_size {
+            return Err(DecodeError::CompressionDictionariesTooLarge {
+                size,
+                max_size: limits.max_dictionaries_size,
+            });
+        }
+
+        inner.by_id.insert(
+            dictionary_id,
+            Arc::new(PreparedDictionary::new(table_name.to_string(), data)),
+        );
+        inner.by_table.insert(table_name.to_string(), dictionary_id);
+
+        Ok(())
+    }
+
+    pub fn get_dictionary_id(&self, table_name: &str) -> Option<u32> {
+        self.inner.read().unwrap().by_table.get(table_name).copied()
+    }
+
+    pub fn get_dictionary_contract(&self, table_name: &str) -> Option<MyNoSqlTcpContract> {
+        let inner = self.inner.read().unwrap();
+        let dictionary_id = *inner.by_table.get(table_name)?;
+        let dictionary = inner.by_id.get(&dictionary_id)?;
+
+        Some(MyNoSqlTcpContract::CompressionDictionary {
+            dictionary_id,
+            table_name: dictionary.table_name.clone(),
+            dictionary: dictionary.data.clone(),
+        })
+    }
+
+    fn get_dictionary(&self, dictionary_id: u32) -> Option<Arc<PreparedDictionary>> {
+        self.inner
+            .read()
+            .unwrap()
+            .by_id
+            .get(&dictionary_id)
+            .cloned()
+    }
+
+    pub fn compress(&self, dictionary_id: u32, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        self.compress_into(dictionary_id, payload, &mut result)?;
+        Ok(result)
+    }
+
+    pub fn compress_into(
+        &self,
+        dictionary_id: u32,
+        payload: &[u8],
+        out: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        self.compress_into_cancellable(dictionary_id, payload, out, None)
+    }
+
+    pub(crate) fn compress_into_cancellable(
+        &self,
+        dictionary_id: u32,
+        payload: &[u8],
+        out: &mut Vec<u8>,
+        cancellation: Option<&CancellationToken>,
+    ) -> std::io::Result<()> {
+        let dictionary = self.get_dictionary(dictionary_id).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Compression dictionary {} is not found", dictionary_id),
+            )
+        })?;
+
+        #[cfg(feature = "zstd")]
+        {
+            out.clear();
+            let mut encoder =
+                zstd::stream::write::Encoder::with_prepared_dictionary(out, &dictionary.encoder)?;
+            crate::compression_offload::write_all_cancellable(&mut encoder, payload, cancellation)?;
+            encoder.finish()?;
+            Ok(())
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = (dictionary, payload, out, cancellation);
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Dictionaries require zstd feature",
+            ))
+        }
+    }
+
+    pub fn decompress(
+        &self,
+        dictionary_id: u32,
+        payload: &[u8],
+        max_size: usize,
+    ) -> Result<Vec<u8>, DecodeError> {
+        self.decompress_cancellable(dictionary_id, payload, max_size, None)
+    }
+
+    pub(crate) fn decompress_cancellable(
+        &self,
+        dictionary_id: u32,
+        payload: &[u8],
+        max_size: usize,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, DecodeError> {
+        let dictionary = self
+            .get_dictionary(dictionary_id)
+            .ok_or(DecodeError::UnknownCompressionDictionary(dictionary_id))?;
+
+        #[cfg(feature = "zstd")]
+        {
+            let decoder =
+                zstd::stream::read::Decoder::with_prepared_dictionary(payload, &dictionary.decoder)
+                    .map_err(DecodeError::DecompressionIo)?;
+
+            crate::payload_comressor::read_to_end_with_limit(
+                decoder,
+                payload.len(),
+                max_size,
+                cancellation,
+            )
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        {
+            let _ = (dictionary, payload, max_size, cancellation);
+            Err(DecodeError::UnsupportedCompressionAlgorithm(
+                crate::CompressionAlgorithm::Zstd.as_u8(),
+            ))
+        }
+    }
+}
+
+impl Default for CompressionDictionaries {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_dictionaries: usize, max_dictionaries_size: usize) -> DecodeLimits {
+        DecodeLimits {
+            max_dictionaries,
+            max_dictionaries_size,
+            ..DecodeLimits::default()
+        }
+    }
+
+    #[test]
+    fn amount_of_received_dictionaries_is_limited() {
+        let dictionaries = CompressionDictionaries::new();
+        let limits = limits(2, 1024);
+
+        dictionaries
+            .register_with_limits(1, "table-1", vec![0; 8], &limits)
+            .unwrap();
+        dictionaries
+            .register_with_limits(2, "table-2", vec![0; 8], &limits)
+            .unwrap();
+
+        // Replacing the dictionary does not add one more
+        dictionaries
+            .register_with_limits(2, "table-2", vec![1; 8], &limits)
+            .unwrap();
+
+        assert!(matches!(
+            dictionaries.register_with_limits(3, "table-3", vec![0; 8], &limits),
+            Err(DecodeError::TooManyCompressionDictionaries { max_amount: 2 })
+        ));
+        assert_eq!(dictionaries.get_dictionary_id("table-3"), None);
+    }
+
+    #[test]
+    fn size_of_received_dictionaries_is_limited() {
+        let dictionaries = CompressionDictionaries::new();
+        let limits = limits(16, 100);
+
+        dictionaries
+            .register_with_limits(1, "table-1", vec![0; 60], &limits)
+            .unwrap();
+
+        assert!(matches!(
+            dictionaries.register_with_limits(2, "table-2", vec![0; 60], &limits),
+            Err(DecodeError::CompressionDictionariesTooLarge {
+                size: 120,
+                max_size: 100
+            })
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+// Stream compressors are fed and drained by chunks of this size, so cancelled work stops within one chunk
+const CANCELLATION_CHECK_CHUNK_SIZE: usize = 256 * 1024;
+
+// What compression of one contract may use besides the payload itself
+#[derive(Default, Clone, Copy)]
+pub(crate) struct CompressionResources<'s> {
+    pub pool: Option<&'s crate::BufferPool>,
+    pub cancellation: Option<&'s CancellationToken>,
+}
+
+// Payloads above the thresholds are compressed/decompressed on the blocking thread pool,
+// so a big InitTable does not stall the runtime worker which also serves pings of other connections.
+// Cancelled task stops at the next chunk and gives its permit of max_parallel_tasks back.
+// Lz4 block is processed at once, it is fast enough not to need the checks
+#[derive(Debug, Clone)]
+pub struct CompressionOffload {
+    pub min_compress_size: usize,
+    pub min_decompress_size: usize,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl CompressionOffload {
+    pub fn new() -> Self {
+        Self {
+            min_compress_size: 1024 * 1024,
+            min_decompress_size: 256 * 1024,
+            permits: None,
+        }
+    }
+
+    pub fn with_max_parallel_tasks(mut self, max_parallel_tasks: usize) -> Self {
+        self.permits = Some(Arc::new(Semaphore::new(max_parallel_tasks)));
+        self
+    }
+
+    // Returns None if cancellation is requested before the task is done. The caller is released
+    // immediately. Task which has not started yet is skipped, the running one is expected to check cancellation
+    pub(crate) async fn run<TResult: Send + 'static>(
+        &self,
+        cancellation: &CancellationToken,
+        task: impl FnOnce() -> TResult + Send + 'static,
+    ) -> Option<TResult> {
+        let permit = match &self.permits {
+            Some(permits) => tokio::select! {
+                permit = permits.clone().acquire_owned() => permit.ok(),
+                _ = cancellation.cancelled() => return None,
+            },
+            None => None,
+        };
+
+        let task_cancellation = cancellation.clone();
+
+        let handle = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+
+            // Task can wait in the blocking pool queue long enough for connection to be dropped
+            if task_cancellation.is_cancelled() {
+                return None;
+            }
+
+            Some(task())
+        });
+
+        tokio::select! {
+            result = handle => match result {
+                Ok(result) => result,
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(_) => None,
+            },
+            _ = cancellation.cancelled() => None,
+        }
+    }
+}
+
+impl Default for CompressionOffload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn check_cancelled(cancellation: Option<&CancellationToken>) -> std::io::Result<()> {
+    match cancellation {
+        Some(cancellation) if cancellation.is_cancelled() => {
+            Err(std::io::Error::other("Compression task is cancelled"))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn is_cancelled(cancellation: Option<&CancellationToken>) -> bool {
+    check_cancelled(cancellation).is_err()
+}
+
+pub(crate) fn write_all_cancellable(
+    writer: &mut impl Write,
+    payload: &[u8],
+    cancellation: Option<&CancellationToken>,
+) -> std::io::Result<()> {
+    for chunk in payload.chunks(CANCELLATION_CHECK_CHUNK_SIZE) {
+        check_cancelled(cancellation)?;
+        writer.write_all(chunk)?;
+    }
+
+    Ok(())
+}
+
+// Decompressor is drained through it, so read_to_end stops as soon as the task is cancelled
+pub(crate) struct CancellableReader<'s, TReader: Read> {
+    reader: TReader,
+    cancellation: Option<&'s CancellationToken>,
+}
+
+impl<'s, TReader: Read> CancellableReader<'s, TReader> {
+    pub fn new(reader: TReader, cancellation: Option<&'s CancellationToken>) -> Self {
+        Self {
+            reader,
+            cancellation,
+        }
+    }
+}
+
+impl<'s, TReader: Read> Read for CancellableReader<'s, TReader> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        check_cancelled(self.cancellation)?;
+
+        let len = buf.len().min(CANCELLATION_CHECK_CHUNK_SIZE);
+        self.reader.read(&mut buf[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cancels the token as soon as the compressor hands it the first chunk
+    struct CancellingWriter {
+        cancellation: CancellationToken,
+        written: usize,
+    }
+
+    impl Write for CancellingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.cancellation.cancel();
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn compression_stops_at_the_next_chunk_after_cancellation() {
+        let cancellation = CancellationToken::new();
+        let mut writer = CancellingWriter {
+            cancellation: cancellation.clone(),
+            written: 0,
+        };
+
+        let payload = vec![0u8; CANCELLATION_CHECK_CHUNK_SIZE * 4];
+
+        assert!(write_all_cancellable(&mut writer, &payload, Some(&cancellation)).is_err());
+        assert_eq!(writer.written, CANCELLATION_CHECK_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn decompression_of_cancelled_task_is_cancelled() {
+        let payload = vec![0u8; 4 * 1024 * 1024];
+        let compressed = crate::payload_comressor::compress(payload.as_slice()).unwrap();
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+
+        let result = crate::payload_comressor::decompress_with_algorithm_cancellable(
+            compressed.as_slice(),
+            crate::CompressionAlgorithm::Zip,
+            usize::MAX,
+            Some(&cancellation),
+        );
+
+        assert!(matches!(result, Err(crate::DecodeError::Cancelled)));
+
+        let result = crate::payload_comressor::decompress_with_algorithm_cancellable(
+            compressed.as_slice(),
+            crate::CompressionAlgorithm::Zip,
+            usize::MAX,
+            Some(&CancellationToken::new()),
+        );
+
+        assert_eq!(result.unwrap(), payload);
+    }
+
+    #[test]
+    fn cancelled_compression_is_not_sent_uncompressed() {
+        let contract = crate::MyNoSqlTcpContract::InitTable {
+            table_name: "table".to_string(),
+            data: vec![0u8; 4 * 1024 * 1024],
+        };
+
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+
+        let mut serialized = Vec::new();
+        contract.serialize_into(&mut serialized);
+
+        let result = contract.compress_serialized(
+            serialized.as_slice(),
+            crate::CompressionAlgorithm::Zip,
+            &crate::CompressionPolicy::default(),
+            None,
+            None,
+            CompressionResources {
+                pool: None,
+                cancellation: Some(&cancellation),
+            },
+        );
+
+        assert!(result.is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use crate::MyNoSqlTcpContract;
+
+#[derive(Debug, Clone)]
+pub struct AdaptiveCompressionSettings {
+    // compressed_size / uncompressed_size above which we treat compression for the table as useless
+    pub max_ratio: f64,
+    pub min_samples: usize,
+    // Once compression is switched off for a table, every Nth packet is still compressed to
+    // find out if the data became compressible again
+    pub probe_every: usize,
+}
+
+impl Default for AdaptiveCompressionSettings {
+    fn default() -> Self {
+        Self {
+            max_ratio: 0.9,
+            min_samples: 5,
+            probe_every: 100,
+        }
+    }
+}
+
+struct TableCompressionStats {
+    samples: usize,
+    avg_ratio: f64,
+    skipped: usize,
+}
+
+pub struct CompressionPolicy {
+    pub min_payload_size: usize,
+    pub min_saved_bytes: usize,
+    pub level: Option<i32>,
+    pub adaptive: Option<AdaptiveCompressionSettings>,
+    disabled_packet_types: HashSet<u8>,
+    tables: Mutex<HashMap<String, TableCompressionStats>>,
+}
+
+impl CompressionPolicy {
+    pub fn new() -> Self {
+        Self {
+            min_payload_size: 128,
+            min_saved_bytes: 10,
+            level: None,
+            adaptive: None,
+            disabled_packet_types: HashSet::new(),
+            tables: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Policy of the API which existed before the policy was introduced: every packet is compressed
+    // and compressed payload is sent if it saves more than 10 bytes
+    pub fn legacy() -> Self {
+        Self {
+            min_payload_size: 0,
+            ..Self::new()
+        }
+    }
+
+    pub fn disable_packet_type(&mut self, packet_type: u8) {
+        self.disabled_packet_types.insert(packet_type);
+    }
+
+    pub fn enable_packet_type(&mut self, packet_type: u8) {
+        self.disabled_packet_types.remove(&packet_type);
+    }
+
+    pub fn should_compress(&self, contract: &MyNoSqlTcpContract) -> bool {
+        if contract.serialized_len() < self.min_payload_size {
+            return false;
+        }
+
+        if self
+            .disabled_packet_types
+            .contains(&contract.get_packet_type())
+        {
+            return false;
+        }
+
+        let adaptive = match &self.adaptive {
+            Some(adaptive) => adaptive,
+            None => return true,
+        };
+
+        let table_name = match contract.get_table_name() {
+            Some(table_name) => table_name,
+            None => return true,
+        };
+
+        let mut tables = self.tables.lock().unwrap();
+
+        let stats = match tables.get_mut(table_name) {
+            Some(stats) => stats,
+            None => return true,
+        };
+
+        if stats.samples < adaptive.min_samples || stats.avg_ratio <= adaptive.max_ratio {
+            return true;
+        }
+
+        stats.skipped += 1;
+
+        if stats.skipped >= adaptive.probe_every {
+            stats.skipped = 0;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn compression_makes_sence(
+        &self,
+        uncompressed_size: usize,
+        compressed_size: usize,
+    ) -> bool {
+        compressed_size + self.min_saved_bytes < uncompressed_size
+    }
+
+    pub fn report_result(
+        &self,
+        contract: &MyNoSqlTcpContract,
+        uncompressed_size: usize,
+        compressed_size: usize,
+    ) {
+        if self.adaptive.is_none() || uncompressed_size == 0 {
+            return;
+        }
+
+        let table_name = match contract.get_table_name() {
+            Some(table_name) => table_name,
+            None => return,
+        };
+
+        let ratio = compressed_size as f64 / uncompressed_size as f64;
+
+        let mut tables = self.tables.lock().unwrap();
+
+        match tables.get_mut(table_name) {
+            Some(stats) => {
+                stats.samples += 1;
+                stats.avg_ratio = stats.avg_ratio * 0.8 + ratio * 0.2;
+            }
+            None => {
+                tables.insert(
+                    table_name.to_string(),
+                    TableCompressionStats {
+                        samples: 1,
+                        avg_ratio: ratio,
+                        skipped: 0,
+                    },
+                );
+            }
+        }
+    }
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::AtomicProtocolMetrics;
+
+    #[test]
+    fn legacy_api_compresses_small_payloads() {
+        let contract = MyNoSqlTcpContract::InitTable {
+            table_name: "table".to_string(),
+            data: vec![0; 64],
+        };
+        assert!(contract.serialized_len() < CompressionPolicy::default().min_payload_size);
+
+        let metrics = AtomicProtocolMetrics::new();
+        contract.compress_if_make_sence_and_serialize_with_metrics(&metrics);
+        assert_eq!(metrics.get_snapshot().compression_attempts, 1);
+
+        let metrics = AtomicProtocolMetrics::new();
+        contract.compress_with_algorithm_if_make_sence_and_serialize(
+            crate::CompressionAlgorithm::Zip,
+            Some(&metrics),
+        );
+        assert_eq!(metrics.get_snapshot().compression_attempts, 0);
+    }
+}
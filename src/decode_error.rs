@@ -0,0 +1,157 @@
+use my_tcp_sockets::socket_reader::ReadingTcpContractFail;
+
+use crate::ChecksumAlgorithm;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Socket(ReadingTcpContractFail),
+    NegativeLength(i32),
+    ByteArrayTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    ListTooLong {
+        len: usize,
+        max_len: usize,
+    },
+    TooManyRowsToDelete {
+        amount: usize,
+        max_amount: usize,
+    },
+    DecompressedPayloadTooLarge {
+        max_size: usize,
+    },
+    Decompression(zip::result::ZipError),
+    DecompressionIo(std::io::Error),
+    UnsupportedCompressionAlgorithm(u8),
+    UnknownCompressionDictionary(u32),
+    TooManyCompressionDictionaries {
+        max_amount: usize,
+    },
+    CompressionDictionariesTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    Cancelled,
+    UnsupportedChecksumAlgorithm(u8),
+    VarIntOverflow,
+    ChecksumMismatch {
+        algorithm: ChecksumAlgorithm,
+        expected: u64,
+        actual: u64,
+    },
+    UnsupportedEncryption,
+    UnknownEncryptionKey(u32),
+    DecryptionFailed {
+        key_id: u32,
+    },
+    // Keys are configured, but the data packet came in plain text
+    UnencryptedPayload {
+        packet_name: &'static str,
+    },
+    UnsupportedPacketVersion {
+        packet_no: u8,
+        version: u8,
+    },
+    Io(std::io::Error),
+    InvalidCapabilities,
+    // Peer reported a protocol error with the Error packet
+    PeerError(String),
+}
+
+impl DecodeError {
+    pub fn get_kind(&self) -> &'static str {
+        match self {
+            Self::Socket(_) => "Socket",
+            Self::NegativeLength(_) => "NegativeLength",
+            Self::ByteArrayTooLarge { .. } => "ByteArrayTooLarge",
+            Self::ListTooLong { .. } => "ListTooLong",
+            Self::TooManyRowsToDelete { .. } => "TooManyRowsToDelete",
+            Self::DecompressedPayloadTooLarge { .. } => "DecompressedPayloadTooLarge",
+            Self::Decompression(_) => "Decompression",
+            Self::DecompressionIo(_) => "DecompressionIo",
+            Self::UnsupportedCompressionAlgorithm(_) => "UnsupportedCompressionAlgorithm",
+            Self::UnknownCompressionDictionary(_) => "UnknownCompressionDictionary",
+            Self::TooManyCompressionDictionaries { .. } => "TooManyCompressionDictionaries",
+            Self::CompressionDictionariesTooLarge { .. } => "CompressionDictionariesTooLarge",
+            Self::Cancelled => "Cancelled",
+            Self::UnsupportedChecksumAlgorithm(_) => "UnsupportedChecksumAlgorithm",
+            Self::VarIntOverflow => "VarIntOverflow",
+            Self::ChecksumMismatch { .. } => "ChecksumMismatch",
+            Self::UnsupportedEncryption => "UnsupportedEncryption",
+            Self::UnknownEncryptionKey(_) => "UnknownEncryptionKey",
+            Self::DecryptionFailed { .. } => "DecryptionFailed",
+            Self::UnencryptedPayload { .. } => "UnencryptedPayload",
+            Self::UnsupportedPacketVersion { .. } => "UnsupportedPacketVersion",
+            Self::Io(_) => "Io",
+            Self::InvalidCapabilities => "InvalidCapabilities",
+            Self::PeerError(_) => "PeerError",
+        }
+    }
+
+    pub fn is_limit_violation(&self) -> bool {
+        match self {
+            Self::Socket(_) => false,
+            Self::Decompression(_) => false,
+            Self::DecompressionIo(_) => false,
+            Self::UnsupportedCompressionAlgorithm(_) => false,
+            Self::UnknownCompressionDictionary(_) => false,
+            Self::Cancelled => false,
+            Self::UnsupportedChecksumAlgorithm(_) => false,
+            Self::VarIntOverflow => false,
+            Self::ChecksumMismatch { .. } => false,
+            Self::UnsupportedEncryption => false,
+            Self::UnknownEncryptionKey(_) => false,
+            Self::DecryptionFailed { .. } => false,
+            Self::UnencryptedPayload { .. } => false,
+            Self::UnsupportedPacketVersion { .. } => false,
+            Self::PeerError(_) => false,
+            Self::Io(_) => false,
+            Self::InvalidCapabilities => false,
+            _ => true,
+        }
+    }
+
+    // Payload is corrupted. Connection is dropped, so after reconnect the tables are
+    // resubscribed and the data is reloaded from scratch
+    pub fn is_integrity_violation(&self) -> bool {
+        matches!(
+            self,
+            Self::ChecksumMismatch { .. } | Self::DecryptionFailed { .. }
+        )
+    }
+
+    // Data which is already applied can not be trusted, so subscribed tables have to be reloaded
+    pub fn requires_resubscribe(&self) -> bool {
+        self.is_integrity_violation()
+    }
+}
+
+// Socket layer gets only ReadingTcpContractFail, so the typed error is reported here before
+// the connection is dropped. Reader which needs more than a reconnect resubscribes from here
+pub trait DecodeErrorHandler: Send + Sync + 'static {
+    fn on_decode_error(&self, err: &DecodeError);
+}
+
+impl From<ReadingTcpContractFail> for DecodeError {
+    fn from(src: ReadingTcpContractFail) -> Self {
+        Self::Socket(src)
+    }
+}
+
+impl From<zip::result::ZipError> for DecodeError {
+    fn from(src: zip::result::ZipError) -> Self {
+        Self::Decompression(src)
+    }
+}
+
+// Socket layer knows only ReadingTcpContractFail. Any violation means we can not trust
+// the stream anymore so the connection has to be dropped.
+impl From<DecodeError> for ReadingTcpContractFail {
+    fn from(src: DecodeError) -> Self {
+        match src {
+            DecodeError::Socket(err) => err,
+            _ => ReadingTcpContractFail::SocketDisconnected,
+        }
+    }
+}
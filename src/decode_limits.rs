@@ -0,0 +1,211 @@
+#[derive(Debug, Clone)]
+pub struct DecodeLimits {
+    pub max_byte_array_size: usize,
+    pub max_list_len: usize,
+    pub max_decompressed_size: usize,
+    pub max_rows_to_delete: usize,
+    // Compression dictionaries received from the peer are kept for the whole connection
+    pub max_dictionaries: usize,
+    pub max_dictionaries_size: usize,
+}
+
+impl DecodeLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_byte_array_size: usize::MAX,
+            max_list_len: usize::MAX,
+            max_decompressed_size: usize::MAX,
+            max_rows_to_delete: usize::MAX,
+            max_dictionaries: usize::MAX,
+            max_dictionaries_size: usize::MAX,
+        }
+    }
+
+    // Opt-in limits for readers which are connected to not fully trusted peers
+    pub fn strict() -> Self {
+        Self {
+            max_byte_array_size: 512 * 1024 * 1024,
+            max_list_len: 1_000_000,
+            max_decompressed_size: 1024 * 1024 * 1024,
+            max_rows_to_delete: 1_000_000,
+            max_dictionaries: 256,
+            max_dictionaries_size: 64 * 1024 * 1024,
+        }
+    }
+
+    pub fn check_byte_array_size(&self, size: i32) -> Result<usize, crate::DecodeError> {
+        let size = check_len(size)?;
+
+        if size > self.max_byte_array_size {
+            return Err(crate::DecodeError::ByteArrayTooLarge {
+                size,
+                max_size: self.max_byte_array_size,
+            });
+        }
+
+        Ok(size)
+    }
+
+    pub fn check_large_byte_array_size(&self, size: u64) -> Result<usize, crate::DecodeError> {
+        let size = usize::try_from(size).unwrap_or(usize::MAX);
+
+        if size > self.max_byte_array_size {
+            return Err(crate::DecodeError::ByteArrayTooLarge {
+                size,
+                max_size: self.max_byte_array_size,
+            });
+        }
+
+        Ok(size)
+    }
+
+    pub fn check_list_len(&self, len: i32) -> Result<usize, crate::DecodeError> {
+        let len = check_len(len)?;
+
+        if len > self.max_list_len {
+            return Err(crate::DecodeError::ListTooLong {
+                len,
+                max_len: self.max_list_len,
+            });
+        }
+
+        Ok(len)
+    }
+
+    pub fn check_rows_to_delete(&self, amount: i32) -> Result<usize, crate::DecodeError> {
+        let amount = check_len(amount)?;
+
+        if amount > self.max_rows_to_delete {
+            return Err(crate::DecodeError::TooManyRowsToDelete {
+                amount,
+                max_amount: self.max_rows_to_delete,
+            });
+        }
+
+        Ok(amount)
+    }
+}
+
+fn check_len(len: i32) -> Result<usize, crate::DecodeError> {
+    if len < 0 {
+        return Err(crate::DecodeError::NegativeLength(len));
+    }
+
+    Ok(len as usize)
+}
+
+// Default keeps the baseline behaviour: everything the wire format can express is accepted.
+// Dictionaries did not exist in the baseline, so they are limited anyway
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_dictionaries: 1024,
+            max_dictionaries_size: 256 * 1024 * 1024,
+            ..Self::unlimited()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::socket_reader::SocketReaderInMem;
+
+    use crate::{DecodeError, DecodeLimits, MyNoSqlTcpContract};
+
+    fn init_table(size: usize) -> Vec<u8> {
+        MyNoSqlTcpContract::InitTable {
+            table_name: "test".to_string(),
+            data: vec![1u8; size],
+        }
+        .serialize()
+    }
+
+    #[tokio::test]
+    async fn default_limits_accept_large_payloads() {
+        let mut reader = SocketReaderInMem::new(init_table(1024 * 1024));
+
+        let result =
+            MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &DecodeLimits::default())
+                .await
+                .unwrap();
+
+        match result {
+            MyNoSqlTcpContract::InitTable { data, .. } => assert_eq!(data.len(), 1024 * 1024),
+            _ => panic!("InitTable is expected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn byte_array_limit_is_applied() {
+        let mut limits = DecodeLimits::strict();
+        limits.max_byte_array_size = 16;
+
+        let mut reader = SocketReaderInMem::new(init_table(17));
+
+        let err = MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &limits)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            DecodeError::ByteArrayTooLarge {
+                size: 17,
+                max_size: 16
+            }
+        ));
+        assert!(err.is_limit_violation());
+    }
+
+    #[tokio::test]
+    async fn rows_to_delete_limit_is_applied() {
+        let mut limits = DecodeLimits::strict();
+        limits.max_rows_to_delete = 1;
+
+        let payload = MyNoSqlTcpContract::DeleteRows {
+            table_name: "test".to_string(),
+            rows: vec![
+                crate::DeleteRowTcpContract {
+                    partition_key: "pk".to_string(),
+                    row_key: "rk1".to_string(),
+                },
+                crate::DeleteRowTcpContract {
+                    partition_key: "pk".to_string(),
+                    row_key: "rk2".to_string(),
+                },
+            ],
+        }
+        .serialize();
+
+        let mut reader = SocketReaderInMem::new(payload);
+
+        let err = MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &limits)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, DecodeError::TooManyRowsToDelete { .. }));
+    }
+
+    #[test]
+    fn negative_length_is_rejected() {
+        let err = DecodeLimits::default().check_list_len(-1).unwrap_err();
+        assert!(matches!(err, DecodeError::NegativeLength(-1)));
+    }
+
+    #[tokio::test]
+    async fn error_packet_is_returned_as_decode_error() {
+        let mut payload = vec![crate::tcp_packets::ERROR, 0];
+        crate::common_serializers::serialize_pascal_string(&mut payload, "boom");
+
+        let mut reader = SocketReaderInMem::new(payload);
+
+        let err =
+            MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &DecodeLimits::default())
+                .await
+                .unwrap_err();
+
+        match err {
+            DecodeError::PeerError(message) => assert_eq!(message, "boom"),
+            _ => panic!("PeerError is expected"),
+        }
+    }
+}
@@ -21,7 +21,11 @@ impl DeleteRowTcpContract {
         Ok(result)
     }
 
-    pub fn serialize(&self, buffer: &mut Vec<u8>) {
+    pub fn serialized_len(&self) -> usize {
+        2 + self.partition_key.len() + self.row_key.len()
+    }
+
+    pub fn serialize(&self, buffer: &mut impl crate::common_serializers::SerializerBuffer) {
         crate::common_serializers::serialize_pascal_string(buffer, self.partition_key.as_str());
         crate::common_serializers::serialize_pascal_string(buffer, self.row_key.as_str());
     }
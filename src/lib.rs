@@ -1,12 +1,46 @@
+mod buffer_pool;
+pub mod capture;
+mod checksum_algorithm;
 pub mod common_deserializers;
 pub mod common_serializers;
+mod compression_algorithm;
+mod compression_dictionaries;
+mod compression_offload;
+mod compression_policy;
+mod decode_error;
+mod decode_limits;
 mod delete_row_tcp_contract;
+pub mod metrics;
 pub mod payload_comressor;
+pub mod payload_encryption;
+mod peer_capabilities;
+mod prepared_contract;
+mod recording_socket_reader;
+mod serialize_error;
+mod sync_batch_tcp_contract;
 mod tcp_contracts;
 pub mod tcp_packets;
 mod tcp_serializer;
+pub use buffer_pool::{BufferPool, PooledBuffer};
+pub use checksum_algorithm::ChecksumAlgorithm;
+pub use compression_algorithm::CompressionAlgorithm;
+pub use compression_dictionaries::CompressionDictionaries;
+pub use compression_offload::CompressionOffload;
+pub use compression_policy::{AdaptiveCompressionSettings, CompressionPolicy};
+pub use decode_error::{DecodeError, DecodeErrorHandler};
+pub use decode_limits::DecodeLimits;
 pub use delete_row_tcp_contract::DeleteRowTcpContract;
-pub use tcp_contracts::MyNoSqlTcpContract;
+#[cfg(feature = "encryption")]
+pub use payload_encryption::{KeyProvider, PreSharedKeys};
+pub use peer_capabilities::PeerCapabilities;
+pub use prepared_contract::PreparedContract;
+pub use recording_socket_reader::RecordingSocketReader;
+pub use serialize_error::SerializeError;
+pub use sync_batch_tcp_contract::{
+    ExpirationTimeBatchPartition, ExpirationTimeBatchTcpContract, LastReadTimeBatchPartition,
+    LastReadTimeBatchTcpContract,
+};
+pub use tcp_contracts::{MyNoSqlTcpContract, GREETING_WITH_CAPABILITIES_VERSION};
 pub use tcp_serializer::MyNoSqlReaderTcpSerializer;
 pub mod sync_to_main;
 mod vec_writer;
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use super::{PacketDirection, PacketMetricsSnapshot, ProtocolMetrics, ProtocolMetricsSnapshot};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PacketMetricsKey {
+    direction: PacketDirection,
+    packet_name: &'static str,
+    table_name: String,
+}
+
+#[derive(Default)]
+struct PacketCounters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+pub struct AtomicProtocolMetrics {
+    packets: RwLock<HashMap<PacketMetricsKey, Arc<PacketCounters>>>,
+    compression_attempts: AtomicU64,
+    compression_applied: AtomicU64,
+    compression_uncompressed_bytes: AtomicU64,
+    compression_compressed_bytes: AtomicU64,
+    decompressions: AtomicU64,
+    decompression_compressed_bytes: AtomicU64,
+    decompression_uncompressed_bytes: AtomicU64,
+    decompression_micros: AtomicU64,
+    sync_queue_depths: RwLock<HashMap<&'static str, Arc<AtomicUsize>>>,
+    sync_confirmations: AtomicU64,
+    sync_confirmation_latency_micros: AtomicU64,
+    sync_max_confirmation_latency_micros: AtomicU64,
+    sync_delivery_timeouts: AtomicU64,
+    sync_queue_entries: RwLock<HashMap<&'static str, Arc<AtomicUsize>>>,
+    sync_queue_bytes: RwLock<HashMap<&'static str, Arc<AtomicUsize>>>,
+    sync_dropped_entries: RwLock<HashMap<&'static str, Arc<AtomicUsize>>>,
+}
+
+impl AtomicProtocolMetrics {
+    pub fn new() -> Self {
+        Self {
+            packets: RwLock::new(HashMap::new()),
+            compression_attempts: AtomicU64::new(0),
+            compression_applied: AtomicU64::new(0),
+            compression_uncompressed_bytes: AtomicU64::new(0),
+            compression_compressed_bytes: AtomicU64::new(0),
+            decompressions: AtomicU64::new(0),
+            decompression_compressed_bytes: AtomicU64::new(0),
+            decompression_uncompressed_bytes: AtomicU64::new(0),
+            decompression_micros: AtomicU64::new(0),
+            sync_queue_depths: RwLock::new(HashMap::new()),
+            sync_confirmations: AtomicU64::new(0),
+            sync_confirmation_latency_micros: AtomicU64::new(0),
+            sync_max_confirmation_latency_micros: AtomicU64::new(0),
+            sync_delivery_timeouts: AtomicU64::new(0),
+            sync_queue_entries: RwLock::new(HashMap::new()),
+            sync_queue_bytes: RwLock::new(HashMap::new()),
+            sync_dropped_entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn get_packet_counters(&self, key: PacketMetricsKey) -> Arc<PacketCounters> {
+        if let Some(counters) = self.packets.read().unwrap().get(&key) {
+            return counters.clone();
+        }
+
+        let mut write_access = self.packets.write().unwrap();
+        write_access.entry(key).or_default().clone()
+    }
+
+    pub fn get_snapshot(&self) -> ProtocolMetricsSnapshot {
+        let mut packets: Vec<PacketMetricsSnapshot> = self
+            .packets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, counters)| PacketMetricsSnapshot {
+                direction: key.direction,
+                packet_name: key.packet_name,
+                table_name: key.table_name.clone(),
+                packets: counters.packets.load(Ordering::Relaxed),
+                bytes: counters.bytes.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        packets.sort_by(|a, b| {
+            (a.direction, a.packet_name, &a.table_name).cmp(&(
+                b.direction,
+                b.packet_name,
+                &b.table_name,
+            ))
+        });
+
+        let sync_queue_depths = get_queue_values(&self.sync_queue_depths);
+
+        ProtocolMetricsSnapshot {
+            packets,
+            compression_attempts: self.compression_attempts.load(Ordering::Relaxed),
+            compression_applied: self.compression_applied.load(Ordering::Relaxed),
+            compression_uncompressed_bytes: self
+                .compression_uncompressed_bytes
+                .load(Ordering::Relaxed),
+            compression_compressed_bytes: self.compression_compressed_bytes.load(Ordering::Relaxed),
+            decompressions: self.decompressions.load(Ordering::Relaxed),
+            decompression_compressed_bytes: self
+                .decompression_compressed_bytes
+                .load(Ordering::Relaxed),
+            decompression_uncompressed_bytes: self
+                .decompression_uncompressed_bytes
+                .load(Ordering::Relaxed),
+            decompression_micros: self.decompression_micros.load(Ordering::Relaxed),
+            sync_queue_depths,
+            sync_confirmations: self.sync_confirmations.load(Ordering::Relaxed),
+            sync_confirmation_latency_micros: self
+                .sync_confirmation_latency_micros
+                .load(Ordering::Relaxed),
+            sync_max_confirmation_latency_micros: self
+                .sync_max_confirmation_latency_micros
+                .load(Ordering::Relaxed),
+            sync_delivery_timeouts: self.sync_delivery_timeouts.load(Ordering::Relaxed),
+            sync_queue_entries: get_queue_values(&self.sync_queue_entries),
+            sync_queue_bytes: get_queue_values(&self.sync_queue_bytes),
+            sync_dropped_entries: get_queue_values(&self.sync_dropped_entries),
+        }
+    }
+}
+
+fn get_queue_counter(
+    counters: &RwLock<HashMap<&'static str, Arc<AtomicUsize>>>,
+    queue_name: &'static str,
+) -> Arc<AtomicUsize> {
+    if let Some(value) = counters.read().unwrap().get(queue_name) {
+        return value.clone();
+    }
+
+    let mut write_access = counters.write().unwrap();
+    write_access.entry(queue_name).or_default().clone()
+}
+
+fn get_queue_values(
+    counters: &RwLock<HashMap<&'static str, Arc<AtomicUsize>>>,
+) -> Vec<(&'static str, usize)> {
+    let mut result: Vec<(&'static str, usize)> = counters
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(queue_name, value)| (*queue_name, value.load(Ordering::Relaxed)))
+        .collect();
+
+    result.sort();
+    result
+}
+
+impl ProtocolMetrics for AtomicProtocolMetrics {
+    fn packet(
+        &self,
+        direction: PacketDirection,
+        packet_name: &'static str,
+        table_name: Option<&str>,
+        size: usize,
+    ) {
+        let counters = self.get_packet_counters(PacketMetricsKey {
+            direction,
+            packet_name,
+            table_name: table_name.unwrap_or_default().to_string(),
+        });
+
+        counters.packets.fetch_add(1, Ordering::Relaxed);
+        counters.bytes.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    fn payload_compressed(&self, uncompressed_size: usize, compressed_size: usize, applied: bool) {
+        self.compression_attempts.fetch_add(1, Ordering::Relaxed);
+
+        if applied {
+            self.compression_applied.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.compression_uncompressed_bytes
+            .fetch_add(uncompressed_size as u64, Ordering::Relaxed);
+        self.compression_compressed_bytes
+            .fetch_add(compressed_size as u64, Ordering::Relaxed);
+    }
+
+    fn payload_decompressed(
+        &self,
+        compressed_size: usize,
+        uncompressed_size: usize,
+        duration: Duration,
+    ) {
+        self.decompressions.fetch_add(1, Ordering::Relaxed);
+        self.decompression_compressed_bytes
+            .fetch_add(compressed_size as u64, Ordering::Relaxed);
+        self.decompression_uncompressed_bytes
+            .fetch_add(uncompressed_size as u64, Ordering::Relaxed);
+        self.decompression_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn sync_queue_depth(&self, queue_name: &'static str, depth: usize) {
+        get_queue_counter(&self.sync_queue_depths, queue_name).store(depth, Ordering::Relaxed);
+    }
+
+    fn sync_confirmed(&self, latency: Duration) {
+        let latency = latency.as_micros() as u64;
+        self.sync_confirmations.fetch_add(1, Ordering::Relaxed);
+        self.sync_confirmation_latency_micros
+            .fetch_add(latency, Ordering::Relaxed);
+        self.sync_max_confirmation_latency_micros
+            .fetch_max(latency, Ordering::Relaxed);
+    }
+
+    fn sync_delivery_timed_out(&self, _queue_name: &'static str, _waited: Duration) {
+        self.sync_delivery_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn sync_queue_usage(&self, queue_name: &'static str, entries: usize, bytes: usize) {
+        get_queue_counter(&self.sync_queue_entries, queue_name).store(entries, Ordering::Relaxed);
+        get_queue_counter(&self.sync_queue_bytes, queue_name).store(bytes, Ordering::Relaxed);
+    }
+
+    fn sync_entries_dropped(&self, queue_name: &'static str, amount: usize) {
+        get_queue_counter(&self.sync_dropped_entries, queue_name)
+            .fetch_add(amount, Ordering::Relaxed);
+    }
+}
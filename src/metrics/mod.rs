@@ -0,0 +1,6 @@
+mod atomic_protocol_metrics;
+mod protocol_metrics;
+mod protocol_metrics_snapshot;
+pub use atomic_protocol_metrics::*;
+pub use protocol_metrics::*;
+pub use protocol_metrics_snapshot::*;
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PacketDirection {
+    Incoming,
+    Outgoing,
+}
+
+impl PacketDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PacketDirection::Incoming => "in",
+            PacketDirection::Outgoing => "out",
+        }
+    }
+}
+
+pub trait ProtocolMetrics: Send + Sync + 'static {
+    fn packet(
+        &self,
+        direction: PacketDirection,
+        packet_name: &'static str,
+        table_name: Option<&str>,
+        size: usize,
+    );
+
+    fn payload_compressed(&self, uncompressed_size: usize, compressed_size: usize, applied: bool);
+
+    fn payload_decompressed(
+        &self,
+        compressed_size: usize,
+        uncompressed_size: usize,
+        duration: Duration,
+    );
+
+    fn sync_queue_depth(&self, queue_name: &'static str, depth: usize);
+
+    fn sync_confirmed(&self, latency: Duration);
+
+    fn sync_delivery_timed_out(&self, queue_name: &'static str, waited: Duration);
+
+    fn sync_queue_usage(&self, queue_name: &'static str, entries: usize, bytes: usize);
+
+    fn sync_entries_dropped(&self, queue_name: &'static str, amount: usize);
+}
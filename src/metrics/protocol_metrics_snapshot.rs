@@ -0,0 +1,276 @@
+use std::fmt::Write;
+
+use super::PacketDirection;
+
+#[derive(Debug, Clone)]
+pub struct PacketMetricsSnapshot {
+    pub direction: PacketDirection,
+    pub packet_name: &'static str,
+    pub table_name: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProtocolMetricsSnapshot {
+    pub packets: Vec<PacketMetricsSnapshot>,
+    pub compression_attempts: u64,
+    pub compression_applied: u64,
+    pub compression_uncompressed_bytes: u64,
+    pub compression_compressed_bytes: u64,
+    pub decompressions: u64,
+    pub decompression_compressed_bytes: u64,
+    pub decompression_uncompressed_bytes: u64,
+    pub decompression_micros: u64,
+    pub sync_queue_depths: Vec<(&'static str, usize)>,
+    pub sync_confirmations: u64,
+    pub sync_confirmation_latency_micros: u64,
+    pub sync_max_confirmation_latency_micros: u64,
+    pub sync_delivery_timeouts: u64,
+    pub sync_queue_entries: Vec<(&'static str, usize)>,
+    pub sync_queue_bytes: Vec<(&'static str, usize)>,
+    pub sync_dropped_entries: Vec<(&'static str, usize)>,
+}
+
+impl ProtocolMetricsSnapshot {
+    pub fn get_compression_ratio(&self) -> f64 {
+        if self.compression_compressed_bytes == 0 {
+            return 1.0;
+        }
+
+        self.compression_uncompressed_bytes as f64 / self.compression_compressed_bytes as f64
+    }
+
+    pub fn to_prometheus_text(&self) -> String {
+        let mut result = String::new();
+
+        write_header(
+            &mut result,
+            "my_no_sql_tcp_packets_total",
+            "counter",
+            "Amount of packets by direction, packet type and table",
+        );
+
+        for item in &self.packets {
+            writeln!(
+                result,
+                "my_no_sql_tcp_packets_total{{direction=\"{}\",packet=\"{}\",table=\"{}\"}} {}",
+                item.direction.as_str(),
+                item.packet_name,
+                escape_label(&item.table_name),
+                item.packets
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut result,
+            "my_no_sql_tcp_bytes_total",
+            "counter",
+            "Amount of bytes by direction, packet type and table",
+        );
+
+        for item in &self.packets {
+            writeln!(
+                result,
+                "my_no_sql_tcp_bytes_total{{direction=\"{}\",packet=\"{}\",table=\"{}\"}} {}",
+                item.direction.as_str(),
+                item.packet_name,
+                escape_label(&item.table_name),
+                item.bytes
+            )
+            .unwrap();
+        }
+
+        write_value(
+            &mut result,
+            "my_no_sql_tcp_compression_attempts_total",
+            "counter",
+            "Amount of payloads we tried to compress",
+            self.compression_attempts,
+        );
+
+        write_value(
+            &mut result,
+            "my_no_sql_tcp_compression_applied_total",
+            "counter",
+            "Amount of payloads sent compressed",
+            self.compression_applied,
+        );
+
+        write_value(
+            &mut result,
+            "my_no_sql_tcp_compression_uncompressed_bytes_total",
+            "counter",
+            "Size of payloads before compression",
+            self.compression_uncompressed_bytes,
+        );
+
+        write_value(
+            &mut result,
+            "my_no_sql_tcp_compression_compressed_bytes_total",
+            "counter",
+            "Size of payloads after compression",
+            self.compression_compressed_bytes,
+        );
+
+        write_header(
+            &mut result,
+            "my_no_sql_tcp_compression_ratio",
+            "gauge",
+            "Achieved compression ratio",
+        );
+        writeln!(
+            result,
+            "my_no_sql_tcp_compression_ratio {}",
+            self.get_compression_ratio()
+        )
+        .unwrap();
+
+        write_value(
+            &mut result,
+            "my_no_sql_tcp_decompressions_total",
+            "counter",
+            "Amount of decompressed payloads",
+            self.decompressions,
+        );
+
+        write_value(
+            &mut result,
+            "my_no_sql_tcp_decompressed_bytes_total",
+            "counter",
+            "Size of payloads after decompression",
+            self.decompression_uncompressed_bytes,
+        );
+
+        write_header(
+            &mut result,
+            "my_no_sql_tcp_decompression_seconds_total",
+            "counter",
+            "Time spent to decompress payloads",
+        );
+        writeln!(
+            result,
+            "my_no_sql_tcp_decompression_seconds_total {}",
+            micros_to_seconds(self.decompression_micros)
+        )
+        .unwrap();
+
+        write_header(
+            &mut result,
+            "my_no_sql_sync_queue_depth",
+            "gauge",
+            "Amount of events waiting to be delivered to main node",
+        );
+
+        write_queue_values(
+            &mut result,
+            "my_no_sql_sync_queue_depth",
+            &self.sync_queue_depths,
+        );
+
+        write_header(
+            &mut result,
+            "my_no_sql_sync_queue_entries",
+            "gauge",
+            "Amount of partition and row keys waiting to be delivered to main node",
+        );
+        write_queue_values(
+            &mut result,
+            "my_no_sql_sync_queue_entries",
+            &self.sync_queue_entries,
+        );
+
+        write_header(
+            &mut result,
+            "my_no_sql_sync_queue_bytes",
+            "gauge",
+            "Approximate memory used by sync queues",
+        );
+        write_queue_values(
+            &mut result,
+            "my_no_sql_sync_queue_bytes",
+            &self.sync_queue_bytes,
+        );
+
+        write_header(
+            &mut result,
+            "my_no_sql_sync_dropped_entries_total",
+            "counter",
+            "Amount of partition and row keys dropped or collapsed because sync queue limits were reached",
+        );
+        write_queue_values(
+            &mut result,
+            "my_no_sql_sync_dropped_entries_total",
+            &self.sync_dropped_entries,
+        );
+
+        write_header(
+            &mut result,
+            "my_no_sql_sync_confirmation_latency_seconds",
+            "summary",
+            "Time between sending sync event to main node and getting confirmation",
+        );
+        writeln!(
+            result,
+            "my_no_sql_sync_confirmation_latency_seconds_sum {}",
+            micros_to_seconds(self.sync_confirmation_latency_micros)
+        )
+        .unwrap();
+        writeln!(
+            result,
+            "my_no_sql_sync_confirmation_latency_seconds_count {}",
+            self.sync_confirmations
+        )
+        .unwrap();
+
+        write_header(
+            &mut result,
+            "my_no_sql_sync_confirmation_latency_max_seconds",
+            "gauge",
+            "Max time between sending sync event to main node and getting confirmation",
+        );
+        writeln!(
+            result,
+            "my_no_sql_sync_confirmation_latency_max_seconds {}",
+            micros_to_seconds(self.sync_max_confirmation_latency_micros)
+        )
+        .unwrap();
+
+        write_value(
+            &mut result,
+            "my_no_sql_sync_delivery_timeouts_total",
+            "counter",
+            "Amount of sync events which were not confirmed by main node in time and were sent again",
+            self.sync_delivery_timeouts,
+        );
+
+        result
+    }
+}
+
+fn write_header(result: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(result, "# HELP {} {}", name, help).unwrap();
+    writeln!(result, "# TYPE {} {}", name, metric_type).unwrap();
+}
+
+fn write_value(result: &mut String, name: &str, metric_type: &str, help: &str, value: u64) {
+    write_header(result, name, metric_type, help);
+    writeln!(result, "{} {}", name, value).unwrap();
+}
+
+fn write_queue_values(result: &mut String, name: &str, values: &[(&'static str, usize)]) {
+    for (queue_name, value) in values {
+        writeln!(result, "{}{{queue=\"{}\"}} {}", name, queue_name, value).unwrap();
+    }
+}
+
+fn micros_to_seconds(micros: u64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
+fn escape_label(src: &str) -> String {
+    src.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,53 +1,309 @@
-use crate::vec_writer::VecWriter;
-use std::io::{Cursor, Read, Write};
+use crate::{
+    compression_offload::{is_cancelled, write_all_cancellable, CancellableReader},
+    vec_writer::VecWriter,
+    CompressionAlgorithm, DecodeError, DecodeLimits,
+};
+use std::io::{Cursor, Read};
+use tokio_util::sync::CancellationToken;
+
+// Output buffer grows as the data is actually decompressed. Declared size only helps up to this
+const MAX_INITIAL_CAPACITY: usize = 64 * 1024;
+
+// Lz4 block can not expand the data more than this
+#[cfg(feature = "lz4")]
+const LZ4_MAX_RATIO: usize = 255;
 
 pub fn compress(payload: &[u8]) -> Result<Vec<u8>, zip::result::ZipError> {
-    let mut writer = VecWriter::new();
+    compress_with_level(payload, None)
+}
+
+pub fn compress_with_level(
+    payload: &[u8],
+    level: Option<i32>,
+) -> Result<Vec<u8>, zip::result::ZipError> {
+    let mut result = Vec::new();
+    compress_with_level_into(payload, level, &mut result)?;
+    Ok(result)
+}
+
+// Output buffer is cleared and reused, so pooled buffers keep their capacity
+pub fn compress_with_level_into(
+    payload: &[u8],
+    level: Option<i32>,
+    out: &mut Vec<u8>,
+) -> Result<(), zip::result::ZipError> {
+    compress_with_level_into_cancellable(payload, level, out, None)
+}
+
+fn compress_with_level_into_cancellable(
+    payload: &[u8],
+    level: Option<i32>,
+    out: &mut Vec<u8>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<(), zip::result::ZipError> {
+    let mut writer = VecWriter::from_vec(std::mem::take(out));
 
     {
         let mut zip = zip::ZipWriter::new(&mut writer);
 
-        let options =
-            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(level);
 
         zip.start_file("d", options)?;
+        write_all_cancellable(&mut zip, payload, cancellation)?;
+        zip.finish()?;
+    }
 
-        let mut pos = 0;
-        while pos < payload.len() {
-            let size = zip.write(&payload[pos..])?;
+    *out = writer.buf;
 
-            pos += size;
-        }
+    Ok(())
+}
 
-        zip.finish()?;
+// Keeps the behaviour of the API which existed before limits were introduced - size is not limited
+pub fn decompress(payload: &[u8]) -> Result<Vec<u8>, zip::result::ZipError> {
+    let max_size = DecodeLimits::unlimited().max_decompressed_size;
+
+    decompress_with_limit(payload, max_size).map_err(|err| match err {
+        DecodeError::Decompression(err) => err,
+        DecodeError::DecompressionIo(err) => zip::result::ZipError::Io(err),
+        _ => zip::result::ZipError::Io(std::io::Error::other(format!("{:?}", err))),
+    })
+}
+
+// Reads directly from the borrowed payload. Declared uncompressed size is used only as a hint
+// for the output buffer, since it comes from the peer and can not be trusted.
+pub fn decompress_with_limit(payload: &[u8], max_size: usize) -> Result<Vec<u8>, DecodeError> {
+    decompress_with_limit_cancellable(payload, max_size, None)
+}
+
+fn decompress_with_limit_cancellable(
+    payload: &[u8],
+    max_size: usize,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(payload))?;
+
+    let zip_file = zip.by_name("d")?;
+
+    let declared_size = zip_file.size();
+
+    if declared_size > max_size as u64 {
+        return Err(DecodeError::DecompressedPayloadTooLarge { max_size });
     }
 
-    Ok(writer.buf)
+    read_to_end_with_limit(zip_file, declared_size as usize, max_size, cancellation)
 }
 
-pub fn decompress(payload: &[u8]) -> Result<Vec<u8>, zip::result::ZipError> {
-    let c = Cursor::new(payload.to_vec());
+pub fn compress_with_algorithm(
+    payload: &[u8],
+    algorithm: CompressionAlgorithm,
+    level: Option<i32>,
+) -> std::io::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    compress_with_algorithm_into(payload, algorithm, level, &mut result)?;
+    Ok(result)
+}
 
-    let mut zip = zip::ZipArchive::new(c)?;
+pub fn compress_with_algorithm_into(
+    payload: &[u8],
+    algorithm: CompressionAlgorithm,
+    level: Option<i32>,
+    out: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    compress_with_algorithm_into_cancellable(payload, algorithm, level, out, None)
+}
 
-    let mut page_buffer: Vec<u8> = Vec::new();
+pub(crate) fn compress_with_algorithm_into_cancellable(
+    payload: &[u8],
+    algorithm: CompressionAlgorithm,
+    level: Option<i32>,
+    out: &mut Vec<u8>,
+    cancellation: Option<&CancellationToken>,
+) -> std::io::Result<()> {
+    match algorithm {
+        CompressionAlgorithm::Zip => {
+            compress_with_level_into_cancellable(payload, level, out, cancellation)
+                .map_err(std::io::Error::other)
+        }
+        #[cfg(feature = "deflate")]
+        CompressionAlgorithm::Deflate => {
+            let compression = match level {
+                Some(level) => flate2::Compression::new(level.clamp(0, 9) as u32),
+                None => flate2::Compression::default(),
+            };
 
-    for i in 0..zip.len() {
-        let mut zip_file = zip.by_index(i)?;
+            out.clear();
+            let mut encoder = flate2::write::DeflateEncoder::new(out, compression);
+            write_all_cancellable(&mut encoder, payload, cancellation)?;
+            encoder.finish()?;
+            Ok(())
+        }
+        #[cfg(feature = "zstd")]
+        CompressionAlgorithm::Zstd => {
+            out.clear();
+            let mut encoder =
+                zstd::stream::write::Encoder::new(out, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            write_all_cancellable(&mut encoder, payload, cancellation)?;
+            encoder.finish()?;
+            Ok(())
+        }
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => {
+            out.clear();
+            out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            out.resize(
+                4 + lz4_flex::block::get_maximum_output_size(payload.len()),
+                0,
+            );
 
-        if zip_file.name() == "d" {
-            let mut buffer = [0u8; 1024 * 1024];
+            let size = lz4_flex::block::compress_into(payload, &mut out[4..])
+                .map_err(std::io::Error::other)?;
 
-            loop {
-                let read_size = zip_file.read(&mut buffer[..])?;
-                if read_size == 0 {
-                    break;
-                }
+            out.truncate(4 + size);
+            Ok(())
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "Compression algorithm {} is not supported",
+                algorithm.as_str()
+            ),
+        )),
+    }
+}
+
+pub fn decompress_with_algorithm(
+    payload: &[u8],
+    algorithm: CompressionAlgorithm,
+    max_size: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    decompress_with_algorithm_cancellable(payload, algorithm, max_size, None)
+}
+
+pub(crate) fn decompress_with_algorithm_cancellable(
+    payload: &[u8],
+    algorithm: CompressionAlgorithm,
+    max_size: usize,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Vec<u8>, DecodeError> {
+    match algorithm {
+        CompressionAlgorithm::Zip => {
+            decompress_with_limit_cancellable(payload, max_size, cancellation)
+        }
+        #[cfg(feature = "deflate")]
+        CompressionAlgorithm::Deflate => read_to_end_with_limit(
+            flate2::read::DeflateDecoder::new(payload),
+            payload.len(),
+            max_size,
+            cancellation,
+        ),
+        #[cfg(feature = "zstd")]
+        CompressionAlgorithm::Zstd => {
+            let decoder =
+                zstd::stream::read::Decoder::new(payload).map_err(DecodeError::DecompressionIo)?;
+            read_to_end_with_limit(decoder, payload.len(), max_size, cancellation)
+        }
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => {
+            if payload.len() < 4 {
+                return Err(DecodeError::DecompressionIo(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Lz4 payload has no size prefix",
+                )));
+            }
+
+            let size = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
 
-                page_buffer.extend(&buffer[..read_size]);
+            if size as usize > max_size {
+                return Err(DecodeError::DecompressedPayloadTooLarge { max_size });
             }
+
+            // Output is allocated with the declared size, so it must be one the payload can produce
+            if size as usize > payload.len().saturating_mul(LZ4_MAX_RATIO) {
+                return Err(DecodeError::DecompressionIo(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Lz4 payload declares more data than it can hold",
+                )));
+            }
+
+            lz4_flex::decompress_size_prepended(payload).map_err(|err| {
+                DecodeError::DecompressionIo(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    err,
+                ))
+            })
         }
+        #[allow(unreachable_patterns)]
+        _ => Err(DecodeError::UnsupportedCompressionAlgorithm(
+            algorithm.as_u8(),
+        )),
     }
+}
+
+pub(crate) fn read_to_end_with_limit(
+    reader: impl Read,
+    expected_size: usize,
+    max_size: usize,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut result = Vec::with_capacity(expected_size.min(max_size).min(MAX_INITIAL_CAPACITY));
+
+    CancellableReader::new(reader, cancellation)
+        .take((max_size as u64).saturating_add(1))
+        .read_to_end(&mut result)
+        .map_err(|err| {
+            if is_cancelled(cancellation) {
+                DecodeError::Cancelled
+            } else {
+                DecodeError::DecompressionIo(err)
+            }
+        })?;
 
-    Ok(page_buffer)
+    if result.len() > max_size {
+        return Err(DecodeError::DecompressedPayloadTooLarge { max_size });
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared_size_does_not_preallocate_output() {
+        let payload = vec![1u8; 16];
+
+        let result =
+            read_to_end_with_limit(payload.as_slice(), usize::MAX, usize::MAX, None).unwrap();
+
+        assert_eq!(result, payload);
+        assert!(result.capacity() <= MAX_INITIAL_CAPACITY);
+    }
+
+    #[test]
+    fn legacy_decompress_is_not_limited() {
+        let payload = vec![0u8; 2 * 1024 * 1024];
+        let compressed = compress(payload.as_slice()).unwrap();
+
+        assert_eq!(decompress(compressed.as_slice()).unwrap(), payload);
+        assert!(matches!(
+            decompress_with_limit(compressed.as_slice(), 1024),
+            Err(DecodeError::DecompressedPayloadTooLarge { max_size: 1024 })
+        ));
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_payload_declaring_impossible_size_is_rejected() {
+        let mut payload = (u32::MAX).to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0; 8]);
+
+        assert!(matches!(
+            decompress_with_algorithm(payload.as_slice(), CompressionAlgorithm::Lz4, usize::MAX),
+            Err(DecodeError::DecompressionIo(_))
+        ));
+    }
 }
@@ -0,0 +1,112 @@
+#[cfg(feature = "encryption")]
+use std::collections::HashMap;
+
+#[cfg(feature = "encryption")]
+use crate::DecodeError;
+
+pub const ENCRYPTION_KEY_SIZE: usize = 32;
+pub const ENCRYPTION_NONCE_SIZE: usize = 12;
+
+// Keys are pre-shared between the nodes. Key id travels with every encrypted payload,
+// so keys can be rotated by adding a new key on the readers first and switching the writer after.
+// Exists only with the encryption feature, so keys can not be configured for a build which can not use them
+#[cfg(feature = "encryption")]
+pub trait KeyProvider: Send + Sync {
+    // Key outgoing data packets are encrypted with
+    fn get_encryption_key_id(&self) -> u32;
+    fn get_key(&self, key_id: u32) -> Option<[u8; ENCRYPTION_KEY_SIZE]>;
+}
+
+#[cfg(feature = "encryption")]
+pub struct PreSharedKeys {
+    encryption_key_id: u32,
+    keys: HashMap<u32, [u8; ENCRYPTION_KEY_SIZE]>,
+}
+
+#[cfg(feature = "encryption")]
+impl PreSharedKeys {
+    pub fn new(key_id: u32, key: [u8; ENCRYPTION_KEY_SIZE]) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(key_id, key);
+
+        Self {
+            encryption_key_id: key_id,
+            keys,
+        }
+    }
+
+    // Key is accepted for incoming payloads, but outgoing ones are still encrypted with the current key
+    pub fn with_key(mut self, key_id: u32, key: [u8; ENCRYPTION_KEY_SIZE]) -> Self {
+        self.keys.insert(key_id, key);
+        self
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl KeyProvider for PreSharedKeys {
+    fn get_encryption_key_id(&self) -> u32 {
+        self.encryption_key_id
+    }
+
+    fn get_key(&self, key_id: u32) -> Option<[u8; ENCRYPTION_KEY_SIZE]> {
+        self.keys.get(&key_id).copied()
+    }
+}
+
+pub fn is_supported() -> bool {
+    cfg!(feature = "encryption")
+}
+
+// Payload is encrypted with ChaCha20-Poly1305. Key id is authenticated as associated data,
+// so the payload can not be replayed under the other key
+#[cfg(feature = "encryption")]
+pub fn encrypt(
+    key_id: u32,
+    key: &[u8; ENCRYPTION_KEY_SIZE],
+    payload: &[u8],
+) -> Option<([u8; ENCRYPTION_NONCE_SIZE], Vec<u8>)> {
+    use chacha20poly1305::{
+        aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+        ChaCha20Poly1305, Key,
+    };
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: payload,
+                aad: &key_id.to_le_bytes(),
+            },
+        )
+        .ok()?;
+
+    Some((nonce.into(), ciphertext))
+}
+
+#[cfg(feature = "encryption")]
+pub fn decrypt(
+    key_id: u32,
+    key: &[u8; ENCRYPTION_KEY_SIZE],
+    nonce: &[u8; ENCRYPTION_NONCE_SIZE],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, DecodeError> {
+    use chacha20poly1305::{
+        aead::{Aead, KeyInit, Payload},
+        ChaCha20Poly1305, Key, Nonce,
+    };
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &key_id.to_le_bytes(),
+            },
+        )
+        .map_err(|_| DecodeError::DecryptionFailed { key_id })
+}
@@ -0,0 +1,186 @@
+use crate::{common_serializers::SerializerBuffer, ChecksumAlgorithm, CompressionAlgorithm};
+
+const LARGE_FRAMES_FLAG: u64 = 1;
+const SYNC_BATCHES_FLAG: u64 = 2;
+const READ_MOMENTS_FLAG: u64 = 4;
+const ENCRYPTION_FLAG: u64 = 8;
+const ROW_EXPIRATIONS_FLAG: u64 = 16;
+
+// Protocol extensions peer can handle. Sent with the Capabilities packet only to peers which
+// have announced they know it, so peers of the baseline protocol never see it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    pub compression_algorithms: Vec<CompressionAlgorithm>,
+    pub checksum_algorithms: Vec<ChecksumAlgorithm>,
+    pub supports_large_frames: bool,
+    pub supports_sync_batches: bool,
+    pub supports_read_moments: bool,
+    // Peer has keys configured, so it encrypts data packets and accepts only encrypted ones
+    pub supports_encryption: bool,
+    // Peer reads rows expiration packet which carries expiration time per row
+    pub supports_row_expirations: bool,
+}
+
+impl PeerCapabilities {
+    fn get_flags(&self) -> u64 {
+        let mut result = 0;
+
+        if self.supports_large_frames {
+            result |= LARGE_FRAMES_FLAG;
+        }
+
+        if self.supports_sync_batches {
+            result |= SYNC_BATCHES_FLAG;
+        }
+
+        if self.supports_read_moments {
+            result |= READ_MOMENTS_FLAG;
+        }
+
+        if self.supports_encryption {
+            result |= ENCRYPTION_FLAG;
+        }
+
+        if self.supports_row_expirations {
+            result |= ROW_EXPIRATIONS_FLAG;
+        }
+
+        result
+    }
+
+    pub fn body_len(&self) -> usize {
+        1 + self.compression_algorithms.len() + 1 + self.checksum_algorithms.len() + 8
+    }
+
+    pub fn serialize_body(&self, buffer: &mut impl SerializerBuffer) {
+        buffer.push(self.compression_algorithms.len() as u8);
+
+        for algorithm in &self.compression_algorithms {
+            buffer.push(algorithm.as_u8());
+        }
+
+        buffer.push(self.checksum_algorithms.len() as u8);
+
+        for algorithm in &self.checksum_algorithms {
+            buffer.push(algorithm.as_u8());
+        }
+
+        buffer.extend_from_slice(self.get_flags().to_le_bytes().as_slice());
+    }
+
+    // Body is length prefixed on the wire, so fields added later are skipped by older peers.
+    // Algorithms and flags we do not know about are skipped as well
+    pub fn deserialize_body(body: &[u8]) -> Option<Self> {
+        let mut pos = 0;
+
+        let compression_algorithms =
+            read_algorithms(body, &mut pos, CompressionAlgorithm::from_u8)?;
+        let checksum_algorithms = read_algorithms(body, &mut pos, ChecksumAlgorithm::from_u8)?;
+
+        let flags = body.get(pos..pos + 8)?;
+        let flags = u64::from_le_bytes(flags.try_into().ok()?);
+
+        Some(Self {
+            compression_algorithms,
+            checksum_algorithms,
+            supports_large_frames: flags & LARGE_FRAMES_FLAG > 0,
+            supports_sync_batches: flags & SYNC_BATCHES_FLAG > 0,
+            supports_read_moments: flags & READ_MOMENTS_FLAG > 0,
+            supports_encryption: flags & ENCRYPTION_FLAG > 0,
+            supports_row_expirations: flags & ROW_EXPIRATIONS_FLAG > 0,
+        })
+    }
+}
+
+fn read_algorithms<T>(
+    body: &[u8],
+    pos: &mut usize,
+    from_u8: fn(u8) -> Option<T>,
+) -> Option<Vec<T>> {
+    let amount = *body.get(*pos)? as usize;
+    *pos += 1;
+
+    let algorithms = body.get(*pos..*pos + amount)?;
+    *pos += amount;
+
+    Some(algorithms.iter().filter_map(|itm| from_u8(*itm)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::socket_reader::SocketReaderInMem;
+
+    use super::*;
+    use crate::{DecodeLimits, MyNoSqlTcpContract};
+
+    async fn deserialize(payload: Vec<u8>) -> MyNoSqlTcpContract {
+        let mut reader = SocketReaderInMem::new(payload);
+        MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &DecodeLimits::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn capabilities_round_trip() {
+        let capabilities = PeerCapabilities {
+            compression_algorithms: vec![CompressionAlgorithm::Zip],
+            checksum_algorithms: vec![ChecksumAlgorithm::Crc32c],
+            supports_large_frames: true,
+            supports_sync_batches: true,
+            supports_read_moments: false,
+            supports_encryption: true,
+            supports_row_expirations: true,
+        };
+
+        let contract = MyNoSqlTcpContract::Capabilities(capabilities.clone());
+        let payload = contract.serialize();
+        assert_eq!(payload.len(), contract.serialized_len());
+
+        match deserialize(payload).await {
+            MyNoSqlTcpContract::Capabilities(result) => assert_eq!(result, capabilities),
+            _ => panic!("Capabilities are expected"),
+        }
+    }
+
+    #[test]
+    fn unknown_algorithms_flags_and_fields_are_skipped() {
+        let mut body = vec![2, CompressionAlgorithm::Zip.as_u8(), 200, 0];
+        body.extend_from_slice((SYNC_BATCHES_FLAG | 1 << 40).to_le_bytes().as_slice());
+        // Field added by a later version
+        body.extend_from_slice(&[1, 2, 3]);
+
+        let result = PeerCapabilities::deserialize_body(body.as_slice()).unwrap();
+
+        assert_eq!(
+            result.compression_algorithms,
+            vec![CompressionAlgorithm::Zip]
+        );
+        assert!(result.supports_sync_batches);
+        assert!(!result.supports_large_frames);
+    }
+
+    #[test]
+    fn truncated_body_is_rejected() {
+        assert!(PeerCapabilities::deserialize_body(&[0, 0, 1]).is_none());
+    }
+
+    // Baseline main node reads the version byte, location, node version and compress flag if version > 0
+    #[test]
+    fn greeting_is_readable_by_baseline_main_node() {
+        let payload = MyNoSqlTcpContract::GreetingFromNode {
+            node_location: "loc".to_string(),
+            node_version: "1.0".to_string(),
+            compress: true,
+        }
+        .serialize();
+
+        let mut pos = 2;
+        for _ in 0..2 {
+            pos += 1 + payload[pos] as usize;
+        }
+
+        assert!(payload[1] > 0);
+        assert_eq!(payload[pos], 1);
+        assert_eq!(pos + 1, payload.len());
+    }
+}
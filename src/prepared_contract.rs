@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use crate::{
+    metrics::ProtocolMetrics, CompressionAlgorithm, CompressionPolicy, MyNoSqlTcpContract,
+};
+
+// Contract serialized once and shared between all the connections it is broadcasted to.
+// Compressed frames are prepared for the algorithms given at creation. If there is no frame
+// for the algorithm peer has negotiated, or compression did not make sence - uncompressed frame is used.
+pub struct PreparedContract {
+    packet_name: &'static str,
+    table_name: Option<String>,
+    uncompressed: Arc<[u8]>,
+    has_large_frame_encoding: bool,
+    compressed: Vec<(CompressionAlgorithm, Arc<[u8]>)>,
+}
+
+impl PreparedContract {
+    pub fn new(contract: &MyNoSqlTcpContract) -> Self {
+        Self {
+            packet_name: contract.get_packet_name(),
+            table_name: contract.get_table_name().map(|itm| itm.to_string()),
+            uncompressed: contract.serialize().into(),
+            has_large_frame_encoding: !contract.requires_large_frames()
+                || contract.has_large_frame_encoding(),
+            compressed: Vec::new(),
+        }
+    }
+
+    pub fn new_with_compression(
+        contract: &MyNoSqlTcpContract,
+        algorithms: &[CompressionAlgorithm],
+        policy: &CompressionPolicy,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Self {
+        let mut result = Self::new(contract);
+
+        if contract.is_compressed() || !policy.should_compress(contract) {
+            return result;
+        }
+
+        for algorithm in algorithms {
+            if result.has_algorithm(*algorithm) {
+                continue;
+            }
+
+            let compressed = contract.compress_serialized(
+                result.uncompressed.as_ref(),
+                *algorithm,
+                policy,
+                None,
+                metrics,
+                Default::default(),
+            );
+
+            if let Some(compressed) = compressed {
+                result.compressed.push((*algorithm, compressed.into()));
+            }
+        }
+
+        result
+    }
+
+    fn has_algorithm(&self, algorithm: CompressionAlgorithm) -> bool {
+        self.compressed.iter().any(|(itm, _)| *itm == algorithm)
+    }
+
+    pub fn get_packet_name(&self) -> &'static str {
+        self.packet_name
+    }
+
+    pub fn get_table_name(&self) -> Option<&str> {
+        self.table_name.as_deref()
+    }
+
+    // False if payload is too big for the contract frame and uncompressed frame is corrupted
+    pub fn has_large_frame_encoding(&self) -> bool {
+        self.has_large_frame_encoding
+    }
+
+    pub fn get_uncompressed_frame(&self) -> &Arc<[u8]> {
+        &self.uncompressed
+    }
+
+    pub fn get_frame(&self, algorithm: Option<CompressionAlgorithm>) -> &Arc<[u8]> {
+        if let Some(algorithm) = algorithm {
+            for (itm, frame) in &self.compressed {
+                if *itm == algorithm {
+                    return frame;
+                }
+            }
+        }
+
+        &self.uncompressed
+    }
+}
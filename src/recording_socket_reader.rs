@@ -0,0 +1,99 @@
+use my_tcp_sockets::socket_reader::{ReadingTcpContractFail, SocketReader};
+
+// Counts the bytes which are actually consumed from the socket and optionally keeps them,
+// so captures and metrics see the frame exactly as it came from the wire.
+pub struct RecordingSocketReader<'s, TSocketReader: SocketReader + Send + Sync> {
+    inner: &'s mut TSocketReader,
+    recorded: Option<Vec<u8>>,
+    consumed: usize,
+    header: [u8; 2],
+}
+
+impl<'s, TSocketReader: SocketReader + Send + Sync> RecordingSocketReader<'s, TSocketReader> {
+    pub fn new(inner: &'s mut TSocketReader, keep_bytes: bool) -> Self {
+        Self {
+            inner,
+            recorded: if keep_bytes { Some(Vec::new()) } else { None },
+            consumed: 0,
+            header: [0; 2],
+        }
+    }
+
+    pub fn get_consumed(&self) -> usize {
+        self.consumed
+    }
+
+    pub fn take_recorded(&mut self) -> Option<Vec<u8>> {
+        self.recorded.take()
+    }
+
+    // Byte which follows the packet id. Versioned packets keep the protocol version there
+    pub fn get_packet_version(&self) -> Option<u8> {
+        if self.consumed > 1 {
+            Some(self.header[1])
+        } else {
+            None
+        }
+    }
+
+    fn record(&mut self, data: &[u8]) {
+        for (index, b) in data.iter().enumerate() {
+            match self.header.get_mut(self.consumed + index) {
+                Some(header_byte) => *header_byte = *b,
+                None => break,
+            }
+        }
+
+        self.consumed += data.len();
+
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(data);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'s, TSocketReader: SocketReader + Send + Sync> SocketReader
+    for RecordingSocketReader<'s, TSocketReader>
+{
+    async fn read_byte(&mut self) -> Result<u8, ReadingTcpContractFail> {
+        let result = self.inner.read_byte().await?;
+        self.record(&[result]);
+        Ok(result)
+    }
+
+    async fn read_bool(&mut self) -> Result<bool, ReadingTcpContractFail> {
+        let result = self.read_byte().await?;
+        Ok(result > 0)
+    }
+
+    async fn read_i32(&mut self) -> Result<i32, ReadingTcpContractFail> {
+        let result = self.inner.read_i32().await?;
+        self.record(&result.to_le_bytes());
+        Ok(result)
+    }
+
+    async fn read_i64(&mut self) -> Result<i64, ReadingTcpContractFail> {
+        let result = self.inner.read_i64().await?;
+        self.record(&result.to_le_bytes());
+        Ok(result)
+    }
+
+    async fn read_u32(&mut self) -> Result<u32, ReadingTcpContractFail> {
+        let result = self.inner.read_u32().await?;
+        self.record(&result.to_le_bytes());
+        Ok(result)
+    }
+
+    async fn read_u64(&mut self) -> Result<u64, ReadingTcpContractFail> {
+        let result = self.inner.read_u64().await?;
+        self.record(&result.to_le_bytes());
+        Ok(result)
+    }
+
+    async fn read_buf(&mut self, buf: &mut [u8]) -> Result<(), ReadingTcpContractFail> {
+        self.inner.read_buf(buf).await?;
+        self.record(buf);
+        Ok(())
+    }
+}
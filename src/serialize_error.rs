@@ -0,0 +1,20 @@
+use crate::ChecksumAlgorithm;
+
+// Frame which can not be sent. Nothing is written to the socket, so the caller decides
+// if the connection has to be dropped or the data has to be sent some other way
+#[derive(Debug)]
+pub enum SerializeError {
+    // Payload exceeds 2 GiB and peer does not support large frames
+    FrameTooLarge {
+        packet_name: &'static str,
+        table_name: Option<String>,
+    },
+    Cancelled,
+    // Data packets are never sent in plain text once keys are configured
+    UnknownEncryptionKey(u32),
+    EncryptionFailed {
+        key_id: u32,
+    },
+    // Algorithm is not compiled in, so checksum can not be calculated
+    UnsupportedChecksumAlgorithm(ChecksumAlgorithm),
+}
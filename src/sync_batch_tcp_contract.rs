@@ -0,0 +1,406 @@
+use my_tcp_sockets::socket_reader::SocketReader;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::{common_serializers::SerializerBuffer, DecodeError, DecodeLimits};
+
+#[derive(Debug, Clone)]
+pub struct LastReadTimeBatchPartition {
+    pub partition_key: String,
+    // None - last read time of the partition itself is not updated.
+    // Some(None) - partition is read, main node stamps the moment it gets the update
+    pub partition_read: Option<Option<DateTimeAsMicroseconds>>,
+    pub rows: Vec<(String, Option<DateTimeAsMicroseconds>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LastReadTimeBatchTcpContract {
+    pub table_name: String,
+    pub partitions: Vec<LastReadTimeBatchPartition>,
+}
+
+impl LastReadTimeBatchTcpContract {
+    pub fn new(table_name: String) -> Self {
+        Self {
+            table_name,
+            partitions: Vec::new(),
+        }
+    }
+
+    pub fn get_partition_mut(&mut self, partition_key: &str) -> &mut LastReadTimeBatchPartition {
+        let index = match self
+            .partitions
+            .iter()
+            .position(|itm| itm.partition_key == partition_key)
+        {
+            Some(index) => index,
+            None => {
+                self.partitions.push(LastReadTimeBatchPartition {
+                    partition_key: partition_key.to_string(),
+                    partition_read: None,
+                    rows: Vec::new(),
+                });
+                self.partitions.len() - 1
+            }
+        };
+
+        &mut self.partitions[index]
+    }
+
+    pub fn has_read_moments(&self) -> bool {
+        self.partitions.iter().any(|itm| {
+            matches!(itm.partition_read, Some(Some(_)))
+                || itm
+                    .rows
+                    .iter()
+                    .any(|(_, read_moment)| read_moment.is_some())
+        })
+    }
+
+    // Read moments are on the wire since version 1 of the packet
+    pub async fn deserialize<TSocketReader: SocketReader>(
+        socket_reader: &mut TSocketReader,
+        limits: &DecodeLimits,
+        with_moments: bool,
+    ) -> Result<Self, DecodeError> {
+        let table_name = crate::common_deserializers::read_pascal_string(socket_reader).await?;
+
+        let amount = limits.check_list_len(socket_reader.read_i32().await?)?;
+
+        let mut partitions = crate::common_deserializers::list_with_capacity(amount);
+
+        for _ in 0..amount {
+            let partition_key =
+                crate::common_deserializers::read_pascal_string(socket_reader).await?;
+
+            let partition_read = match (socket_reader.read_bool().await?, with_moments) {
+                (false, _) => None,
+                (true, false) => Some(None),
+                (true, true) => {
+                    Some(crate::common_deserializers::read_date_time_opt(socket_reader).await?)
+                }
+            };
+
+            let rows = crate::common_deserializers::read_list_of_keys_with_moments(
+                socket_reader,
+                limits,
+                with_moments,
+            )
+            .await?;
+
+            partitions.push(LastReadTimeBatchPartition {
+                partition_key,
+                partition_read,
+                rows,
+            });
+        }
+
+        Ok(Self {
+            table_name,
+            partitions,
+        })
+    }
+
+    pub fn serialized_len(&self, with_moments: bool) -> usize {
+        let moment_len = if with_moments { 8 } else { 0 };
+
+        1 + self.table_name.len()
+            + 4
+            + self
+                .partitions
+                .iter()
+                .map(|itm| {
+                    let partition_read_len = if itm.partition_read.is_some() {
+                        1 + moment_len
+                    } else {
+                        1
+                    };
+
+                    1 + itm.partition_key.len()
+                        + partition_read_len
+                        + 4
+                        + itm
+                            .rows
+                            .iter()
+                            .map(|(row_key, _)| 1 + row_key.len() + moment_len)
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    pub fn serialize(&self, buffer: &mut impl SerializerBuffer, with_moments: bool) {
+        crate::common_serializers::serialize_pascal_string(buffer, self.table_name.as_str());
+        crate::common_serializers::serialize_i32(buffer, self.partitions.len() as i32);
+
+        for partition in &self.partitions {
+            crate::common_serializers::serialize_pascal_string(
+                buffer,
+                partition.partition_key.as_str(),
+            );
+
+            match partition.partition_read {
+                Some(read_moment) => {
+                    crate::common_serializers::serialize_bool(buffer, true);
+
+                    if with_moments {
+                        crate::common_serializers::serialize_date_time_opt(buffer, read_moment);
+                    }
+                }
+                None => {
+                    crate::common_serializers::serialize_bool(buffer, false);
+                }
+            }
+
+            crate::common_serializers::serialize_list_of_keys_with_moments(
+                buffer,
+                &partition.rows,
+                with_moments,
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExpirationTimeBatchPartition {
+    pub partition_key: String,
+    // None - expiration time of the partition itself is not updated
+    pub partition_expiration: Option<Option<DateTimeAsMicroseconds>>,
+    pub rows: Vec<(String, Option<DateTimeAsMicroseconds>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExpirationTimeBatchTcpContract {
+    pub table_name: String,
+    pub partitions: Vec<ExpirationTimeBatchPartition>,
+}
+
+impl ExpirationTimeBatchTcpContract {
+    pub fn new(table_name: String) -> Self {
+        Self {
+            table_name,
+            partitions: Vec::new(),
+        }
+    }
+
+    pub fn get_partition_mut(&mut self, partition_key: &str) -> &mut ExpirationTimeBatchPartition {
+        let index = match self
+            .partitions
+            .iter()
+            .position(|itm| itm.partition_key == partition_key)
+        {
+            Some(index) => index,
+            None => {
+                self.partitions.push(ExpirationTimeBatchPartition {
+                    partition_key: partition_key.to_string(),
+                    partition_expiration: None,
+                    rows: Vec::new(),
+                });
+                self.partitions.len() - 1
+            }
+        };
+
+        &mut self.partitions[index]
+    }
+
+    pub async fn deserialize<TSocketReader: SocketReader>(
+        socket_reader: &mut TSocketReader,
+        limits: &DecodeLimits,
+    ) -> Result<Self, DecodeError> {
+        let table_name = crate::common_deserializers::read_pascal_string(socket_reader).await?;
+
+        let amount = limits.check_list_len(socket_reader.read_i32().await?)?;
+
+        let mut partitions = crate::common_deserializers::list_with_capacity(amount);
+
+        for _ in 0..amount {
+            let partition_key =
+                crate::common_deserializers::read_pascal_string(socket_reader).await?;
+
+            let partition_expiration = if socket_reader.read_bool().await? {
+                Some(crate::common_deserializers::read_date_time_opt(socket_reader).await?)
+            } else {
+                None
+            };
+
+            let rows_amount = limits.check_list_len(socket_reader.read_i32().await?)?;
+
+            let mut rows = crate::common_deserializers::list_with_capacity(rows_amount);
+
+            for _ in 0..rows_amount {
+                let row_key =
+                    crate::common_deserializers::read_pascal_string(socket_reader).await?;
+                let expiration_time =
+                    crate::common_deserializers::read_date_time_opt(socket_reader).await?;
+                rows.push((row_key, expiration_time));
+            }
+
+            partitions.push(ExpirationTimeBatchPartition {
+                partition_key,
+                partition_expiration,
+                rows,
+            });
+        }
+
+        Ok(Self {
+            table_name,
+            partitions,
+        })
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        1 + self.table_name.len()
+            + 4
+            + self
+                .partitions
+                .iter()
+                .map(|itm| {
+                    let partition_expiration_len = if itm.partition_expiration.is_some() {
+                        9
+                    } else {
+                        1
+                    };
+
+                    1 + itm.partition_key.len()
+                        + partition_expiration_len
+                        + 4
+                        + itm
+                            .rows
+                            .iter()
+                            .map(|(row_key, _)| 1 + row_key.len() + 8)
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    pub fn serialize(&self, buffer: &mut impl SerializerBuffer) {
+        crate::common_serializers::serialize_pascal_string(buffer, self.table_name.as_str());
+        crate::common_serializers::serialize_i32(buffer, self.partitions.len() as i32);
+
+        for partition in &self.partitions {
+            crate::common_serializers::serialize_pascal_string(
+                buffer,
+                partition.partition_key.as_str(),
+            );
+
+            match partition.partition_expiration {
+                Some(expiration_time) => {
+                    crate::common_serializers::serialize_bool(buffer, true);
+                    crate::common_serializers::serialize_date_time_opt(buffer, expiration_time);
+                }
+                None => {
+                    crate::common_serializers::serialize_bool(buffer, false);
+                }
+            }
+
+            crate::common_serializers::serialize_i32(buffer, partition.rows.len() as i32);
+
+            for (row_key, expiration_time) in &partition.rows {
+                crate::common_serializers::serialize_pascal_string(buffer, row_key.as_str());
+                crate::common_serializers::serialize_date_time_opt(buffer, *expiration_time);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::socket_reader::SocketReaderInMem;
+
+    use super::*;
+    use crate::MyNoSqlTcpContract;
+
+    async fn round_trip(contract: &MyNoSqlTcpContract) -> MyNoSqlTcpContract {
+        let payload = contract.serialize();
+        assert_eq!(payload.len(), contract.serialized_len());
+
+        let mut reader = SocketReaderInMem::new(payload);
+        MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &DecodeLimits::default())
+            .await
+            .unwrap()
+    }
+
+    fn last_read_time_batch(read_moment: Option<DateTimeAsMicroseconds>) -> MyNoSqlTcpContract {
+        let mut table = LastReadTimeBatchTcpContract::new("table".to_string());
+        table.get_partition_mut("pk1").partition_read = Some(read_moment);
+        table
+            .get_partition_mut("pk2")
+            .rows
+            .push(("rk".to_string(), read_moment));
+
+        MyNoSqlTcpContract::UpdateLastReadTimeBatch {
+            confirmation_id: 5,
+            tables: vec![
+                table,
+                LastReadTimeBatchTcpContract::new("empty".to_string()),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn last_read_time_batch_round_trip() {
+        for read_moment in [None, Some(DateTimeAsMicroseconds::new(123))] {
+            let contract = last_read_time_batch(read_moment);
+
+            // Packets without read moments stay of version 0
+            assert_eq!(contract.serialize()[1], read_moment.is_some() as u8);
+
+            match round_trip(&contract).await {
+                MyNoSqlTcpContract::UpdateLastReadTimeBatch {
+                    confirmation_id,
+                    tables,
+                } => {
+                    assert_eq!(confirmation_id, 5);
+                    assert_eq!(tables.len(), 2);
+                    assert_eq!(tables[0].partitions[0].partition_read, Some(read_moment));
+                    assert_eq!(tables[0].partitions[1].partition_read, None);
+                    assert_eq!(
+                        tables[0].partitions[1].rows,
+                        vec![("rk".to_string(), read_moment)]
+                    );
+                    assert!(tables[1].partitions.is_empty());
+                }
+                _ => panic!("UpdateLastReadTimeBatch is expected"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn expiration_time_batch_round_trip() {
+        let mut table = ExpirationTimeBatchTcpContract::new("table".to_string());
+        table.get_partition_mut("pk1").partition_expiration =
+            Some(Some(DateTimeAsMicroseconds::new(7)));
+        table.get_partition_mut("pk2").partition_expiration = Some(None);
+        table.get_partition_mut("pk3").rows = vec![
+            ("rk1".to_string(), None),
+            ("rk2".to_string(), Some(DateTimeAsMicroseconds::new(9))),
+        ];
+
+        let contract = MyNoSqlTcpContract::UpdateExpirationTimeBatch {
+            confirmation_id: 6,
+            tables: vec![table],
+        };
+
+        match round_trip(&contract).await {
+            MyNoSqlTcpContract::UpdateExpirationTimeBatch {
+                confirmation_id,
+                tables,
+            } => {
+                assert_eq!(confirmation_id, 6);
+                let partitions = &tables[0].partitions;
+                assert_eq!(
+                    partitions[0].partition_expiration,
+                    Some(Some(DateTimeAsMicroseconds::new(7)))
+                );
+                assert_eq!(partitions[1].partition_expiration, Some(None));
+                assert_eq!(partitions[2].partition_expiration, None);
+                assert_eq!(
+                    partitions[2].rows,
+                    vec![
+                        ("rk1".to_string(), None),
+                        ("rk2".to_string(), Some(DateTimeAsMicroseconds::new(9)))
+                    ]
+                );
+            }
+            _ => panic!("UpdateExpirationTimeBatch is expected"),
+        }
+    }
+}
@@ -0,0 +1,719 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use my_tcp_sockets::{
+    socket_reader::{ReadingTcpContractFail, SocketReader},
+    TcpSocketSerializer,
+};
+use rust_extensions::events_loop::EventsLoopTick;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+
+use crate::{MyNoSqlReaderTcpSerializer, MyNoSqlTcpContract};
+
+use super::{
+    MainNodeConnection, SyncToMainNodeEvent, SyncToMainNodeEventLoop, SyncToMainNodeQueues,
+};
+
+const DUPLEX_BUFFER_SIZE: usize = 1024 * 1024;
+const DELIVER_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy)]
+pub enum FakeConfirmation {
+    Confirm,
+    Delay(Duration),
+    DropConnection,
+    NeverConfirm,
+}
+
+// Main node on the other end of an in-memory socket. Both ends serialize with MyNoSqlReaderTcpSerializer,
+// so the sync packets go through the same framing and capabilities negotiation as in production.
+// Events of the node side are processed one by one with SyncToMainNodeEventLoop, the same way EventsLoop does
+pub struct FakeMainNode {
+    queues: Arc<SyncToMainNodeQueues>,
+    events: tokio::sync::mpsc::UnboundedSender<SyncToMainNodeEvent>,
+    received: Mutex<Vec<MyNoSqlTcpContract>>,
+    script: Mutex<HashMap<i64, FakeConfirmation>>,
+    default_confirmation: Mutex<FakeConfirmation>,
+    disconnect_requests: Mutex<usize>,
+    supports_sync_batches: AtomicBool,
+    supports_read_moments: AtomicBool,
+    supports_row_expirations: AtomicBool,
+    main_side: Mutex<Option<Arc<FakeMainSide>>>,
+    node_connection: Mutex<Option<Arc<FakeNodeConnection>>>,
+}
+
+impl FakeMainNode {
+    pub fn new(queues: Arc<SyncToMainNodeQueues>) -> Arc<Self> {
+        let (events, receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(events_loop(
+            SyncToMainNodeEventLoop::new(queues.clone()),
+            receiver,
+        ));
+
+        Arc::new(Self {
+            queues,
+            events,
+            received: Mutex::new(Vec::new()),
+            script: Mutex::new(HashMap::new()),
+            default_confirmation: Mutex::new(FakeConfirmation::Confirm),
+            disconnect_requests: Mutex::new(0),
+            supports_sync_batches: AtomicBool::new(false),
+            supports_read_moments: AtomicBool::new(false),
+            supports_row_expirations: AtomicBool::new(false),
+            main_side: Mutex::new(None),
+            node_connection: Mutex::new(None),
+        })
+    }
+
+    pub fn set_default_confirmation(&self, confirmation: FakeConfirmation) {
+        *self.default_confirmation.lock().unwrap() = confirmation;
+    }
+
+    pub fn script_confirmation(&self, confirmation_id: i64, confirmation: FakeConfirmation) {
+        self.script
+            .lock()
+            .unwrap()
+            .insert(confirmation_id, confirmation);
+    }
+
+    // Capabilities are advertised during the handshake, so they apply from the next connect
+    pub fn advertise_sync_batches(&self, supported: bool) {
+        self.supports_sync_batches
+            .store(supported, Ordering::SeqCst);
+    }
+
+    pub fn advertise_read_moments(&self, supported: bool) {
+        self.supports_read_moments
+            .store(supported, Ordering::SeqCst);
+    }
+
+    pub fn advertise_row_expirations(&self, supported: bool) {
+        self.supports_row_expirations
+            .store(supported, Ordering::SeqCst);
+    }
+
+    pub async fn connect(self: &Arc<Self>) {
+        self.disconnect().await;
+
+        let (node_stream, main_stream) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+        let (node_read, node_write) = tokio::io::split(node_stream);
+        let (main_read, main_write) = tokio::io::split(main_stream);
+
+        let main_side = Arc::new(FakeMainSide {
+            serializer: tokio::sync::Mutex::new(
+                MyNoSqlReaderTcpSerializer::new()
+                    .with_sync_batches(self.supports_sync_batches.load(Ordering::SeqCst))
+                    .with_read_moments(self.supports_read_moments.load(Ordering::SeqCst))
+                    .with_row_expirations(self.supports_row_expirations.load(Ordering::SeqCst)),
+            ),
+            writer: tokio::sync::Mutex::new(Some(main_write)),
+        });
+
+        *self.main_side.lock().unwrap() = Some(main_side.clone());
+        tokio::spawn(main_read_loop(
+            self.clone(),
+            main_side,
+            DuplexSocketReader::new(main_read),
+        ));
+
+        let connection = Arc::new(FakeNodeConnection {
+            fake: self.clone(),
+            serializer: tokio::sync::Mutex::new(MyNoSqlReaderTcpSerializer::new()),
+            writer: tokio::sync::Mutex::new(Some(node_write)),
+            connected: AtomicBool::new(true),
+            capabilities_received: AtomicBool::new(false),
+        });
+
+        connection
+            .send(MyNoSqlTcpContract::GreetingFromNode {
+                node_location: "fake".to_string(),
+                node_version: env!("CARGO_PKG_VERSION").to_string(),
+                compress: false,
+            })
+            .await;
+        connection.send(MyNoSqlTcpContract::Ping).await;
+
+        *self.node_connection.lock().unwrap() = Some(connection.clone());
+        tokio::spawn(node_read_loop(
+            connection.clone(),
+            DuplexSocketReader::new(node_read),
+        ));
+
+        self.send_event(SyncToMainNodeEvent::Connected(connection));
+    }
+
+    // Main node closes the socket. Node notices it the way it notices a dropped TCP connection.
+    // Returns once the node has reported it, so Disconnected never overtakes the next Connected
+    pub async fn disconnect(&self) {
+        let main_side = self.main_side.lock().unwrap().take();
+
+        if let Some(main_side) = main_side {
+            main_side.close().await;
+        }
+
+        let node_connection = self.node_connection.lock().unwrap().take();
+
+        if let Some(node_connection) = node_connection {
+            while node_connection.connected.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+    }
+
+    pub async fn confirm(&self, confirmation_id: i64) {
+        let main_side = self.main_side.lock().unwrap().clone();
+
+        if let Some(main_side) = main_side {
+            main_side
+                .send(MyNoSqlTcpContract::Confirmation { confirmation_id })
+                .await;
+        }
+    }
+
+    pub fn take_received(&self) -> Vec<MyNoSqlTcpContract> {
+        let mut received = self.received.lock().unwrap();
+        std::mem::take(&mut *received)
+    }
+
+    pub fn get_received_confirmation_ids(&self) -> Vec<i64> {
+        let received = self.received.lock().unwrap();
+        received.iter().filter_map(get_confirmation_id).collect()
+    }
+
+    pub fn get_disconnect_requests(&self) -> usize {
+        *self.disconnect_requests.lock().unwrap()
+    }
+
+    pub fn get_received_amount(&self) -> usize {
+        self.received.lock().unwrap().len()
+    }
+
+    pub async fn wait_for_received_amount(&self, amount: usize, timeout: Duration) -> bool {
+        let started = std::time::Instant::now();
+
+        while self.get_received_amount() < amount {
+            if started.elapsed() > timeout {
+                return false;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        true
+    }
+
+    // Node knows what main node supports once it has received the Capabilities packet
+    pub async fn wait_for_capabilities(&self, timeout: Duration) -> bool {
+        let started = std::time::Instant::now();
+
+        while !self.is_capabilities_received()
+            || self.queues.main_node_supports_batches().await
+                != self.supports_sync_batches.load(Ordering::SeqCst)
+            || self.queues.main_node_supports_read_moments().await
+                != self.supports_read_moments.load(Ordering::SeqCst)
+            || self.queues.main_node_supports_row_expirations().await
+                != self.supports_row_expirations.load(Ordering::SeqCst)
+        {
+            if started.elapsed() > timeout {
+                return false;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        true
+    }
+
+    fn is_capabilities_received(&self) -> bool {
+        match self.node_connection.lock().unwrap().as_ref() {
+            Some(connection) => connection.capabilities_received.load(Ordering::SeqCst),
+            None => false,
+        }
+    }
+
+    fn send_event(&self, event: SyncToMainNodeEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn get_confirmation_behavior(&self, confirmation_id: i64) -> FakeConfirmation {
+        if let Some(confirmation) = self.script.lock().unwrap().remove(&confirmation_id) {
+            return confirmation;
+        }
+
+        *self.default_confirmation.lock().unwrap()
+    }
+
+    async fn on_received(
+        self: &Arc<Self>,
+        main_side: &Arc<FakeMainSide>,
+        contract: MyNoSqlTcpContract,
+    ) {
+        let confirmation_id = get_confirmation_id(&contract);
+
+        self.received.lock().unwrap().push(contract);
+
+        let confirmation_id = match confirmation_id {
+            Some(confirmation_id) => confirmation_id,
+            None => return,
+        };
+
+        match self.get_confirmation_behavior(confirmation_id) {
+            FakeConfirmation::Confirm => {
+                main_side
+                    .send(MyNoSqlTcpContract::Confirmation { confirmation_id })
+                    .await;
+            }
+            FakeConfirmation::Delay(delay) => {
+                let main_side = main_side.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    main_side
+                        .send(MyNoSqlTcpContract::Confirmation { confirmation_id })
+                        .await;
+                });
+            }
+            FakeConfirmation::DropConnection => {
+                main_side.close().await;
+            }
+            FakeConfirmation::NeverConfirm => {}
+        }
+    }
+}
+
+// Queues notify their own EventsLoop about new updates, which is not started with the fake.
+// So the fake looks for updates to deliver on its own
+async fn events_loop(
+    event_loop: SyncToMainNodeEventLoop,
+    mut receiver: tokio::sync::mpsc::UnboundedReceiver<SyncToMainNodeEvent>,
+) {
+    let mut deliver_interval = tokio::time::interval(DELIVER_INTERVAL);
+
+    loop {
+        let event = tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => event,
+                None => return,
+            },
+            _ = deliver_interval.tick() => SyncToMainNodeEvent::PingToDeliver,
+        };
+
+        event_loop.tick(event).await;
+    }
+}
+
+struct FakeMainSide {
+    serializer: tokio::sync::Mutex<MyNoSqlReaderTcpSerializer>,
+    writer: tokio::sync::Mutex<Option<WriteHalf<DuplexStream>>>,
+}
+
+impl FakeMainSide {
+    async fn send(&self, contract: MyNoSqlTcpContract) {
+        let payload = self.serializer.lock().await.serialize(contract);
+        write_payload(&self.writer, payload.as_slice()).await;
+    }
+
+    async fn close(&self) {
+        close_writer(&self.writer).await;
+    }
+}
+
+async fn main_read_loop(
+    fake: Arc<FakeMainNode>,
+    main_side: Arc<FakeMainSide>,
+    mut reader: DuplexSocketReader,
+) {
+    while reader.wait_for_data().await {
+        let contract = {
+            let mut serializer = main_side.serializer.lock().await;
+
+            match serializer.deserialize(&mut reader).await {
+                Ok(contract) => {
+                    serializer.apply_packet(&contract);
+                    contract
+                }
+                Err(err) => {
+                    println!("Fake main node can not read packet. Err: {:?}", err);
+                    break;
+                }
+            }
+        };
+
+        match contract {
+            MyNoSqlTcpContract::Ping => main_side.send(MyNoSqlTcpContract::Pong).await,
+            MyNoSqlTcpContract::GreetingFromNode { .. } => {}
+            MyNoSqlTcpContract::Capabilities(_) => {}
+            contract => fake.on_received(&main_side, contract).await,
+        }
+    }
+
+    main_side.close().await;
+}
+
+struct FakeNodeConnection {
+    fake: Arc<FakeMainNode>,
+    serializer: tokio::sync::Mutex<MyNoSqlReaderTcpSerializer>,
+    writer: tokio::sync::Mutex<Option<WriteHalf<DuplexStream>>>,
+    connected: AtomicBool,
+    capabilities_received: AtomicBool,
+}
+
+impl FakeNodeConnection {
+    // Node side reports the disconnect once, no matter which side has closed the socket
+    async fn on_disconnected(self: &Arc<Self>) {
+        close_writer(&self.writer).await;
+
+        if self.connected.swap(false, Ordering::SeqCst) {
+            self.fake
+                .send_event(SyncToMainNodeEvent::Disconnected(self.clone()));
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MainNodeConnection for FakeNodeConnection {
+    async fn send(&self, contract: MyNoSqlTcpContract) {
+        let payload = self.serializer.lock().await.serialize(contract);
+        write_payload(&self.writer, payload.as_slice()).await;
+    }
+
+    async fn disconnect(&self) {
+        *self.fake.disconnect_requests.lock().unwrap() += 1;
+        close_writer(&self.writer).await;
+    }
+}
+
+async fn node_read_loop(connection: Arc<FakeNodeConnection>, mut reader: DuplexSocketReader) {
+    while reader.wait_for_data().await {
+        let (contract, capabilities) = {
+            let mut serializer = connection.serializer.lock().await;
+
+            match serializer.deserialize(&mut reader).await {
+                Ok(contract) => {
+                    serializer.apply_packet(&contract);
+                    let capabilities = (
+                        serializer.peer_supports_sync_batches(),
+                        serializer.peer_supports_read_moments(),
+                        serializer.peer_supports_row_expirations(),
+                    );
+                    (contract, capabilities)
+                }
+                Err(err) => {
+                    println!("Fake node can not read packet. Err: {:?}", err);
+                    break;
+                }
+            }
+        };
+
+        match contract {
+            MyNoSqlTcpContract::Confirmation { confirmation_id } => {
+                connection
+                    .fake
+                    .send_event(SyncToMainNodeEvent::Delivered(confirmation_id));
+            }
+            MyNoSqlTcpContract::Capabilities(_) => {
+                let (sync_batches, read_moments, row_expirations) = capabilities;
+                connection
+                    .fake
+                    .send_event(SyncToMainNodeEvent::ReadMomentsSupported(read_moments));
+                connection
+                    .fake
+                    .send_event(SyncToMainNodeEvent::RowExpirationsSupported(
+                        row_expirations,
+                    ));
+                connection
+                    .fake
+                    .send_event(SyncToMainNodeEvent::SyncBatchesSupported(sync_batches));
+                connection
+                    .capabilities_received
+                    .store(true, Ordering::SeqCst);
+                // Node answers with its own capabilities in front of the next frame
+                connection.send(MyNoSqlTcpContract::Ping).await;
+            }
+            _ => {}
+        }
+    }
+
+    connection.on_disconnected().await;
+}
+
+async fn write_payload(
+    writer: &tokio::sync::Mutex<Option<WriteHalf<DuplexStream>>>,
+    payload: &[u8],
+) {
+    let mut writer = writer.lock().await;
+
+    let result = match writer.as_mut() {
+        Some(writer) => writer.write_all(payload).await,
+        None => return,
+    };
+
+    if result.is_err() {
+        *writer = None;
+    }
+}
+
+async fn close_writer(writer: &tokio::sync::Mutex<Option<WriteHalf<DuplexStream>>>) {
+    if let Some(mut writer) = writer.lock().await.take() {
+        let _ = writer.shutdown().await;
+    }
+}
+
+// Reads the in-memory socket. wait_for_data lets read loops keep the serializer unlocked
+// while the socket is idle, so packets can be sent meanwhile
+struct DuplexSocketReader {
+    inner: ReadHalf<DuplexStream>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl DuplexSocketReader {
+    fn new(inner: ReadHalf<DuplexStream>) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    // Returns false if the other side has closed the socket
+    async fn wait_for_data(&mut self) -> bool {
+        if self.pos < self.buffer.len() {
+            return true;
+        }
+
+        self.fill_buffer().await.is_ok()
+    }
+
+    async fn fill_buffer(&mut self) -> Result<(), ReadingTcpContractFail> {
+        self.buffer.resize(DUPLEX_BUFFER_SIZE, 0);
+        self.pos = 0;
+
+        let read = self.inner.read(self.buffer.as_mut_slice()).await;
+
+        match read {
+            Ok(read) if read > 0 => {
+                self.buffer.truncate(read);
+                Ok(())
+            }
+            _ => {
+                self.buffer.clear();
+                Err(ReadingTcpContractFail::SocketDisconnected)
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SocketReader for DuplexSocketReader {
+    async fn read_byte(&mut self) -> Result<u8, ReadingTcpContractFail> {
+        let mut result = [0u8; 1];
+        self.read_buf(&mut result).await?;
+        Ok(result[0])
+    }
+
+    async fn read_bool(&mut self) -> Result<bool, ReadingTcpContractFail> {
+        let result = self.read_byte().await?;
+        Ok(result > 0)
+    }
+
+    async fn read_i32(&mut self) -> Result<i32, ReadingTcpContractFail> {
+        let mut result = [0u8; 4];
+        self.read_buf(&mut result).await?;
+        Ok(i32::from_le_bytes(result))
+    }
+
+    async fn read_i64(&mut self) -> Result<i64, ReadingTcpContractFail> {
+        let mut result = [0u8; 8];
+        self.read_buf(&mut result).await?;
+        Ok(i64::from_le_bytes(result))
+    }
+
+    async fn read_u32(&mut self) -> Result<u32, ReadingTcpContractFail> {
+        let mut result = [0u8; 4];
+        self.read_buf(&mut result).await?;
+        Ok(u32::from_le_bytes(result))
+    }
+
+    async fn read_u64(&mut self) -> Result<u64, ReadingTcpContractFail> {
+        let mut result = [0u8; 8];
+        self.read_buf(&mut result).await?;
+        Ok(u64::from_le_bytes(result))
+    }
+
+    async fn read_buf(&mut self, buf: &mut [u8]) -> Result<(), ReadingTcpContractFail> {
+        let mut filled = 0;
+
+        while filled < buf.len() {
+            if self.pos == self.buffer.len() {
+                self.fill_buffer().await?;
+            }
+
+            let amount = (buf.len() - filled).min(self.buffer.len() - self.pos);
+            buf[filled..filled + amount].copy_from_slice(&self.buffer[self.pos..self.pos + amount]);
+            filled += amount;
+            self.pos += amount;
+        }
+
+        Ok(())
+    }
+}
+
+fn get_confirmation_id(contract: &MyNoSqlTcpContract) -> Option<i64> {
+    match contract {
+        MyNoSqlTcpContract::UpdatePartitionsLastReadTime {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdateRowsLastReadTime {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdatePartitionsReadMoments {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdateRowsReadMoments {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdatePartitionsExpirationTime {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdateRowsExpirationTime {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdateRowsExpirationTimePerRow {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdateLastReadTimeBatch {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        MyNoSqlTcpContract::UpdateExpirationTimeBatch {
+            confirmation_id, ..
+        } => Some(*confirmation_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn connect(
+        sync_batches: bool,
+        read_moments: bool,
+    ) -> (Arc<SyncToMainNodeQueues>, Arc<FakeMainNode>) {
+        let queues = Arc::new(SyncToMainNodeQueues::new());
+        let fake = FakeMainNode::new(queues.clone());
+        fake.advertise_sync_batches(sync_batches);
+        fake.advertise_read_moments(read_moments);
+
+        fake.connect().await;
+        assert!(fake.wait_for_capabilities(TIMEOUT).await);
+
+        (queues, fake)
+    }
+
+    async fn update_rows_last_read_time(queues: &SyncToMainNodeQueues, table_name: &str) {
+        let row_keys = ["rk".to_string()];
+        queues
+            .update_rows_last_read_time(table_name, "pk", row_keys.iter())
+            .await;
+    }
+
+    async fn wait_for_in_flight(queues: &SyncToMainNodeQueues, amount: usize) -> bool {
+        let started = std::time::Instant::now();
+
+        while queues.get_in_flight_amount().await != amount {
+            if started.elapsed() > TIMEOUT {
+                return false;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        true
+    }
+
+    #[tokio::test]
+    async fn main_node_which_advertises_batches_and_read_moments_gets_them() {
+        let (queues, fake) = connect(true, true).await;
+
+        update_rows_last_read_time(&queues, "table-1").await;
+        update_rows_last_read_time(&queues, "table-2").await;
+        assert!(fake.wait_for_received_amount(1, TIMEOUT).await);
+        assert!(wait_for_in_flight(&queues, 0).await);
+
+        let received = fake.take_received();
+        assert_eq!(received.len(), 1);
+
+        match &received[0] {
+            MyNoSqlTcpContract::UpdateLastReadTimeBatch { tables, .. } => {
+                assert_eq!(tables.len(), 2);
+
+                for table in tables {
+                    let rows = &table.partitions[0].rows;
+                    assert_eq!(rows[0].0, "rk");
+                    assert!(rows[0].1.is_some());
+                }
+            }
+            other => panic!(
+                "UpdateLastReadTimeBatch is expected. Got {}",
+                other.get_packet_name()
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn baseline_main_node_gets_per_table_packets_without_read_moments() {
+        let (queues, fake) = connect(false, false).await;
+
+        update_rows_last_read_time(&queues, "table-1").await;
+        update_rows_last_read_time(&queues, "table-2").await;
+        assert!(fake.wait_for_received_amount(2, TIMEOUT).await);
+        assert!(wait_for_in_flight(&queues, 0).await);
+
+        for contract in fake.take_received() {
+            match contract {
+                MyNoSqlTcpContract::UpdateRowsLastReadTime { row_keys, .. } => {
+                    assert_eq!(row_keys, vec!["rk".to_string()]);
+                }
+                other => panic!(
+                    "UpdateRowsLastReadTime is expected. Got {}",
+                    other.get_packet_name()
+                ),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn event_is_redelivered_after_connection_is_dropped() {
+        let (queues, fake) = connect(true, true).await;
+        fake.set_default_confirmation(FakeConfirmation::DropConnection);
+
+        update_rows_last_read_time(&queues, "table").await;
+        assert!(fake.wait_for_received_amount(1, TIMEOUT).await);
+
+        fake.set_default_confirmation(FakeConfirmation::Confirm);
+        fake.connect().await;
+
+        assert!(fake.wait_for_received_amount(2, TIMEOUT).await);
+        assert!(wait_for_in_flight(&queues, 0).await);
+
+        let received = fake.take_received();
+        assert_eq!(received.len(), 2);
+
+        // Event can be sent again before the capabilities of the new connection are received
+        for contract in received {
+            assert!(matches!(
+                contract,
+                MyNoSqlTcpContract::UpdateRowsReadMoments { .. }
+                    | MyNoSqlTcpContract::UpdateRowsLastReadTime { .. }
+            ));
+        }
+    }
+}
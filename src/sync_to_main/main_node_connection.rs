@@ -0,0 +1,20 @@
+use crate::MyNoSqlTcpContract;
+
+use super::DataReaderTcpConnection;
+
+#[async_trait::async_trait]
+pub trait MainNodeConnection: Send + Sync + 'static {
+    async fn send(&self, contract: MyNoSqlTcpContract);
+    async fn disconnect(&self);
+}
+
+#[async_trait::async_trait]
+impl MainNodeConnection for DataReaderTcpConnection {
+    async fn send(&self, contract: MyNoSqlTcpContract) {
+        DataReaderTcpConnection::send(self, contract).await;
+    }
+
+    async fn disconnect(&self) {
+        DataReaderTcpConnection::disconnect(self).await;
+    }
+}
@@ -1,7 +1,21 @@
+#[cfg(any(test, feature = "test-support"))]
+mod fake_main_node;
+mod main_node_connection;
+mod sync_queue_limits;
 mod sync_queues;
+mod sync_queues_journal;
+mod sync_queues_scheduler;
+mod sync_queues_snapshot;
 mod sync_to_main_node_event;
 mod sync_to_main_node_handler;
+#[cfg(any(test, feature = "test-support"))]
+pub use fake_main_node::*;
+pub use main_node_connection::*;
+pub use sync_queue_limits::*;
 pub use sync_queues::*;
+pub use sync_queues_journal::*;
+pub use sync_queues_scheduler::*;
+pub use sync_queues_snapshot::*;
 mod update_entity_statistics_data;
 mod update_partition_expiration_time_queue;
 mod update_partitions_last_read_time_queue;
@@ -0,0 +1,96 @@
+// Rough heap footprint of the queued key: String header and hash map slot
+pub(crate) const QUEUE_KEY_OVERHEAD: usize = 40;
+// Rough footprint of the queued event without its keys
+pub(crate) const QUEUE_EVENT_OVERHEAD: usize = 96;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncQueueOverflowPolicy {
+    DropOldest,
+    // Row updates of the oldest partition are replaced with the partition update.
+    // Rows expiration can not be collapsed, so it falls back to DropOldest
+    CollapseToPartitions,
+    // Updates are rejected until the queue is drained
+    RejectNew,
+}
+
+impl SyncQueueOverflowPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncQueueOverflowPolicy::DropOldest => "drop_oldest",
+            SyncQueueOverflowPolicy::CollapseToPartitions => "collapse_to_partitions",
+            SyncQueueOverflowPolicy::RejectNew => "reject_new",
+        }
+    }
+}
+
+// None - no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncQueueLimits {
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl SyncQueueLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn is_reached(&self, usage: &SyncQueueUsage) -> bool {
+        self.max_entries
+            .map(|max_entries| usage.entries >= max_entries)
+            .unwrap_or(false)
+            || self
+                .max_bytes
+                .map(|max_bytes| usage.bytes >= max_bytes)
+                .unwrap_or(false)
+    }
+
+    pub fn is_exceeded(&self, usage: &SyncQueueUsage) -> bool {
+        self.max_entries
+            .map(|max_entries| usage.entries > max_entries)
+            .unwrap_or(false)
+            || self
+                .max_bytes
+                .map(|max_bytes| usage.bytes > max_bytes)
+                .unwrap_or(false)
+    }
+}
+
+// Entries are partition keys or row keys waiting to be delivered. Bytes are approximate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncQueueUsage {
+    pub entries: usize,
+    pub bytes: usize,
+    // Row keys collapsed into partition updates are counted as dropped too
+    pub dropped_entries: u64,
+}
+
+impl SyncQueueUsage {
+    pub fn new(entries: usize, bytes: usize) -> Self {
+        Self {
+            entries,
+            bytes,
+            dropped_entries: 0,
+        }
+    }
+
+    pub fn append(&mut self, other: &SyncQueueUsage) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.dropped_entries += other.dropped_entries;
+    }
+}
+
+pub(crate) fn get_key_size(key: &str) -> usize {
+    key.len() + QUEUE_KEY_OVERHEAD
+}
@@ -1,10 +1,38 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use rust_extensions::{date_time::DateTimeAsMicroseconds, events_loop::EventsLoop};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
+
+use crate::metrics::ProtocolMetrics;
 
 use super::{sync_to_main_node_event::SyncToMainNodeEvent, *};
 
+pub const PARTITIONS_EXPIRATION_QUEUE_NAME: &str = "partitions_expiration_time";
+pub const PARTITIONS_LAST_READ_TIME_QUEUE_NAME: &str = "partitions_last_read_time";
+pub const ROWS_EXPIRATION_QUEUE_NAME: &str = "rows_expiration_time";
+pub const ROWS_LAST_READ_TIME_QUEUE_NAME: &str = "rows_last_read_time";
+
+// Names of the events which carry updates of several queues
+pub const LAST_READ_TIME_BATCH_NAME: &str = "last_read_time_batch";
+pub const EXPIRATION_TIME_BATCH_NAME: &str = "expiration_time_batch";
+
+// Order in which queues are shrunk when limits are exceeded
+const QUEUE_NAMES: [&str; 4] = [
+    ROWS_LAST_READ_TIME_QUEUE_NAME,
+    ROWS_EXPIRATION_QUEUE_NAME,
+    PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+    PARTITIONS_EXPIRATION_QUEUE_NAME,
+];
+
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 1;
+pub const DEFAULT_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_TIMEOUTS_BEFORE_DISCONNECT: usize = 3;
+pub const DEFAULT_MAX_SYNC_BATCH_ENTRIES: usize = 10_000;
+
 #[derive(Debug, Clone)]
 pub enum DeliverToMainNodeEvent {
     UpdatePartitionsExpiration {
@@ -23,6 +51,17 @@ pub enum DeliverToMainNodeEvent {
         event: UpdateRowsLastReadTimeEvent,
         confirmation_id: i64,
     },
+    // Sent only when main node supports sync batches
+    LastReadTimeBatch {
+        partitions: Vec<UpdatePartitionsLastReadTimeEvent>,
+        rows: Vec<UpdateRowsLastReadTimeEvent>,
+        confirmation_id: i64,
+    },
+    ExpirationTimeBatch {
+        partitions: Vec<UpdatePartitionExpirationEvent>,
+        rows: Vec<UpdateRowsExpirationTimeEvent>,
+        confirmation_id: i64,
+    },
 }
 
 impl DeliverToMainNodeEvent {
@@ -44,8 +83,198 @@ impl DeliverToMainNodeEvent {
                 event: _,
                 confirmation_id,
             } => *confirmation_id,
+            DeliverToMainNodeEvent::LastReadTimeBatch {
+                confirmation_id, ..
+            } => *confirmation_id,
+            DeliverToMainNodeEvent::ExpirationTimeBatch {
+                confirmation_id, ..
+            } => *confirmation_id,
         }
     }
+
+    pub fn get_queue_name(&self) -> &'static str {
+        match self {
+            DeliverToMainNodeEvent::UpdatePartitionsExpiration { .. } => {
+                PARTITIONS_EXPIRATION_QUEUE_NAME
+            }
+            DeliverToMainNodeEvent::UpdatePartitionsLastReadTime { .. } => {
+                PARTITIONS_LAST_READ_TIME_QUEUE_NAME
+            }
+            DeliverToMainNodeEvent::UpdateRowsExpirationTime { .. } => ROWS_EXPIRATION_QUEUE_NAME,
+            DeliverToMainNodeEvent::UpdateRowsLastReadTime { .. } => ROWS_LAST_READ_TIME_QUEUE_NAME,
+            DeliverToMainNodeEvent::LastReadTimeBatch { .. } => LAST_READ_TIME_BATCH_NAME,
+            DeliverToMainNodeEvent::ExpirationTimeBatch { .. } => EXPIRATION_TIME_BATCH_NAME,
+        }
+    }
+
+    pub fn get_table_name(&self) -> &str {
+        match self {
+            DeliverToMainNodeEvent::UpdatePartitionsExpiration { event, .. } => &event.table_name,
+            DeliverToMainNodeEvent::UpdatePartitionsLastReadTime { event, .. } => &event.table_name,
+            DeliverToMainNodeEvent::UpdateRowsExpirationTime { event, .. } => &event.table_name,
+            DeliverToMainNodeEvent::UpdateRowsLastReadTime { event, .. } => &event.table_name,
+            // Batch is reported by its first table
+            DeliverToMainNodeEvent::LastReadTimeBatch {
+                partitions, rows, ..
+            } => partitions
+                .first()
+                .map(|itm| itm.table_name.as_str())
+                .or_else(|| rows.first().map(|itm| itm.table_name.as_str()))
+                .unwrap_or_default(),
+            DeliverToMainNodeEvent::ExpirationTimeBatch {
+                partitions, rows, ..
+            } => partitions
+                .first()
+                .map(|itm| itm.table_name.as_str())
+                .or_else(|| rows.first().map(|itm| itm.table_name.as_str()))
+                .unwrap_or_default(),
+        }
+    }
+
+    // Partition and row keys the event carries
+    pub fn get_entries_amount(&self) -> usize {
+        match self {
+            DeliverToMainNodeEvent::UpdatePartitionsExpiration { event, .. } => {
+                event.get_entries_amount()
+            }
+            DeliverToMainNodeEvent::UpdatePartitionsLastReadTime { event, .. } => {
+                event.get_entries_amount()
+            }
+            DeliverToMainNodeEvent::UpdateRowsExpirationTime { event, .. } => {
+                event.get_entries_amount()
+            }
+            DeliverToMainNodeEvent::UpdateRowsLastReadTime { event, .. } => {
+                event.get_entries_amount()
+            }
+            DeliverToMainNodeEvent::LastReadTimeBatch {
+                partitions, rows, ..
+            } => {
+                partitions
+                    .iter()
+                    .map(|itm| itm.get_entries_amount())
+                    .sum::<usize>()
+                    + rows
+                        .iter()
+                        .map(|itm| itm.get_entries_amount())
+                        .sum::<usize>()
+            }
+            DeliverToMainNodeEvent::ExpirationTimeBatch {
+                partitions, rows, ..
+            } => {
+                partitions
+                    .iter()
+                    .map(|itm| itm.get_entries_amount())
+                    .sum::<usize>()
+                    + rows
+                        .iter()
+                        .map(|itm| itm.get_entries_amount())
+                        .sum::<usize>()
+            }
+        }
+    }
+
+    // Whether the event carries an update of the queue for the table
+    pub fn contains(&self, queue_name: &str, table_name: &str) -> bool {
+        match self {
+            DeliverToMainNodeEvent::LastReadTimeBatch {
+                partitions, rows, ..
+            } => match queue_name {
+                PARTITIONS_LAST_READ_TIME_QUEUE_NAME => {
+                    partitions.iter().any(|itm| itm.table_name == table_name)
+                }
+                ROWS_LAST_READ_TIME_QUEUE_NAME => {
+                    rows.iter().any(|itm| itm.table_name == table_name)
+                }
+                _ => false,
+            },
+            DeliverToMainNodeEvent::ExpirationTimeBatch {
+                partitions, rows, ..
+            } => match queue_name {
+                PARTITIONS_EXPIRATION_QUEUE_NAME => {
+                    partitions.iter().any(|itm| itm.table_name == table_name)
+                }
+                ROWS_EXPIRATION_QUEUE_NAME => rows.iter().any(|itm| itm.table_name == table_name),
+                _ => false,
+            },
+            _ => self.get_queue_name() == queue_name && self.get_table_name() == table_name,
+        }
+    }
+
+    fn get_journal_records(&self) -> Vec<SyncJournalRecord> {
+        match self {
+            DeliverToMainNodeEvent::UpdatePartitionsExpiration { event, .. } => {
+                vec![SyncJournalRecord::PartitionsExpiration(event.clone())]
+            }
+            DeliverToMainNodeEvent::UpdatePartitionsLastReadTime { event, .. } => {
+                vec![SyncJournalRecord::PartitionsLastReadTime(event.clone())]
+            }
+            DeliverToMainNodeEvent::UpdateRowsExpirationTime { event, .. } => {
+                vec![SyncJournalRecord::RowsExpirationTime(event.clone())]
+            }
+            DeliverToMainNodeEvent::UpdateRowsLastReadTime { event, .. } => {
+                vec![SyncJournalRecord::RowsLastReadTime(event.clone())]
+            }
+            DeliverToMainNodeEvent::LastReadTimeBatch {
+                partitions, rows, ..
+            } => partitions
+                .iter()
+                .cloned()
+                .map(SyncJournalRecord::PartitionsLastReadTime)
+                .chain(
+                    rows.iter()
+                        .cloned()
+                        .map(SyncJournalRecord::RowsLastReadTime),
+                )
+                .collect(),
+            DeliverToMainNodeEvent::ExpirationTimeBatch {
+                partitions, rows, ..
+            } => partitions
+                .iter()
+                .cloned()
+                .map(SyncJournalRecord::PartitionsExpiration)
+                .chain(
+                    rows.iter()
+                        .cloned()
+                        .map(SyncJournalRecord::RowsExpirationTime),
+                )
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimedOutDelivery {
+    pub confirmation_id: i64,
+    pub queue_name: &'static str,
+    pub table_name: String,
+    pub waited: Duration,
+}
+
+pub struct DeliveryTimeouts {
+    pub timed_out: Vec<TimedOutDelivery>,
+    // Main node does not confirm events several times in a row. Connection is treated as stalled
+    pub connection_to_drop: Option<Arc<dyn MainNodeConnection>>,
+}
+
+struct InFlightEvent {
+    event: DeliverToMainNodeEvent,
+    started: Instant,
+    sent_at: DateTimeAsMicroseconds,
+    // Timeout is counted from the last time the event was sent
+    last_sent: Instant,
+    resend_pending: bool,
+}
+
+// Only one event of the queue per table is in flight, so the events of the table
+// are applied on the main node in the order they were queued even if they are confirmed out of order
+fn is_in_flight(
+    on_delivery: &BTreeMap<i64, InFlightEvent>,
+    queue_name: &str,
+    table_name: &str,
+) -> bool {
+    on_delivery
+        .values()
+        .any(|itm| itm.event.contains(queue_name, table_name))
 }
 
 pub struct SyncQueuesInner {
@@ -55,8 +284,27 @@ pub struct SyncQueuesInner {
 
     update_rows_expiration_time_queue: UpdateRowsExpirationTimeQueue,
     update_rows_last_read_time_queue: UpdateRowsLastReadTimeQueue,
-    on_delivery: Option<DeliverToMainNodeEvent>,
-    connection: Option<Arc<DataReaderTcpConnection>>,
+    on_delivery: BTreeMap<i64, InFlightEvent>,
+    // Timed out events which are sent again under the same confirmation id
+    to_resend: VecDeque<i64>,
+    last_confirmed_id: Option<i64>,
+    timeouts_in_row: usize,
+    connection: Option<Arc<dyn MainNodeConnection>>,
+    journal: Option<SyncQueuesJournal>,
+    queue_limits: HashMap<&'static str, SyncQueueLimits>,
+    global_limits: SyncQueueLimits,
+    overflow_policy: SyncQueueOverflowPolicy,
+    dropped_entries: HashMap<&'static str, u64>,
+    scheduler: Box<dyn SyncQueuesScheduler>,
+    // Main node advertised sync batches for the current connection
+    main_node_supports_batches: bool,
+    // Main node advertised read moments for the current connection
+    main_node_supports_read_moments: bool,
+    // Main node advertised expiration time per row for the current connection
+    main_node_supports_row_expirations: bool,
+    max_sync_batch_entries: usize,
+    // Flush timer has no deadline and waits for the next queued event
+    flush_timer_waiting: bool,
 }
 
 impl SyncQueuesInner {
@@ -67,8 +315,22 @@ impl SyncQueuesInner {
             update_rows_expiration_time_queue: UpdateRowsExpirationTimeQueue::new(),
             update_rows_last_read_time_queue: UpdateRowsLastReadTimeQueue::new(),
             update_partitions_last_read_time_queue: UpdatePartitionsLastReadTimeQueue::new(),
-            on_delivery: None,
+            on_delivery: BTreeMap::new(),
+            to_resend: VecDeque::new(),
+            last_confirmed_id: None,
+            timeouts_in_row: 0,
             connection: None,
+            journal: None,
+            queue_limits: HashMap::new(),
+            global_limits: SyncQueueLimits::new(),
+            overflow_policy: SyncQueueOverflowPolicy::DropOldest,
+            dropped_entries: HashMap::new(),
+            scheduler: Box::new(StrictPriorityScheduler::new()),
+            main_node_supports_batches: false,
+            main_node_supports_read_moments: false,
+            main_node_supports_row_expirations: false,
+            max_sync_batch_entries: DEFAULT_MAX_SYNC_BATCH_ENTRIES,
+            flush_timer_waiting: false,
         }
     }
 
@@ -77,21 +339,837 @@ impl SyncQueuesInner {
         self.confirmation_id
     }
 
-    fn confirm_delivery(&mut self, delivery_id: i64) {
-        let on_delivery = self.on_delivery.take();
+    fn confirm_delivery(&mut self, delivery_id: i64, metrics: Option<&Arc<dyn ProtocolMetrics>>) {
+        match self.on_delivery.remove(&delivery_id) {
+            Some(in_flight) => {
+                self.timeouts_in_row = 0;
+                self.last_confirmed_id = Some(delivery_id);
+
+                if let Some(metrics) = metrics {
+                    metrics.sync_confirmed(in_flight.started.elapsed());
+                }
+
+                self.on_journal_confirmed();
+            }
+            None => {
+                // Event which is sent again is confirmed twice if the first packet arrives as well
+                if delivery_id > self.confirmation_id {
+                    println!(
+                        "Somehow we got confirmation for delivery with id {}, but there is no such delivery in progress",
+                        delivery_id
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn get_in_flight_amount(&self) -> usize {
+        self.on_delivery.len()
+    }
+
+    fn has_pending_events(&self) -> bool {
+        !self.on_delivery.is_empty()
+            || self
+                .update_partition_expiration_time_update
+                .get_events_amount()
+                > 0
+            || self
+                .update_partitions_last_read_time_queue
+                .get_events_amount()
+                > 0
+            || self.update_rows_expiration_time_queue.get_events_amount() > 0
+            || self.update_rows_last_read_time_queue.get_events_amount() > 0
+    }
+
+    // Queue is updated before the record is written, so compaction on overflow already includes the record.
+    // Overflowed journal gets nothing until confirmations free space for the pending updates
+    fn journal_append(&mut self, record: impl FnOnce() -> SyncJournalRecord) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
+
+        if journal.is_overflowed() {
+            return;
+        }
+
+        if !journal.append(&record()) {
+            self.compact_journal();
+        }
+    }
+
+    fn on_journal_confirmed(&mut self) {
+        if !self.has_pending_events() {
+            if let Some(journal) = self.journal.as_mut() {
+                journal.clear();
+            }
+
+            return;
+        }
+
+        if let Some(journal) = self.journal.as_ref() {
+            if journal.should_compact() {
+                self.compact_journal();
+            }
+        }
+    }
+
+    fn compact_journal(&mut self) {
+        let mut records: Vec<SyncJournalRecord> = self
+            .on_delivery
+            .values()
+            .flat_map(|itm| itm.event.get_journal_records())
+            .collect();
+
+        records.extend(
+            self.update_partition_expiration_time_update
+                .get_events()
+                .cloned()
+                .map(SyncJournalRecord::PartitionsExpiration),
+        );
+        records.extend(
+            self.update_partitions_last_read_time_queue
+                .get_events()
+                .cloned()
+                .map(SyncJournalRecord::PartitionsLastReadTime),
+        );
+        records.extend(
+            self.update_rows_expiration_time_queue
+                .get_events()
+                .cloned()
+                .map(SyncJournalRecord::RowsExpirationTime),
+        );
+        records.extend(
+            self.update_rows_last_read_time_queue
+                .get_events()
+                .cloned()
+                .map(SyncJournalRecord::RowsLastReadTime),
+        );
+
+        if let Some(journal) = self.journal.as_mut() {
+            journal.compact(records.iter());
+        }
+    }
+
+    fn apply_journal_record(&mut self, record: SyncJournalRecord) {
+        match record {
+            SyncJournalRecord::PartitionsExpiration(event) => {
+                for (partition_key, expiration_time) in event.partitions {
+                    self.update_partition_expiration_time_update.add(
+                        &event.table_name,
+                        &partition_key,
+                        expiration_time,
+                    );
+                }
+            }
+            SyncJournalRecord::PartitionsLastReadTime(event) => {
+                for (partition_key, read_moment) in &event.partitions {
+                    self.update_partitions_last_read_time_queue.add_partition(
+                        &event.table_name,
+                        partition_key,
+                        *read_moment,
+                    );
+                }
+            }
+            SyncJournalRecord::RowsExpirationTime(event) => {
+                for (row_key, expiration_time) in &event.row_keys {
+                    self.update_rows_expiration_time_queue.add(
+                        &event.table_name,
+                        &event.partition_key,
+                        std::iter::once(row_key.as_str()),
+                        *expiration_time,
+                    );
+                }
+            }
+            SyncJournalRecord::RowsLastReadTime(event) => {
+                for (row_key, read_moment) in &event.row_keys {
+                    self.update_rows_last_read_time_queue.add(
+                        &event.table_name,
+                        &event.partition_key,
+                        std::iter::once(row_key.as_str()),
+                        *read_moment,
+                    );
+                }
+            }
+        }
+    }
+
+    fn get_queue_usage(&self, queue_name: &'static str) -> SyncQueueUsage {
+        let mut result = match queue_name {
+            PARTITIONS_EXPIRATION_QUEUE_NAME => {
+                self.update_partition_expiration_time_update.get_usage()
+            }
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME => {
+                self.update_partitions_last_read_time_queue.get_usage()
+            }
+            ROWS_EXPIRATION_QUEUE_NAME => self.update_rows_expiration_time_queue.get_usage(),
+            _ => self.update_rows_last_read_time_queue.get_usage(),
+        };
+
+        result.dropped_entries = self
+            .dropped_entries
+            .get(queue_name)
+            .copied()
+            .unwrap_or_default();
+
+        result
+    }
+
+    fn get_oldest_enqueued(&self, queue_name: &'static str) -> Option<Instant> {
+        match queue_name {
+            PARTITIONS_EXPIRATION_QUEUE_NAME => self
+                .update_partition_expiration_time_update
+                .get_enqueued_if(|_| true),
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME => self
+                .update_partitions_last_read_time_queue
+                .get_enqueued_if(|_| true),
+            ROWS_EXPIRATION_QUEUE_NAME => self
+                .update_rows_expiration_time_queue
+                .get_enqueued_if(|_| true),
+            _ => self
+                .update_rows_last_read_time_queue
+                .get_enqueued_if(|_| true),
+        }
+    }
+
+    fn get_snapshot(&self) -> SyncQueuesSnapshot {
+        let queues = SYNC_QUEUES_PRIORITY
+            .into_iter()
+            .map(|queue_name| SyncQueueSnapshot {
+                queue_name,
+                usage: self.get_queue_usage(queue_name),
+                oldest_pending_age: self
+                    .get_oldest_enqueued(queue_name)
+                    .map(|enqueued| enqueued.elapsed()),
+                tables: self.get_tables_snapshot(queue_name),
+            })
+            .collect();
+
+        SyncQueuesSnapshot {
+            queues,
+            in_flight: self
+                .on_delivery
+                .values()
+                .map(|itm| InFlightEventSnapshot {
+                    confirmation_id: itm.event.get_confirmation_id(),
+                    queue_name: itm.event.get_queue_name(),
+                    table_name: itm.event.get_table_name().to_string(),
+                    entries: itm.event.get_entries_amount(),
+                    sent_at: itm.sent_at,
+                    waited: itm.started.elapsed(),
+                })
+                .collect(),
+            last_confirmed_id: self.last_confirmed_id,
+            connected: self.connection.is_some(),
+            main_node_supports_batches: self.main_node_supports_batches,
+            main_node_supports_read_moments: self.main_node_supports_read_moments,
+            main_node_supports_row_expirations: self.main_node_supports_row_expirations,
+            timeouts_in_row: self.timeouts_in_row,
+        }
+    }
+
+    fn get_tables_snapshot(&self, queue_name: &'static str) -> Vec<SyncTableSnapshot> {
+        match queue_name {
+            PARTITIONS_EXPIRATION_QUEUE_NAME => self
+                .update_partition_expiration_time_update
+                .get_tables_snapshot(),
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME => self
+                .update_partitions_last_read_time_queue
+                .get_tables_snapshot(),
+            ROWS_EXPIRATION_QUEUE_NAME => {
+                self.update_rows_expiration_time_queue.get_tables_snapshot()
+            }
+            _ => self.update_rows_last_read_time_queue.get_tables_snapshot(),
+        }
+    }
+
+    fn get_total_usage(&self) -> SyncQueueUsage {
+        let mut result = SyncQueueUsage::default();
+
+        for queue_name in QUEUE_NAMES {
+            result.append(&self.get_queue_usage(queue_name));
+        }
+
+        result
+    }
+
+    fn get_queue_limits(&self, queue_name: &'static str) -> SyncQueueLimits {
+        self.queue_limits
+            .get(queue_name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn on_entries_dropped(
+        &mut self,
+        queue_name: &'static str,
+        amount: usize,
+        metrics: Option<&Arc<dyn ProtocolMetrics>>,
+    ) {
+        if amount == 0 {
+            return;
+        }
+
+        *self.dropped_entries.entry(queue_name).or_default() += amount as u64;
+
+        if let Some(metrics) = metrics {
+            metrics.sync_entries_dropped(queue_name, amount);
+        }
+    }
+
+    // With RejectNew policy update is rejected as soon as the limit is reached
+    fn can_accept(
+        &mut self,
+        queue_name: &'static str,
+        entries: usize,
+        metrics: Option<&Arc<dyn ProtocolMetrics>>,
+    ) -> bool {
+        if self.overflow_policy != SyncQueueOverflowPolicy::RejectNew {
+            return true;
+        }
+
+        if self
+            .get_queue_limits(queue_name)
+            .is_reached(&self.get_queue_usage(queue_name))
+            || self.global_limits.is_reached(&self.get_total_usage())
+        {
+            self.on_entries_dropped(queue_name, entries, metrics);
+            return false;
+        }
+
+        true
+    }
+
+    fn enforce_limits(&mut self, metrics: Option<&Arc<dyn ProtocolMetrics>>) {
+        if self.overflow_policy == SyncQueueOverflowPolicy::RejectNew {
+            return;
+        }
+
+        let mut shrunk = false;
+
+        // Row queues go first, since collapsing them adds updates to partition queues
+        for queue_name in QUEUE_NAMES {
+            while self
+                .get_queue_limits(queue_name)
+                .is_exceeded(&self.get_queue_usage(queue_name))
+            {
+                if !self.shrink_queue(queue_name, metrics) {
+                    break;
+                }
+
+                shrunk = true;
+            }
+        }
+
+        while self.global_limits.is_exceeded(&self.get_total_usage()) {
+            let queue_name = QUEUE_NAMES
+                .into_iter()
+                .max_by_key(|queue_name| self.get_queue_usage(queue_name).bytes)
+                .unwrap();
+
+            if !self.shrink_queue(queue_name, metrics) {
+                break;
+            }
+
+            shrunk = true;
+        }
+
+        if !shrunk {
+            return;
+        }
+
+        // Otherwise dropped updates come back from the journal after restart
+        if let Some(journal) = self.journal.as_mut() {
+            journal.on_updates_dropped();
+
+            if journal.should_compact() {
+                self.compact_journal();
+            }
+        }
+    }
+
+    fn shrink_queue(
+        &mut self,
+        queue_name: &'static str,
+        metrics: Option<&Arc<dyn ProtocolMetrics>>,
+    ) -> bool {
+        let dropped = match queue_name {
+            PARTITIONS_EXPIRATION_QUEUE_NAME => self
+                .update_partition_expiration_time_update
+                .dequeue()
+                .map(|itm| itm.get_entries_amount()),
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME => self
+                .update_partitions_last_read_time_queue
+                .dequeue()
+                .map(|itm| itm.get_entries_amount()),
+            ROWS_EXPIRATION_QUEUE_NAME => self
+                .update_rows_expiration_time_queue
+                .dequeue()
+                .map(|itm| itm.get_entries_amount()),
+            _ => {
+                let event = self.update_rows_last_read_time_queue.dequeue();
+
+                if self.overflow_policy == SyncQueueOverflowPolicy::CollapseToPartitions {
+                    return match event {
+                        Some(event) => {
+                            // Row keys are lost, only the partition is delivered
+                            self.on_entries_dropped(
+                                queue_name,
+                                event.get_entries_amount(),
+                                metrics,
+                            );
+
+                            let read_moment = event
+                                .get_max_read_moment()
+                                .unwrap_or_else(DateTimeAsMicroseconds::now);
+
+                            self.update_partitions_last_read_time_queue.add_partition(
+                                &event.table_name,
+                                &event.partition_key,
+                                read_moment,
+                            );
+                            true
+                        }
+                        None => false,
+                    };
+                }
+
+                event.map(|itm| itm.get_entries_amount())
+            }
+        };
+
+        match dropped {
+            Some(dropped) => {
+                self.on_entries_dropped(queue_name, dropped, metrics);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn report_queue_depths(&self, metrics: Option<&Arc<dyn ProtocolMetrics>>) {
+        if let Some(metrics) = metrics {
+            for queue_name in QUEUE_NAMES {
+                let usage = self.get_queue_usage(queue_name);
+                metrics.sync_queue_usage(queue_name, usage.entries, usage.bytes);
+            }
+
+            metrics.sync_queue_depth(
+                PARTITIONS_EXPIRATION_QUEUE_NAME,
+                self.update_partition_expiration_time_update
+                    .get_events_amount(),
+            );
+            metrics.sync_queue_depth(
+                PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+                self.update_partitions_last_read_time_queue
+                    .get_events_amount(),
+            );
+            metrics.sync_queue_depth(
+                ROWS_EXPIRATION_QUEUE_NAME,
+                self.update_rows_expiration_time_queue.get_events_amount(),
+            );
+            metrics.sync_queue_depth(
+                ROWS_LAST_READ_TIME_QUEUE_NAME,
+                self.update_rows_last_read_time_queue.get_events_amount(),
+            );
+        }
+    }
+
+    fn start_delivery(&mut self, event: DeliverToMainNodeEvent) {
+        self.on_delivery.insert(
+            event.get_confirmation_id(),
+            InFlightEvent {
+                event,
+                started: Instant::now(),
+                sent_at: DateTimeAsMicroseconds::now(),
+                last_sent: Instant::now(),
+                resend_pending: false,
+            },
+        );
+    }
+
+    fn get_delivery_candidates(
+        &self,
+        flush_interval: Option<Duration>,
+        max_batch_size: Option<usize>,
+    ) -> Vec<SyncQueueCandidate> {
+        let on_delivery = &self.on_delivery;
+
+        SYNC_QUEUES_PRIORITY
+            .into_iter()
+            .filter_map(|queue_name| {
+                let can_dequeue =
+                    |table_name: &str| !is_in_flight(on_delivery, queue_name, table_name);
+
+                let candidate = match queue_name {
+                    PARTITIONS_EXPIRATION_QUEUE_NAME => self
+                        .update_partition_expiration_time_update
+                        .get_candidate_if(can_dequeue),
+                    PARTITIONS_LAST_READ_TIME_QUEUE_NAME => self
+                        .update_partitions_last_read_time_queue
+                        .get_candidate_if(can_dequeue),
+                    ROWS_EXPIRATION_QUEUE_NAME => self
+                        .update_rows_expiration_time_queue
+                        .get_candidate_if(can_dequeue),
+                    _ => self
+                        .update_rows_last_read_time_queue
+                        .get_candidate_if(can_dequeue),
+                };
+
+                let (enqueued, entries) = candidate?;
+
+                Some(SyncQueueCandidate {
+                    queue_name,
+                    enqueued,
+                    entries,
+                })
+            })
+            .filter(|itm| itm.is_ready(flush_interval, max_batch_size))
+            .collect()
+    }
+
+    // Events which are already old enough are delivered on connection and confirmations,
+    // so only the events held back by flush_interval need the timer
+    fn get_next_flush_deadline(&self, flush_interval: Duration) -> Option<Instant> {
+        let now = Instant::now();
+        let is_held_back = |enqueued: Instant| enqueued + flush_interval > now;
+
+        [
+            self.update_partition_expiration_time_update
+                .get_enqueued_if(is_held_back),
+            self.update_partitions_last_read_time_queue
+                .get_enqueued_if(is_held_back),
+            self.update_rows_expiration_time_queue
+                .get_enqueued_if(is_held_back),
+            self.update_rows_last_read_time_queue
+                .get_enqueued_if(is_held_back),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|enqueued| enqueued + flush_interval)
+    }
+
+    fn dequeue_next_event(
+        &mut self,
+        flush_interval: Option<Duration>,
+        max_batch_size: Option<usize>,
+    ) -> Option<DeliverToMainNodeEvent> {
+        let candidates = self.get_delivery_candidates(flush_interval, max_batch_size);
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let queue_name = self.scheduler.pick_queue(candidates.as_slice());
+
+        // Scheduler can not pick the queue which has nothing to deliver
+        let queue_name = candidates
+            .iter()
+            .find(|itm| itm.queue_name == queue_name)
+            .unwrap_or(&candidates[0])
+            .queue_name;
+
+        if self.main_node_supports_batches {
+            return match queue_name {
+                PARTITIONS_LAST_READ_TIME_QUEUE_NAME | ROWS_LAST_READ_TIME_QUEUE_NAME => {
+                    self.dequeue_last_read_time_batch(queue_name)
+                }
+                _ => self.dequeue_expiration_time_batch(queue_name),
+            };
+        }
+
+        self.dequeue_from(queue_name)
+    }
+
+    // Event picked by the scheduler goes first. The rest of the last read time updates
+    // of all the tables join it regardless of flush interval
+    fn dequeue_last_read_time_batch(
+        &mut self,
+        queue_name: &'static str,
+    ) -> Option<DeliverToMainNodeEvent> {
+        let mut partitions = Vec::new();
+        let mut rows = Vec::new();
+        let mut entries = 0;
+
+        for queue_name in [
+            queue_name,
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+            ROWS_LAST_READ_TIME_QUEUE_NAME,
+        ] {
+            while entries < self.max_sync_batch_entries {
+                let on_delivery = &self.on_delivery;
+                let can_dequeue =
+                    |table_name: &str| !is_in_flight(on_delivery, queue_name, table_name);
+
+                if queue_name == PARTITIONS_LAST_READ_TIME_QUEUE_NAME {
+                    match self
+                        .update_partitions_last_read_time_queue
+                        .dequeue_if(can_dequeue)
+                    {
+                        Some(event) => {
+                            entries += event.get_entries_amount();
+                            partitions.push(event);
+                        }
+                        None => break,
+                    }
+                } else {
+                    match self
+                        .update_rows_last_read_time_queue
+                        .dequeue_if(can_dequeue)
+                    {
+                        Some(event) => {
+                            entries += event.get_entries_amount();
+                            rows.push(event);
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        // Single event is sent with the per-table packet
+        let result = match (partitions.len(), rows.len()) {
+            (0, 0) => return None,
+            (1, 0) => DeliverToMainNodeEvent::UpdatePartitionsLastReadTime {
+                event: partitions.pop()?,
+                confirmation_id: self.get_confirmation_id(),
+            },
+            (0, 1) => DeliverToMainNodeEvent::UpdateRowsLastReadTime {
+                event: rows.pop()?,
+                confirmation_id: self.get_confirmation_id(),
+            },
+            _ => DeliverToMainNodeEvent::LastReadTimeBatch {
+                partitions,
+                rows,
+                confirmation_id: self.get_confirmation_id(),
+            },
+        };
+
+        Some(result)
+    }
+
+    fn dequeue_expiration_time_batch(
+        &mut self,
+        queue_name: &'static str,
+    ) -> Option<DeliverToMainNodeEvent> {
+        let mut partitions = Vec::new();
+        let mut rows = Vec::new();
+        let mut entries = 0;
+
+        for queue_name in [
+            queue_name,
+            PARTITIONS_EXPIRATION_QUEUE_NAME,
+            ROWS_EXPIRATION_QUEUE_NAME,
+        ] {
+            while entries < self.max_sync_batch_entries {
+                let on_delivery = &self.on_delivery;
+                let can_dequeue =
+                    |table_name: &str| !is_in_flight(on_delivery, queue_name, table_name);
+
+                if queue_name == PARTITIONS_EXPIRATION_QUEUE_NAME {
+                    match self
+                        .update_partition_expiration_time_update
+                        .dequeue_if(can_dequeue)
+                    {
+                        Some(event) => {
+                            entries += event.get_entries_amount();
+                            partitions.push(event);
+                        }
+                        None => break,
+                    }
+                } else {
+                    match self
+                        .update_rows_expiration_time_queue
+                        .dequeue_if(can_dequeue)
+                    {
+                        Some(event) => {
+                            entries += event.get_entries_amount();
+                            rows.push(event);
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        let result = match (partitions.len(), rows.len()) {
+            (0, 0) => return None,
+            (1, 0) => DeliverToMainNodeEvent::UpdatePartitionsExpiration {
+                event: partitions.pop()?,
+                confirmation_id: self.get_confirmation_id(),
+            },
+            (0, 1) => {
+                let mut event = rows.pop()?;
+                self.split_rows_expiration_if_needed(&mut event);
+
+                DeliverToMainNodeEvent::UpdateRowsExpirationTime {
+                    event,
+                    confirmation_id: self.get_confirmation_id(),
+                }
+            }
+            _ => DeliverToMainNodeEvent::ExpirationTimeBatch {
+                partitions,
+                rows,
+                confirmation_id: self.get_confirmation_id(),
+            },
+        };
+
+        Some(result)
+    }
+
+    fn dequeue_from(&mut self, queue_name: &'static str) -> Option<DeliverToMainNodeEvent> {
+        let on_delivery = &self.on_delivery;
+        let can_dequeue = |table_name: &str| !is_in_flight(on_delivery, queue_name, table_name);
+
+        match queue_name {
+            PARTITIONS_EXPIRATION_QUEUE_NAME => {
+                let event = self
+                    .update_partition_expiration_time_update
+                    .dequeue_if(can_dequeue)?;
+                let confirmation_id = self.get_confirmation_id();
+                Some(DeliverToMainNodeEvent::UpdatePartitionsExpiration {
+                    event,
+                    confirmation_id,
+                })
+            }
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME => {
+                let event = self
+                    .update_partitions_last_read_time_queue
+                    .dequeue_if(can_dequeue)?;
+                let confirmation_id = self.get_confirmation_id();
+                Some(DeliverToMainNodeEvent::UpdatePartitionsLastReadTime {
+                    event,
+                    confirmation_id,
+                })
+            }
+            ROWS_EXPIRATION_QUEUE_NAME => {
+                let mut event = self
+                    .update_rows_expiration_time_queue
+                    .dequeue_if(can_dequeue)?;
+                self.split_rows_expiration_if_needed(&mut event);
+
+                let confirmation_id = self.get_confirmation_id();
+                Some(DeliverToMainNodeEvent::UpdateRowsExpirationTime {
+                    event,
+                    confirmation_id,
+                })
+            }
+            _ => {
+                let event = self
+                    .update_rows_last_read_time_queue
+                    .dequeue_if(can_dequeue)?;
+                let confirmation_id = self.get_confirmation_id();
+                Some(DeliverToMainNodeEvent::UpdateRowsLastReadTime {
+                    event,
+                    confirmation_id,
+                })
+            }
+        }
+    }
+
+    // Main node which does not read expiration time per row gets one expiration time per packet.
+    // Rows with other expiration times go back to the queue
+    fn split_rows_expiration_if_needed(&mut self, event: &mut UpdateRowsExpirationTimeEvent) {
+        if self.main_node_supports_row_expirations {
+            return;
+        }
+
+        if let Some(other_rows) = event.split_by_expiration_time() {
+            self.update_rows_expiration_time_queue
+                .return_event(other_rows);
+        }
+    }
 
-        match on_delivery {
-            Some(event) => {
-                let on_devliery_confirmation_id = event.get_confirmation_id();
+    // Timed out event stays in flight and is sent again as is, so the packet which arrives late
+    // carries the same data and the same confirmation id. Newer updates of the table wait for it
+    fn schedule_timed_out_events(&mut self, timeout: Duration) -> Vec<TimedOutDelivery> {
+        let mut result = Vec::new();
+
+        for (confirmation_id, in_flight) in self.on_delivery.iter_mut() {
+            if in_flight.resend_pending || in_flight.last_sent.elapsed() < timeout {
+                continue;
+            }
+
+            in_flight.resend_pending = true;
+            self.to_resend.push_back(*confirmation_id);
+
+            result.push(TimedOutDelivery {
+                confirmation_id: *confirmation_id,
+                queue_name: in_flight.event.get_queue_name(),
+                table_name: in_flight.event.get_table_name().to_string(),
+                waited: in_flight.last_sent.elapsed(),
+            });
+        }
+
+        result
+    }
+
+    fn take_event_to_resend(&mut self) -> Option<DeliverToMainNodeEvent> {
+        while let Some(confirmation_id) = self.to_resend.pop_front() {
+            // Event can be confirmed while it waits to be sent again
+            if let Some(in_flight) = self.on_delivery.get_mut(&confirmation_id) {
+                in_flight.resend_pending = false;
+                in_flight.last_sent = Instant::now();
+                return Some(in_flight.event.clone());
+            }
+        }
+
+        None
+    }
+
+    fn return_event(&mut self, event: DeliverToMainNodeEvent) {
+        match event {
+            DeliverToMainNodeEvent::UpdatePartitionsExpiration {
+                event,
+                confirmation_id: _,
+            } => {
+                self.update_partition_expiration_time_update
+                    .return_event(event);
+            }
+            DeliverToMainNodeEvent::UpdatePartitionsLastReadTime {
+                event,
+                confirmation_id: _,
+            } => {
+                self.update_partitions_last_read_time_queue
+                    .return_event(event);
+            }
+            DeliverToMainNodeEvent::UpdateRowsExpirationTime {
+                event,
+                confirmation_id: _,
+            } => {
+                self.update_rows_expiration_time_queue.return_event(event);
+            }
+            DeliverToMainNodeEvent::UpdateRowsLastReadTime {
+                event,
+                confirmation_id: _,
+            } => {
+                self.update_rows_last_read_time_queue.return_event(event);
+            }
+            // Events are returned newest first to keep their order in the queues
+            DeliverToMainNodeEvent::LastReadTimeBatch {
+                partitions, rows, ..
+            } => {
+                for event in partitions.into_iter().rev() {
+                    self.update_partitions_last_read_time_queue
+                        .return_event(event);
+                }
 
-                if on_devliery_confirmation_id != delivery_id {
-                    println!("Somehow we are waiting confirmation for delivery with id {}, but we go confirmation id {} which is not the same  as the one we are waiting for. This is a bug.", on_devliery_confirmation_id, delivery_id);
+                for event in rows.into_iter().rev() {
+                    self.update_rows_last_read_time_queue.return_event(event);
                 }
             }
-            None => {
-                println!(
-                    "Somehow we got confirmation for delivery, but there is no delivery in progress"
-                );
+            DeliverToMainNodeEvent::ExpirationTimeBatch {
+                partitions, rows, ..
+            } => {
+                for event in partitions.into_iter().rev() {
+                    self.update_partition_expiration_time_update
+                        .return_event(event);
+                }
+
+                for event in rows.into_iter().rev() {
+                    self.update_rows_expiration_time_queue.return_event(event);
+                }
             }
         }
     }
@@ -100,6 +1178,13 @@ impl SyncQueuesInner {
 pub struct SyncToMainNodeQueues {
     inner: Mutex<SyncQueuesInner>,
     pub event_loop: EventsLoop<SyncToMainNodeEvent>,
+    metrics: Option<Arc<dyn ProtocolMetrics>>,
+    max_in_flight: usize,
+    confirmation_timeout: Option<Duration>,
+    max_timeouts_before_disconnect: usize,
+    flush_interval: Option<Duration>,
+    max_batch_size: Option<usize>,
+    flush_timer_wakeup: Notify,
 }
 
 impl SyncToMainNodeQueues {
@@ -107,9 +1192,168 @@ impl SyncToMainNodeQueues {
         Self {
             inner: Mutex::new(SyncQueuesInner::new()),
             event_loop: EventsLoop::new("SyncToMainNodeQueues".to_string()),
+            metrics: None,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            confirmation_timeout: Some(DEFAULT_CONFIRMATION_TIMEOUT),
+            max_timeouts_before_disconnect: DEFAULT_MAX_TIMEOUTS_BEFORE_DISCONNECT,
+            flush_interval: None,
+            max_batch_size: None,
+            flush_timer_wakeup: Notify::new(),
+        }
+    }
+
+    pub fn new_with_metrics(metrics: Arc<dyn ProtocolMetrics>) -> Self {
+        Self {
+            inner: Mutex::new(SyncQueuesInner::new()),
+            event_loop: EventsLoop::new("SyncToMainNodeQueues".to_string()),
+            metrics: Some(metrics),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            confirmation_timeout: Some(DEFAULT_CONFIRMATION_TIMEOUT),
+            max_timeouts_before_disconnect: DEFAULT_MAX_TIMEOUTS_BEFORE_DISCONNECT,
+            flush_interval: None,
+            max_batch_size: None,
+            flush_timer_wakeup: Notify::new(),
+        }
+    }
+
+    // Amount of events sent to the main node without waiting for confirmation.
+    // Round trip caps throughput at max_in_flight packets per round trip
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    pub fn get_max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    // None - we wait for confirmation until the connection is dropped
+    pub fn with_confirmation_timeout(mut self, confirmation_timeout: Option<Duration>) -> Self {
+        self.confirmation_timeout = confirmation_timeout;
+        self
+    }
+
+    pub fn get_confirmation_timeout(&self) -> Option<Duration> {
+        self.confirmation_timeout
+    }
+
+    pub fn with_max_timeouts_before_disconnect(mut self, max_timeouts: usize) -> Self {
+        self.max_timeouts_before_disconnect = max_timeouts.max(1);
+        self
+    }
+
+    // Pending updates are replayed from the journal and are delivered as soon as main node is connected
+    pub fn with_journal(mut self, mut journal: SyncQueuesJournal) -> Self {
+        let inner = self.inner.get_mut();
+
+        for record in journal.take_recovered() {
+            inner.apply_journal_record(record);
+        }
+
+        inner.journal = Some(journal);
+
+        self
+    }
+
+    // Completes when the updates queued so far are written to the journal
+    pub async fn flush_journal(&self) -> std::io::Result<()> {
+        let flush = match self.inner.lock().await.journal.as_ref() {
+            Some(journal) => journal.flush(),
+            None => return Ok(()),
+        };
+
+        flush.await
+    }
+
+    // Updates are accumulated for up to flush_interval before they are delivered to main node.
+    // None - updates are delivered as soon as they are queued
+    pub fn with_flush_interval(mut self, flush_interval: Option<Duration>) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn get_flush_interval(&self) -> Option<Duration> {
+        self.flush_interval
+    }
+
+    // Moment the oldest event held back by flush_interval becomes ready to be delivered.
+    // None - nothing is held back. Flush timer is woken up when the next event is queued
+    pub(crate) async fn get_next_flush_deadline(&self) -> Option<Instant> {
+        let flush_interval = self.flush_interval?;
+
+        let mut inner = self.inner.lock().await;
+        let result = inner.get_next_flush_deadline(flush_interval);
+        inner.flush_timer_waiting = result.is_none();
+        result
+    }
+
+    pub(crate) async fn wait_for_queued_event(&self) {
+        self.flush_timer_wakeup.notified().await
+    }
+
+    // Deadline of the new event is later than the one the timer sleeps until, so only idle timer is woken up
+    fn wake_flush_timer(&self, inner: &mut SyncQueuesInner) {
+        if inner.flush_timer_waiting {
+            inner.flush_timer_waiting = false;
+            self.flush_timer_wakeup.notify_one();
         }
     }
 
+    // Event with this amount of partition or row keys is delivered without waiting for flush_interval.
+    // Has no effect without flush_interval, since every update is delivered right away then
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = Some(max_batch_size.max(1));
+        self
+    }
+
+    // Limits of the single queue. Queue names are the *_QUEUE_NAME constants
+    pub fn with_queue_limits(mut self, queue_name: &'static str, limits: SyncQueueLimits) -> Self {
+        self.inner.get_mut().queue_limits.insert(queue_name, limits);
+        self
+    }
+
+    // Limits of all the queues together
+    pub fn with_global_limits(mut self, limits: SyncQueueLimits) -> Self {
+        self.inner.get_mut().global_limits = limits;
+        self
+    }
+
+    pub fn with_overflow_policy(mut self, overflow_policy: SyncQueueOverflowPolicy) -> Self {
+        self.inner.get_mut().overflow_policy = overflow_policy;
+        self
+    }
+
+    // Amount of partition and row keys packed into a single sync batch
+    pub fn with_max_sync_batch_entries(mut self, max_entries: usize) -> Self {
+        self.inner.get_mut().max_sync_batch_entries = max_entries.max(1);
+        self
+    }
+
+    // Strict priority by default: partition expiration, partition last read, row expiration, row last read
+    pub fn with_scheduler(mut self, scheduler: impl SyncQueuesScheduler) -> Self {
+        self.inner.get_mut().scheduler = Box::new(scheduler);
+        self
+    }
+
+    pub async fn get_usage(&self) -> Vec<(&'static str, SyncQueueUsage)> {
+        let inner = self.inner.lock().await;
+
+        QUEUE_NAMES
+            .into_iter()
+            .map(|queue_name| (queue_name, inner.get_queue_usage(queue_name)))
+            .collect()
+    }
+
+    // State of the updates the main node has not confirmed yet. Meant for health checks.
+    // Counters per table are kept by the queues, so the queued events are not walked
+    pub async fn snapshot(&self) -> SyncQueuesSnapshot {
+        self.inner.lock().await.get_snapshot()
+    }
+
+    pub async fn get_in_flight_amount(&self) -> usize {
+        self.inner.lock().await.get_in_flight_amount()
+    }
+
     pub async fn update<'s, TRowKeys: Iterator<Item = &'s str>>(
         &self,
         table_name: &str,
@@ -121,36 +1365,86 @@ impl SyncToMainNodeQueues {
             return;
         }
 
+        let read_moment = DateTimeAsMicroseconds::now();
+
         let mut inner = self.inner.lock().await;
 
-        if data.partition_last_read_moment {
-            inner
-                .update_partitions_last_read_time_queue
-                .add_partition(table_name, partition_key);
+        if data.partition_last_read_moment
+            && inner.can_accept(
+                PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+                1,
+                self.metrics.as_ref(),
+            )
+        {
+            inner.update_partitions_last_read_time_queue.add_partition(
+                table_name,
+                partition_key,
+                read_moment,
+            );
+
+            inner.journal_append(|| {
+                SyncJournalRecord::PartitionsLastReadTime(UpdatePartitionsLastReadTimeEvent {
+                    table_name: table_name.to_string(),
+                    partitions: HashMap::from([(partition_key.to_string(), read_moment)]),
+                })
+            });
 
             self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
         }
 
-        if let Some(partition_expiration) = data.partition_expiration_moment {
+        if let Some(partition_expiration) = data.partition_expiration_moment.filter(|_| {
+            inner.can_accept(PARTITIONS_EXPIRATION_QUEUE_NAME, 1, self.metrics.as_ref())
+        }) {
             inner.update_partition_expiration_time_update.add(
                 table_name,
                 partition_key,
                 partition_expiration,
             );
 
+            inner.journal_append(|| {
+                SyncJournalRecord::PartitionsExpiration(UpdatePartitionExpirationEvent {
+                    table_name: table_name.to_string(),
+                    partitions: HashMap::from([(partition_key.to_string(), partition_expiration)]),
+                })
+            });
+
             self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
         }
 
         if data.row_last_read_moment {
-            if data.row_last_read_moment {
-                inner
-                    .update_rows_last_read_time_queue
-                    .add(table_name, partition_key, row_keys());
+            if inner.can_accept(
+                ROWS_LAST_READ_TIME_QUEUE_NAME,
+                row_keys().count(),
+                self.metrics.as_ref(),
+            ) {
+                inner.update_rows_last_read_time_queue.add(
+                    table_name,
+                    partition_key,
+                    row_keys(),
+                    read_moment,
+                );
+
+                inner.journal_append(|| {
+                    SyncJournalRecord::RowsLastReadTime(UpdateRowsLastReadTimeEvent {
+                        table_name: table_name.to_string(),
+                        partition_key: partition_key.to_string(),
+                        row_keys: row_keys()
+                            .map(|itm| (itm.to_string(), read_moment))
+                            .collect(),
+                    })
+                });
+
                 self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
             }
         }
 
-        if let Some(row_expiration) = data.row_expiration_moment {
+        if let Some(row_expiration) = data.row_expiration_moment.filter(|_| {
+            inner.can_accept(
+                ROWS_EXPIRATION_QUEUE_NAME,
+                row_keys().count(),
+                self.metrics.as_ref(),
+            )
+        }) {
             inner.update_rows_expiration_time_queue.add(
                 table_name,
                 partition_key,
@@ -158,8 +1452,22 @@ impl SyncToMainNodeQueues {
                 row_expiration,
             );
 
+            inner.journal_append(|| {
+                SyncJournalRecord::RowsExpirationTime(UpdateRowsExpirationTimeEvent {
+                    table_name: table_name.to_string(),
+                    partition_key: partition_key.to_string(),
+                    row_keys: row_keys()
+                        .map(|itm| (itm.to_string(), row_expiration))
+                        .collect(),
+                })
+            });
+
             self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
         }
+
+        inner.enforce_limits(self.metrics.as_ref());
+        inner.report_queue_depths(self.metrics.as_ref());
+        self.wake_flush_timer(&mut inner);
     }
 
     pub async fn update_partition_expiration_time(
@@ -170,10 +1478,25 @@ impl SyncToMainNodeQueues {
     ) {
         let mut inner = self.inner.lock().await;
 
+        if !inner.can_accept(PARTITIONS_EXPIRATION_QUEUE_NAME, 1, self.metrics.as_ref()) {
+            return;
+        }
+
         inner
             .update_partition_expiration_time_update
             .add(table_name, partition_key, date_time);
 
+        inner.journal_append(|| {
+            SyncJournalRecord::PartitionsExpiration(UpdatePartitionExpirationEvent {
+                table_name: table_name.to_string(),
+                partitions: HashMap::from([(partition_key.to_string(), date_time)]),
+            })
+        });
+
+        inner.enforce_limits(self.metrics.as_ref());
+        inner.report_queue_depths(self.metrics.as_ref());
+        self.wake_flush_timer(&mut inner);
+
         self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
     }
 
@@ -184,14 +1507,40 @@ impl SyncToMainNodeQueues {
         row_keys: TRowKeys,
         date_time: Option<DateTimeAsMicroseconds>,
     ) {
+        let row_keys: Vec<&String> = row_keys.collect();
+
         let mut inner = self.inner.lock().await;
+
+        if !inner.can_accept(
+            ROWS_EXPIRATION_QUEUE_NAME,
+            row_keys.len(),
+            self.metrics.as_ref(),
+        ) {
+            return;
+        }
+
         inner.update_rows_expiration_time_queue.add(
             table_name,
             partition_key,
-            row_keys.map(|itm| itm.as_str()),
+            row_keys.iter().map(|itm| itm.as_str()),
             date_time,
         );
 
+        inner.journal_append(|| {
+            SyncJournalRecord::RowsExpirationTime(UpdateRowsExpirationTimeEvent {
+                table_name: table_name.to_string(),
+                partition_key: partition_key.to_string(),
+                row_keys: row_keys
+                    .iter()
+                    .map(|itm| (itm.to_string(), date_time))
+                    .collect(),
+            })
+        });
+
+        inner.enforce_limits(self.metrics.as_ref());
+        inner.report_queue_depths(self.metrics.as_ref());
+        self.wake_flush_timer(&mut inner);
+
         self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
     }
 
@@ -201,13 +1550,41 @@ impl SyncToMainNodeQueues {
         partition_key: &str,
         row_keys: TRowKeys,
     ) {
+        let row_keys: Vec<&String> = row_keys.collect();
+        let read_moment = DateTimeAsMicroseconds::now();
+
         let mut inner = self.inner.lock().await;
+
+        if !inner.can_accept(
+            ROWS_LAST_READ_TIME_QUEUE_NAME,
+            row_keys.len(),
+            self.metrics.as_ref(),
+        ) {
+            return;
+        }
+
         inner.update_rows_last_read_time_queue.add(
             table_name,
             partition_key,
-            row_keys.map(|itm| itm.as_str()),
+            row_keys.iter().map(|itm| itm.as_str()),
+            read_moment,
         );
 
+        inner.journal_append(|| {
+            SyncJournalRecord::RowsLastReadTime(UpdateRowsLastReadTimeEvent {
+                table_name: table_name.to_string(),
+                partition_key: partition_key.to_string(),
+                row_keys: row_keys
+                    .iter()
+                    .map(|itm| (itm.to_string(), read_moment))
+                    .collect(),
+            })
+        });
+
+        inner.enforce_limits(self.metrics.as_ref());
+        inner.report_queue_depths(self.metrics.as_ref());
+        self.wake_flush_timer(&mut inner);
+
         self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
     }
 
@@ -216,10 +1593,38 @@ impl SyncToMainNodeQueues {
         table_name: &str,
         partition_keys: TPartitions,
     ) {
+        let partition_keys: Vec<&String> = partition_keys.collect();
+        let read_moment = DateTimeAsMicroseconds::now();
+
         let mut inner = self.inner.lock().await;
-        inner
-            .update_partitions_last_read_time_queue
-            .add(table_name, partition_keys);
+
+        if !inner.can_accept(
+            PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+            partition_keys.len(),
+            self.metrics.as_ref(),
+        ) {
+            return;
+        }
+
+        inner.update_partitions_last_read_time_queue.add(
+            table_name,
+            partition_keys.iter().copied(),
+            read_moment,
+        );
+
+        inner.journal_append(|| {
+            SyncJournalRecord::PartitionsLastReadTime(UpdatePartitionsLastReadTimeEvent {
+                table_name: table_name.to_string(),
+                partitions: partition_keys
+                    .iter()
+                    .map(|itm| (itm.to_string(), read_moment))
+                    .collect(),
+            })
+        });
+
+        inner.enforce_limits(self.metrics.as_ref());
+        inner.report_queue_depths(self.metrics.as_ref());
+        self.wake_flush_timer(&mut inner);
 
         self.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
     }
@@ -227,114 +1632,439 @@ impl SyncToMainNodeQueues {
     pub async fn get_next_event_to_deliver(
         &self,
         delivery_id: Option<i64>,
-    ) -> Option<(Arc<DataReaderTcpConnection>, DeliverToMainNodeEvent)> {
+    ) -> Option<(Arc<dyn MainNodeConnection>, DeliverToMainNodeEvent)> {
         let mut inner = self.inner.lock().await;
 
         if let Some(delivery_id) = delivery_id {
-            inner.confirm_delivery(delivery_id);
+            inner.confirm_delivery(delivery_id, self.metrics.as_ref());
         }
 
-        if inner.on_delivery.is_some() {
-            return None;
+        if let Some(connection) = inner.connection.clone() {
+            if let Some(event) = inner.take_event_to_resend() {
+                return Some((connection, event));
+            }
         }
 
-        if inner.connection.is_none() {
+        if inner.on_delivery.len() >= self.max_in_flight {
             return None;
         }
 
-        if let Some(event) = inner.update_partition_expiration_time_update.dequeue() {
-            let confirmation_id = inner.get_confirmation_id();
-            let result = DeliverToMainNodeEvent::UpdatePartitionsExpiration {
-                event,
-                confirmation_id,
-            };
+        let connection = inner.connection.clone()?;
 
-            inner.on_delivery = Some(result.clone());
-            return Some((inner.connection.as_ref().unwrap().clone(), result));
-        }
+        let result = inner.dequeue_next_event(self.flush_interval, self.max_batch_size)?;
 
-        if let Some(event) = inner.update_partitions_last_read_time_queue.dequeue() {
-            let confirmation_id = inner.get_confirmation_id();
-            let result = DeliverToMainNodeEvent::UpdatePartitionsLastReadTime {
-                event,
-                confirmation_id,
-            };
+        inner.start_delivery(result.clone());
+        inner.report_queue_depths(self.metrics.as_ref());
+        Some((connection, result))
+    }
 
-            inner.on_delivery = Some(result.clone());
-            return Some((inner.connection.as_ref().unwrap().clone(), result));
-        }
+    pub async fn new_connection(&self, connection: Arc<dyn MainNodeConnection>) {
+        let mut inner = self.inner.lock().await;
+        inner.connection = Some(connection);
+    }
 
-        if let Some(event) = inner.update_rows_expiration_time_queue.dequeue() {
-            let confirmation_id = inner.get_confirmation_id();
-            let result = DeliverToMainNodeEvent::UpdateRowsExpirationTime {
-                event,
-                confirmation_id,
-            };
+    // Main node which does not advertise sync batches gets per-table packets
+    pub async fn set_main_node_supports_batches(&self, supports_batches: bool) {
+        let mut inner = self.inner.lock().await;
+        inner.main_node_supports_batches = supports_batches;
+    }
 
-            inner.on_delivery = Some(result.clone());
-            return Some((inner.connection.as_ref().unwrap().clone(), result));
-        }
+    pub async fn main_node_supports_batches(&self) -> bool {
+        self.inner.lock().await.main_node_supports_batches
+    }
 
-        if let Some(event) = inner.update_rows_last_read_time_queue.dequeue() {
-            let confirmation_id = inner.get_confirmation_id();
-            let result = DeliverToMainNodeEvent::UpdateRowsLastReadTime {
-                event,
-                confirmation_id,
-            };
+    pub async fn set_main_node_supports_read_moments(&self, supports_read_moments: bool) {
+        let mut inner = self.inner.lock().await;
+        inner.main_node_supports_read_moments = supports_read_moments;
+    }
 
-            inner.on_delivery = Some(result.clone());
-            return Some((inner.connection.as_ref().unwrap().clone(), result));
-        }
+    pub async fn main_node_supports_read_moments(&self) -> bool {
+        self.inner.lock().await.main_node_supports_read_moments
+    }
 
-        None
+    pub async fn set_main_node_supports_row_expirations(&self, supports_row_expirations: bool) {
+        let mut inner = self.inner.lock().await;
+        inner.main_node_supports_row_expirations = supports_row_expirations;
+    }
+
+    pub async fn main_node_supports_row_expirations(&self) -> bool {
+        self.inner.lock().await.main_node_supports_row_expirations
     }
 
-    pub async fn new_connection(&self, connection: Arc<DataReaderTcpConnection>) {
+    // Events which are not confirmed in time are sent again with the same confirmation id,
+    // so either of the confirmations completes the delivery
+    pub async fn resend_timed_out_events(&self) -> DeliveryTimeouts {
+        let mut result = DeliveryTimeouts {
+            timed_out: Vec::new(),
+            connection_to_drop: None,
+        };
+
+        let confirmation_timeout = match self.confirmation_timeout {
+            Some(confirmation_timeout) => confirmation_timeout,
+            None => return result,
+        };
+
         let mut inner = self.inner.lock().await;
-        inner.connection = Some(connection);
+
+        result.timed_out = inner.schedule_timed_out_events(confirmation_timeout);
+
+        if result.timed_out.is_empty() {
+            return result;
+        }
+
+        if let Some(metrics) = self.metrics.as_ref() {
+            for item in &result.timed_out {
+                metrics.sync_delivery_timed_out(item.queue_name, item.waited);
+            }
+        }
+
+        inner.timeouts_in_row += 1;
+
+        if inner.timeouts_in_row >= self.max_timeouts_before_disconnect {
+            inner.timeouts_in_row = 0;
+            result.connection_to_drop = inner.connection.clone();
+        }
+
+        result
     }
 
+    // Every outstanding event goes back to its queue. Events are returned newest first,
+    // so they are delivered again in the original order
     pub async fn disconnected(&self) {
         let mut inner = self.inner.lock().await;
         inner.connection = None;
+        inner.timeouts_in_row = 0;
+        inner.main_node_supports_batches = false;
+        inner.main_node_supports_read_moments = false;
+        inner.main_node_supports_row_expirations = false;
 
-        let event_on_delivery = inner.on_delivery.take();
+        inner.to_resend.clear();
+        let on_delivery = std::mem::take(&mut inner.on_delivery);
 
-        if event_on_delivery.is_none() {
-            return;
+        for (_, in_flight) in on_delivery.into_iter().rev() {
+            inner.return_event(in_flight.event);
         }
 
-        let event_on_delivery = event_on_delivery.unwrap();
+        inner.report_queue_depths(self.metrics.as_ref());
+    }
+}
 
-        match event_on_delivery {
-            DeliverToMainNodeEvent::UpdatePartitionsExpiration {
-                event,
-                confirmation_id: _,
-            } => {
-                inner
-                    .update_partition_expiration_time_update
-                    .return_event(event);
-            }
-            DeliverToMainNodeEvent::UpdatePartitionsLastReadTime {
-                event,
-                confirmation_id: _,
-            } => {
-                inner
-                    .update_partitions_last_read_time_queue
-                    .return_event(event);
-            }
-            DeliverToMainNodeEvent::UpdateRowsExpirationTime {
-                event,
-                confirmation_id: _,
-            } => {
-                inner.update_rows_expiration_time_queue.return_event(event);
-            }
-            DeliverToMainNodeEvent::UpdateRowsLastReadTime {
-                event,
-                confirmation_id: _,
-            } => {
-                inner.update_rows_last_read_time_queue.return_event(event);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopConnection;
+
+    #[async_trait::async_trait]
+    impl MainNodeConnection for NoopConnection {
+        async fn send(&self, _contract: crate::MyNoSqlTcpContract) {}
+
+        async fn disconnect(&self) {}
+    }
+
+    #[tokio::test]
+    async fn timed_out_event_is_sent_again_with_the_same_confirmation_id() {
+        let queues = SyncToMainNodeQueues::new()
+            .with_max_in_flight(2)
+            .with_confirmation_timeout(Some(Duration::from_millis(20)));
+        queues.new_connection(Arc::new(NoopConnection)).await;
+
+        queues
+            .update_partition_expiration_time("table", "pk", None)
+            .await;
+
+        let (_, sent) = queues.get_next_event_to_deliver(None).await.unwrap();
+
+        // Newer update of the table waits until the first one is confirmed
+        queues
+            .update_partition_expiration_time("table", "pk", Some(DateTimeAsMicroseconds::now()))
+            .await;
+        assert!(queues.get_next_event_to_deliver(None).await.is_none());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let timeouts = queues.resend_timed_out_events().await;
+        assert_eq!(timeouts.timed_out.len(), 1);
+        assert_eq!(
+            timeouts.timed_out[0].confirmation_id,
+            sent.get_confirmation_id()
+        );
+
+        let (_, resent) = queues.get_next_event_to_deliver(None).await.unwrap();
+        assert_eq!(resent.get_confirmation_id(), sent.get_confirmation_id());
+        assert!(queues.get_next_event_to_deliver(None).await.is_none());
+        assert_eq!(queues.get_in_flight_amount().await, 1);
+
+        // Either of the confirmations completes the delivery, the other one is ignored
+        let (_, next) = queues
+            .get_next_event_to_deliver(Some(sent.get_confirmation_id()))
+            .await
+            .unwrap();
+        assert_ne!(next.get_confirmation_id(), sent.get_confirmation_id());
+        assert_eq!(next.get_table_name(), "table");
+
+        assert!(queues
+            .get_next_event_to_deliver(Some(sent.get_confirmation_id()))
+            .await
+            .is_none());
+        assert_eq!(queues.get_in_flight_amount().await, 1);
+    }
+
+    #[tokio::test]
+    async fn event_confirmed_before_it_is_sent_again_is_not_resent() {
+        let queues =
+            SyncToMainNodeQueues::new().with_confirmation_timeout(Some(Duration::from_millis(20)));
+        queues.new_connection(Arc::new(NoopConnection)).await;
+
+        queues
+            .update_partition_expiration_time("table", "pk", None)
+            .await;
+
+        let (_, sent) = queues.get_next_event_to_deliver(None).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(queues.resend_timed_out_events().await.timed_out.len(), 1);
+
+        // Event waits to be sent again only once
+        assert!(queues.resend_timed_out_events().await.timed_out.is_empty());
+
+        assert!(queues
+            .get_next_event_to_deliver(Some(sent.get_confirmation_id()))
+            .await
+            .is_none());
+        assert_eq!(queues.get_in_flight_amount().await, 0);
+    }
+
+    #[tokio::test]
+    async fn collapsed_row_keys_are_counted_as_dropped() {
+        let queues = SyncToMainNodeQueues::new()
+            .with_overflow_policy(SyncQueueOverflowPolicy::CollapseToPartitions)
+            .with_queue_limits(
+                ROWS_LAST_READ_TIME_QUEUE_NAME,
+                SyncQueueLimits::new().with_max_entries(2),
+            );
+
+        let row_keys = ["row-1".to_string(), "row-2".to_string()];
+        queues
+            .update_rows_last_read_time("table", "pk-1", row_keys.iter())
+            .await;
+
+        let row_keys = ["row-3".to_string()];
+        queues
+            .update_rows_last_read_time("table", "pk-2", row_keys.iter())
+            .await;
+
+        let usage: HashMap<_, _> = queues.get_usage().await.into_iter().collect();
+
+        assert_eq!(usage[ROWS_LAST_READ_TIME_QUEUE_NAME].entries, 1);
+        assert_eq!(usage[ROWS_LAST_READ_TIME_QUEUE_NAME].dropped_entries, 2);
+        assert_eq!(usage[PARTITIONS_LAST_READ_TIME_QUEUE_NAME].entries, 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_updates_are_removed_from_the_journal() {
+        let path = std::env::temp_dir().join(format!(
+            "my-no-sql-sync-queues-journal-{}-dropped",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let queues = SyncToMainNodeQueues::new()
+                .with_global_limits(SyncQueueLimits::new().with_max_entries(1))
+                .with_journal(
+                    SyncQueuesJournal::open(&path)
+                        .unwrap()
+                        .with_min_compaction_interval(Duration::ZERO),
+                );
+
+            queues
+                .update_partition_expiration_time("table-1", "pk", None)
+                .await;
+            queues
+                .update_partition_expiration_time("table-2", "pk", None)
+                .await;
         }
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        let table_names: Vec<String> = journal
+            .take_recovered()
+            .into_iter()
+            .map(|record| match record {
+                SyncJournalRecord::PartitionsExpiration(event) => event.table_name,
+                record => panic!("Unexpected record {:?}", record),
+            })
+            .collect();
+
+        assert_eq!(table_names, vec!["table-2".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn flush_deadline_is_the_moment_oldest_held_back_event_is_ready() {
+        let flush_interval = Duration::from_millis(50);
+        let queues = SyncToMainNodeQueues::new().with_flush_interval(Some(flush_interval));
+
+        assert!(queues.get_next_flush_deadline().await.is_none());
+
+        let queued = Instant::now();
+        queues
+            .update_partition_expiration_time("table", "pk", None)
+            .await;
+
+        let deadline = queues.get_next_flush_deadline().await.unwrap();
+        assert!(deadline >= queued + flush_interval);
+        assert!(deadline <= Instant::now() + flush_interval);
+
+        // Event which is ready is delivered with the next ping, timer has nothing to wait for
+        tokio::time::sleep(flush_interval).await;
+        assert!(queues.get_next_flush_deadline().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn idle_flush_timer_is_woken_up_by_queued_event() {
+        let queues = Arc::new(
+            SyncToMainNodeQueues::new().with_flush_interval(Some(Duration::from_secs(10))),
+        );
+
+        assert!(queues.get_next_flush_deadline().await.is_none());
+
+        let waiting = {
+            let queues = queues.clone();
+            tokio::spawn(async move { queues.wait_for_queued_event().await })
+        };
+
+        queues
+            .update_partition_expiration_time("table", "pk", None)
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(queues.get_next_flush_deadline().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn snapshot_reports_in_flight_events_without_their_keys() {
+        let queues = SyncToMainNodeQueues::new();
+        queues.new_connection(Arc::new(NoopConnection)).await;
+
+        let row_keys = ["row-1".to_string(), "row-2".to_string()];
+        queues
+            .update_rows_last_read_time("table-1", "pk", row_keys.iter())
+            .await;
+        queues
+            .update_rows_last_read_time("table-2", "pk", row_keys.iter())
+            .await;
+
+        let (_, sent) = queues.get_next_event_to_deliver(None).await.unwrap();
+
+        let snapshot = queues.snapshot().await;
+
+        assert_eq!(snapshot.in_flight.len(), 1);
+        assert_eq!(
+            snapshot.in_flight[0].confirmation_id,
+            sent.get_confirmation_id()
+        );
+        assert_eq!(
+            snapshot.in_flight[0].queue_name,
+            ROWS_LAST_READ_TIME_QUEUE_NAME
+        );
+        assert_eq!(snapshot.in_flight[0].table_name, "table-1");
+        assert_eq!(snapshot.in_flight[0].entries, 2);
+
+        let rows = snapshot
+            .queues
+            .iter()
+            .find(|itm| itm.queue_name == ROWS_LAST_READ_TIME_QUEUE_NAME)
+            .unwrap();
+        assert_eq!(rows.usage.entries, 2);
+        assert!(rows.oldest_pending_age.is_some());
+        assert_eq!(rows.tables.len(), 1);
+        assert_eq!(rows.tables[0].table_name, "table-2");
+        assert_eq!(rows.tables[0].pending_rows, 2);
+    }
+
+    fn get_snapshot_tables(
+        snapshot: &SyncQueuesSnapshot,
+        queue_name: &str,
+    ) -> Vec<(String, usize, usize)> {
+        snapshot
+            .queues
+            .iter()
+            .find(|itm| itm.queue_name == queue_name)
+            .unwrap()
+            .tables
+            .iter()
+            .map(|itm| {
+                assert!(itm.oldest_pending_age.is_some());
+                (
+                    itm.table_name.clone(),
+                    itm.pending_partitions,
+                    itm.pending_rows,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn snapshot_tables_follow_enqueued_returned_and_dropped_events() {
+        let queues = SyncToMainNodeQueues::new().with_queue_limits(
+            ROWS_LAST_READ_TIME_QUEUE_NAME,
+            SyncQueueLimits::new().with_max_entries(5),
+        );
+        queues.new_connection(Arc::new(NoopConnection)).await;
+
+        let row_keys = ["row-1".to_string(), "row-2".to_string()];
+        queues
+            .update_rows_last_read_time("table-1", "pk", row_keys.iter())
+            .await;
+        queues
+            .update_rows_last_read_time("table-2", "pk", row_keys.iter())
+            .await;
+
+        // Rows are merged into the queued event
+        let row_keys = ["row-3".to_string()];
+        queues
+            .update_rows_last_read_time("table-2", "pk", row_keys.iter())
+            .await;
+
+        let snapshot = queues.snapshot().await;
+        assert_eq!(
+            get_snapshot_tables(&snapshot, ROWS_LAST_READ_TIME_QUEUE_NAME),
+            vec![("table-1".to_string(), 1, 2), ("table-2".to_string(), 1, 3)]
+        );
+
+        let (_, sent) = queues.get_next_event_to_deliver(None).await.unwrap();
+        assert_eq!(sent.get_table_name(), "table-1");
+
+        let snapshot = queues.snapshot().await;
+        assert_eq!(
+            get_snapshot_tables(&snapshot, ROWS_LAST_READ_TIME_QUEUE_NAME),
+            vec![("table-2".to_string(), 1, 3)]
+        );
+
+        queues.disconnected().await;
+
+        let snapshot = queues.snapshot().await;
+        assert_eq!(
+            get_snapshot_tables(&snapshot, ROWS_LAST_READ_TIME_QUEUE_NAME),
+            vec![("table-1".to_string(), 1, 2), ("table-2".to_string(), 1, 3)]
+        );
+
+        // Oldest event of table-1 is dropped to fit the limit
+        let row_keys = ["row-4".to_string()];
+        queues
+            .update_rows_last_read_time("table-3", "pk", row_keys.iter())
+            .await;
+
+        let snapshot = queues.snapshot().await;
+        assert_eq!(
+            get_snapshot_tables(&snapshot, ROWS_LAST_READ_TIME_QUEUE_NAME),
+            vec![("table-2".to_string(), 1, 3), ("table-3".to_string(), 1, 1)]
+        );
+        assert!(get_snapshot_tables(&snapshot, PARTITIONS_LAST_READ_TIME_QUEUE_NAME).is_empty());
     }
 }
@@ -0,0 +1,930 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::ChecksumAlgorithm;
+
+use super::{
+    UpdatePartitionExpirationEvent, UpdatePartitionsLastReadTimeEvent,
+    UpdateRowsExpirationTimeEvent, UpdateRowsLastReadTimeEvent,
+};
+
+pub const SYNC_JOURNAL_FILE_MAGIC: &[u8; 4] = b"MNSJ";
+// Version 1 adds records 4, 5 and 6
+pub const SYNC_JOURNAL_FILE_VERSION: u8 = 1;
+
+pub const DEFAULT_SYNC_JOURNAL_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+// Journal is compacted only after it has grown this much, so small queues do not rewrite the file on every confirmation
+const MIN_COMPACTION_SIZE: u64 = 1024 * 1024;
+
+// Overflowed journal or journal with dropped updates is rewritten, but not more often than this
+pub const DEFAULT_SYNC_JOURNAL_MIN_COMPACTION_INTERVAL: Duration = Duration::from_secs(1);
+
+const RECORD_HEADER_SIZE: usize = 8;
+
+const PARTITIONS_EXPIRATION_RECORD: u8 = 0;
+const PARTITIONS_LAST_READ_TIME_RECORD: u8 = 1;
+const ROWS_EXPIRATION_TIME_RECORD: u8 = 2;
+const ROWS_LAST_READ_TIME_RECORD: u8 = 3;
+// Rows expiration with expiration time per row. Record 2 has one expiration time for all the rows
+const ROWS_EXPIRATION_TIMES_RECORD: u8 = 4;
+// Last read time with read moment per key. Records 1 and 3 have keys only
+const PARTITIONS_READ_MOMENTS_RECORD: u8 = 5;
+const ROWS_READ_MOMENTS_RECORD: u8 = 6;
+
+#[derive(Debug, Clone)]
+pub(crate) enum SyncJournalRecord {
+    PartitionsExpiration(UpdatePartitionExpirationEvent),
+    PartitionsLastReadTime(UpdatePartitionsLastReadTimeEvent),
+    RowsExpirationTime(UpdateRowsExpirationTimeEvent),
+    RowsLastReadTime(UpdateRowsLastReadTimeEvent),
+}
+
+impl SyncJournalRecord {
+    fn serialize(&self, buffer: &mut Vec<u8>) {
+        match self {
+            SyncJournalRecord::PartitionsExpiration(event) => {
+                buffer.push(PARTITIONS_EXPIRATION_RECORD);
+                crate::common_serializers::serialize_pascal_string(buffer, &event.table_name);
+                crate::common_serializers::serialize_i32(buffer, event.partitions.len() as i32);
+
+                for (partition_key, expiration_time) in &event.partitions {
+                    crate::common_serializers::serialize_pascal_string(buffer, partition_key);
+                    crate::common_serializers::serialize_date_time_opt(buffer, *expiration_time);
+                }
+            }
+            SyncJournalRecord::PartitionsLastReadTime(event) => {
+                buffer.push(PARTITIONS_READ_MOMENTS_RECORD);
+                crate::common_serializers::serialize_pascal_string(buffer, &event.table_name);
+                serialize_read_moments(buffer, &event.partitions);
+            }
+            SyncJournalRecord::RowsExpirationTime(event) => {
+                buffer.push(ROWS_EXPIRATION_TIMES_RECORD);
+                crate::common_serializers::serialize_pascal_string(buffer, &event.table_name);
+                crate::common_serializers::serialize_pascal_string(buffer, &event.partition_key);
+                crate::common_serializers::serialize_i32(buffer, event.row_keys.len() as i32);
+
+                for (row_key, expiration_time) in &event.row_keys {
+                    crate::common_serializers::serialize_pascal_string(buffer, row_key);
+                    crate::common_serializers::serialize_date_time_opt(buffer, *expiration_time);
+                }
+            }
+            SyncJournalRecord::RowsLastReadTime(event) => {
+                buffer.push(ROWS_READ_MOMENTS_RECORD);
+                crate::common_serializers::serialize_pascal_string(buffer, &event.table_name);
+                crate::common_serializers::serialize_pascal_string(buffer, &event.partition_key);
+                serialize_read_moments(buffer, &event.row_keys);
+            }
+        }
+    }
+
+    fn deserialize(payload: &[u8]) -> Option<Self> {
+        let mut reader = JournalRecordReader { payload, pos: 0 };
+
+        let result = match reader.read_byte()? {
+            PARTITIONS_EXPIRATION_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let amount = reader.read_len()?;
+
+                let mut partitions = HashMap::new();
+
+                for _ in 0..amount {
+                    let partition_key = reader.read_pascal_string()?;
+                    let expiration_time = reader.read_date_time_opt()?;
+                    partitions.insert(partition_key, expiration_time);
+                }
+
+                SyncJournalRecord::PartitionsExpiration(UpdatePartitionExpirationEvent {
+                    table_name,
+                    partitions,
+                })
+            }
+            // Read moment was not journaled, so the key is treated as read at recovery
+            PARTITIONS_LAST_READ_TIME_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let partitions = reader.read_keys()?;
+
+                SyncJournalRecord::PartitionsLastReadTime(UpdatePartitionsLastReadTimeEvent {
+                    table_name,
+                    partitions: with_read_moment_now(partitions),
+                })
+            }
+            PARTITIONS_READ_MOMENTS_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let partitions = reader.read_read_moments()?;
+
+                SyncJournalRecord::PartitionsLastReadTime(UpdatePartitionsLastReadTimeEvent {
+                    table_name,
+                    partitions,
+                })
+            }
+            ROWS_EXPIRATION_TIME_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let partition_key = reader.read_pascal_string()?;
+                let expiration_time = reader.read_date_time_opt()?;
+                let row_keys = reader.read_keys()?;
+
+                SyncJournalRecord::RowsExpirationTime(UpdateRowsExpirationTimeEvent {
+                    table_name,
+                    partition_key,
+                    row_keys: row_keys
+                        .into_keys()
+                        .map(|row_key| (row_key, expiration_time))
+                        .collect(),
+                })
+            }
+            ROWS_EXPIRATION_TIMES_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let partition_key = reader.read_pascal_string()?;
+                let amount = reader.read_len()?;
+
+                let mut row_keys = HashMap::new();
+
+                for _ in 0..amount {
+                    let row_key = reader.read_pascal_string()?;
+                    let expiration_time = reader.read_date_time_opt()?;
+                    row_keys.insert(row_key, expiration_time);
+                }
+
+                SyncJournalRecord::RowsExpirationTime(UpdateRowsExpirationTimeEvent {
+                    table_name,
+                    partition_key,
+                    row_keys,
+                })
+            }
+            ROWS_LAST_READ_TIME_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let partition_key = reader.read_pascal_string()?;
+                let row_keys = reader.read_keys()?;
+
+                SyncJournalRecord::RowsLastReadTime(UpdateRowsLastReadTimeEvent {
+                    table_name,
+                    partition_key,
+                    row_keys: with_read_moment_now(row_keys),
+                })
+            }
+            ROWS_READ_MOMENTS_RECORD => {
+                let table_name = reader.read_pascal_string()?;
+                let partition_key = reader.read_pascal_string()?;
+                let row_keys = reader.read_read_moments()?;
+
+                SyncJournalRecord::RowsLastReadTime(UpdateRowsLastReadTimeEvent {
+                    table_name,
+                    partition_key,
+                    row_keys,
+                })
+            }
+            _ => return None,
+        };
+
+        if reader.pos != payload.len() {
+            return None;
+        }
+
+        Some(result)
+    }
+}
+
+enum JournalCommand {
+    Append(Vec<u8>),
+    // Journal is replaced with the content: compaction and clearing
+    Rewrite(Vec<u8>),
+    Flush(tokio::sync::oneshot::Sender<std::io::Result<()>>),
+}
+
+// Append-only file with the updates which are not confirmed by main node yet.
+// Every record is [len: u32][crc32c: u32][payload]. Records after the first damaged one are dropped on recovery,
+// intact records of unknown type are skipped.
+//
+// File is written by a dedicated thread, so update() never waits for the disk. Records which are queued
+// together are synced with one sync_data. Update is on disk once the writer has synced it, usually within
+// milliseconds. Updates which are still queued for the writer are lost if the process or the host dies.
+// Dropping the journal waits until everything queued is written
+pub struct SyncQueuesJournal {
+    path: PathBuf,
+    sender: Option<mpsc::Sender<JournalCommand>>,
+    writer: Option<JoinHandle<()>>,
+    size: u64,
+    max_size: u64,
+    min_compaction_interval: Duration,
+    compacted_size: u64,
+    compacted_at: Instant,
+    // Pending updates do not fit into max_size. Nothing is appended until a confirmation frees space
+    overflowed: bool,
+    // Journal has updates which were dropped from the queues
+    has_dropped: bool,
+    recovered: Vec<SyncJournalRecord>,
+}
+
+impl SyncQueuesJournal {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let recovered = match File::open(&path) {
+            Ok(mut file) => {
+                let mut content = Vec::new();
+                file.read_to_end(&mut content)?;
+                recover_records(&path, content.as_slice())?
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        // Damaged tail is cut off by rewriting the journal with the records we could read
+        let (content, overflowed) =
+            serialize_records(recovered.iter(), DEFAULT_SYNC_JOURNAL_MAX_SIZE);
+        let size = get_file_size(content.as_slice());
+
+        let file = write_journal_file(&path, content.as_slice())?;
+
+        let (sender, receiver) = mpsc::channel();
+
+        let writer = {
+            let path = path.clone();
+            std::thread::Builder::new()
+                .name("my-no-sql-sync-journal".to_string())
+                .spawn(move || write_loop(path, file, receiver))?
+        };
+
+        let result = Self {
+            path,
+            sender: Some(sender),
+            writer: Some(writer),
+            size,
+            max_size: DEFAULT_SYNC_JOURNAL_MAX_SIZE,
+            min_compaction_interval: DEFAULT_SYNC_JOURNAL_MIN_COMPACTION_INTERVAL,
+            compacted_size: size,
+            compacted_at: Instant::now(),
+            overflowed,
+            has_dropped: false,
+            recovered,
+        };
+
+        if overflowed {
+            result.on_overflowed();
+        }
+
+        Ok(result)
+    }
+
+    // Updates which do not fit into the journal are still delivered, but are lost on restart
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    // Overflowed journal or journal with dropped updates is not rewritten more often than this
+    pub fn with_min_compaction_interval(mut self, min_compaction_interval: Duration) -> Self {
+        self.min_compaction_interval = min_compaction_interval;
+        self
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    // Size of the journal including the records which are queued for the writer
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    // Completes when everything queued before is written and synced
+    pub fn flush(&self) -> impl std::future::Future<Output = std::io::Result<()>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let sent = self.send(JournalCommand::Flush(sender));
+
+        async move {
+            if !sent {
+                return Err(writer_is_stopped());
+            }
+
+            match receiver.await {
+                Ok(result) => result,
+                Err(_) => Err(writer_is_stopped()),
+            }
+        }
+    }
+
+    pub(crate) fn take_recovered(&mut self) -> Vec<SyncJournalRecord> {
+        std::mem::take(&mut self.recovered)
+    }
+
+    // Dropped updates are removed from the journal with the next compaction
+    pub(crate) fn on_updates_dropped(&mut self) {
+        self.has_dropped = true;
+    }
+
+    pub(crate) fn should_compact(&self) -> bool {
+        if self.overflowed || self.has_dropped {
+            return self.compacted_at.elapsed() >= self.min_compaction_interval;
+        }
+
+        self.size >= (self.compacted_size * 2).max(MIN_COMPACTION_SIZE)
+    }
+
+    // Returns false if the record does not fit. Journal has to be compacted then.
+    // Journal which was compacted recently is marked as overflowed instead, so we do not rewrite it on every update
+    pub(crate) fn append(&mut self, record: &SyncJournalRecord) -> bool {
+        if self.overflowed {
+            return true;
+        }
+
+        let buffer = serialize_record(record);
+
+        if self.size + buffer.len() as u64 > self.max_size {
+            if self.compacted_at.elapsed() >= self.min_compaction_interval {
+                return false;
+            }
+
+            self.on_overflowed();
+            self.overflowed = true;
+            return true;
+        }
+
+        self.size += buffer.len() as u64;
+        self.send(JournalCommand::Append(buffer));
+        true
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.rewrite(Vec::new(), false);
+    }
+
+    // Journal is replaced with the records of the events which are still pending.
+    // New file is written aside and renamed, so we never end up without the journal
+    pub(crate) fn compact<'s>(&mut self, records: impl Iterator<Item = &'s SyncJournalRecord>) {
+        let (content, overflowed) = serialize_records(records, self.max_size);
+        self.rewrite(content, overflowed);
+    }
+
+    fn rewrite(&mut self, content: Vec<u8>, overflowed: bool) {
+        self.size = get_file_size(content.as_slice());
+        self.compacted_size = self.size;
+        self.compacted_at = Instant::now();
+
+        if overflowed && !self.overflowed {
+            self.on_overflowed();
+        }
+
+        self.overflowed = overflowed;
+        self.has_dropped = false;
+        self.send(JournalCommand::Rewrite(content));
+    }
+
+    fn on_overflowed(&self) {
+        println!(
+            "Sync journal {:?} exceeds max size {}. New updates are kept in memory only until main node confirms the pending ones",
+            self.path, self.max_size
+        );
+    }
+
+    fn send(&self, command: JournalCommand) -> bool {
+        match &self.sender {
+            Some(sender) => sender.send(command).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl Drop for SyncQueuesJournal {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn writer_is_stopped() -> std::io::Error {
+    std::io::Error::new(ErrorKind::BrokenPipe, "Sync journal writer is stopped")
+}
+
+fn get_file_size(content: &[u8]) -> u64 {
+    (SYNC_JOURNAL_FILE_MAGIC.len() + 1 + content.len()) as u64
+}
+
+// Returns true as the second value if not all the records fit into max_size
+fn serialize_records<'s>(
+    records: impl Iterator<Item = &'s SyncJournalRecord>,
+    max_size: u64,
+) -> (Vec<u8>, bool) {
+    let mut result = Vec::new();
+
+    for record in records {
+        let buffer = serialize_record(record);
+
+        if get_file_size(result.as_slice()) + buffer.len() as u64 > max_size {
+            return (result, true);
+        }
+
+        result.extend_from_slice(buffer.as_slice());
+    }
+
+    (result, false)
+}
+
+// Loop ends when the journal is dropped. Everything which is queued is written before that
+fn write_loop(path: PathBuf, mut file: File, receiver: mpsc::Receiver<JournalCommand>) {
+    while let Ok(command) = receiver.recv() {
+        let mut commands = vec![command];
+
+        // Commands which are queued meanwhile are synced together
+        while let Ok(command) = receiver.try_recv() {
+            commands.push(command);
+        }
+
+        let mut flushes = Vec::new();
+        let mut result = Ok(());
+        let mut appended = false;
+
+        for command in commands {
+            match command {
+                JournalCommand::Append(buffer) => {
+                    if let Err(err) = file.write_all(buffer.as_slice()) {
+                        println!("Can not append to sync journal {:?}. Err: {:?}", path, err);
+                        result = Err(err);
+                    }
+
+                    appended = true;
+                }
+                JournalCommand::Rewrite(content) => {
+                    match write_journal_file(&path, content.as_slice()) {
+                        Ok(new_file) => file = new_file,
+                        Err(err) => {
+                            println!("Can not rewrite sync journal {:?}. Err: {:?}", path, err);
+                            result = Err(err);
+                        }
+                    }
+
+                    appended = false;
+                }
+                JournalCommand::Flush(sender) => flushes.push(sender),
+            }
+        }
+
+        // Rewritten file is synced before it is renamed
+        if appended {
+            if let Err(err) = file.sync_data() {
+                println!("Can not sync sync journal {:?}. Err: {:?}", path, err);
+                result = Err(err);
+            }
+        }
+
+        for flush in flushes {
+            let flush_result = match &result {
+                Ok(_) => Ok(()),
+                Err(err) => Err(std::io::Error::new(err.kind(), err.to_string())),
+            };
+
+            let _ = flush.send(flush_result);
+        }
+    }
+}
+
+fn write_journal_file(path: &Path, content: &[u8]) -> std::io::Result<File> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(SYNC_JOURNAL_FILE_MAGIC)?;
+        file.write_all(&[SYNC_JOURNAL_FILE_VERSION])?;
+        file.write_all(content)?;
+        file.sync_all()?;
+    }
+
+    std::fs::rename(&tmp_path, path)?;
+
+    OpenOptions::new().append(true).open(path)
+}
+
+// File which is not a journal or is written by a newer version is refused, so it is not overwritten
+fn recover_records(path: &Path, content: &[u8]) -> std::io::Result<Vec<SyncJournalRecord>> {
+    let mut result = Vec::new();
+
+    if content.is_empty() {
+        return Ok(result);
+    }
+
+    let header_len = SYNC_JOURNAL_FILE_MAGIC.len() + 1;
+
+    if content.len() < header_len || &content[..4] != SYNC_JOURNAL_FILE_MAGIC {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?} is not a sync journal", path),
+        ));
+    }
+
+    if content[4] > SYNC_JOURNAL_FILE_VERSION {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Sync journal {:?} has version {}. Versions up to {} are supported",
+                path, content[4], SYNC_JOURNAL_FILE_VERSION
+            ),
+        ));
+    }
+
+    let mut pos = header_len;
+    let mut skipped = 0;
+
+    while pos < content.len() {
+        match read_record(&content[pos..]) {
+            JournalEntry::Record(record, record_len) => {
+                result.push(record);
+                pos += record_len;
+            }
+            JournalEntry::Unknown(record_len) => {
+                skipped += 1;
+                pos += record_len;
+            }
+            JournalEntry::Damaged => {
+                println!(
+                    "Sync journal {:?} is damaged at offset {}. Recovered {} records, {} bytes are dropped",
+                    path,
+                    pos,
+                    result.len(),
+                    content.len() - pos
+                );
+                break;
+            }
+        }
+    }
+
+    if skipped > 0 {
+        println!(
+            "Sync journal {:?} has {} records this version can not read. They are skipped",
+            path, skipped
+        );
+    }
+
+    Ok(result)
+}
+
+enum JournalEntry {
+    Record(SyncJournalRecord, usize),
+    // Record is intact, but its type is not known to this version
+    Unknown(usize),
+    Damaged,
+}
+
+fn read_record(content: &[u8]) -> JournalEntry {
+    if content.len() < RECORD_HEADER_SIZE {
+        return JournalEntry::Damaged;
+    }
+
+    let payload_len = u32::from_le_bytes([content[0], content[1], content[2], content[3]]) as usize;
+    let checksum = u32::from_le_bytes([content[4], content[5], content[6], content[7]]) as u64;
+
+    let payload = match content.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload_len) {
+        Some(payload) => payload,
+        None => return JournalEntry::Damaged,
+    };
+
+    if ChecksumAlgorithm::Crc32c.calculate(payload) != Some(checksum) {
+        return JournalEntry::Damaged;
+    }
+
+    match SyncJournalRecord::deserialize(payload) {
+        Some(record) => JournalEntry::Record(record, RECORD_HEADER_SIZE + payload_len),
+        None => JournalEntry::Unknown(RECORD_HEADER_SIZE + payload_len),
+    }
+}
+
+fn serialize_record(record: &SyncJournalRecord) -> Vec<u8> {
+    let mut payload = Vec::new();
+    record.serialize(&mut payload);
+
+    let checksum = ChecksumAlgorithm::Crc32c
+        .calculate(payload.as_slice())
+        .unwrap_or_default() as u32;
+
+    let mut result = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    result.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    result.extend_from_slice(&checksum.to_le_bytes());
+    result.extend_from_slice(payload.as_slice());
+    result
+}
+
+fn serialize_read_moments(buffer: &mut Vec<u8>, keys: &HashMap<String, DateTimeAsMicroseconds>) {
+    crate::common_serializers::serialize_i32(buffer, keys.len() as i32);
+
+    for (key, read_moment) in keys {
+        crate::common_serializers::serialize_pascal_string(buffer, key);
+        crate::common_serializers::serialize_i64(buffer, read_moment.unix_microseconds);
+    }
+}
+
+fn with_read_moment_now(keys: HashMap<String, ()>) -> HashMap<String, DateTimeAsMicroseconds> {
+    let now = DateTimeAsMicroseconds::now();
+    keys.into_keys().map(|key| (key, now)).collect()
+}
+
+struct JournalRecordReader<'s> {
+    payload: &'s [u8],
+    pos: usize,
+}
+
+impl<'s> JournalRecordReader<'s> {
+    fn read_slice(&mut self, len: usize) -> Option<&'s [u8]> {
+        let result = self.payload.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(result)
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        Some(self.read_slice(1)?[0])
+    }
+
+    fn read_len(&mut self) -> Option<usize> {
+        let len = i32::from_le_bytes(self.read_slice(4)?.try_into().ok()?);
+        usize::try_from(len).ok()
+    }
+
+    fn read_date_time_opt(&mut self) -> Option<Option<DateTimeAsMicroseconds>> {
+        let unix_microseconds = i64::from_le_bytes(self.read_slice(8)?.try_into().ok()?);
+
+        if unix_microseconds == 0 {
+            Some(None)
+        } else {
+            Some(Some(DateTimeAsMicroseconds::new(unix_microseconds)))
+        }
+    }
+
+    fn read_pascal_string(&mut self) -> Option<String> {
+        let len = self.read_byte()? as usize;
+        String::from_utf8(self.read_slice(len)?.to_vec()).ok()
+    }
+
+    fn read_read_moments(&mut self) -> Option<HashMap<String, DateTimeAsMicroseconds>> {
+        let amount = self.read_len()?;
+
+        let mut result = HashMap::new();
+
+        for _ in 0..amount {
+            let key = self.read_pascal_string()?;
+            let read_moment = i64::from_le_bytes(self.read_slice(8)?.try_into().ok()?);
+            result.insert(key, DateTimeAsMicroseconds::new(read_moment));
+        }
+
+        Some(result)
+    }
+
+    fn read_keys(&mut self) -> Option<HashMap<String, ()>> {
+        let amount = self.read_len()?;
+
+        let mut result = HashMap::new();
+
+        for _ in 0..amount {
+            result.insert(self.read_pascal_string()?, ());
+        }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "my-no-sql-sync-journal-{}-{}",
+            std::process::id(),
+            name
+        ));
+
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn create_record(table_name: &str) -> SyncJournalRecord {
+        let mut partitions = HashMap::new();
+        partitions.insert("pk".to_string(), DateTimeAsMicroseconds::new(1_000_000));
+
+        SyncJournalRecord::PartitionsLastReadTime(UpdatePartitionsLastReadTimeEvent {
+            table_name: table_name.to_string(),
+            partitions,
+        })
+    }
+
+    fn serialize_recovered(journal: &mut SyncQueuesJournal) -> Vec<Vec<u8>> {
+        journal
+            .take_recovered()
+            .iter()
+            .map(serialize_record)
+            .collect()
+    }
+
+    fn write_file(path: &Path, version: u8, records: &[Vec<u8>]) {
+        let mut content = SYNC_JOURNAL_FILE_MAGIC.to_vec();
+        content.push(version);
+
+        for record in records {
+            content.extend_from_slice(record.as_slice());
+        }
+
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn appended_records_are_recovered_after_reopen() {
+        let path = get_journal_path("reopen");
+
+        {
+            let mut journal = SyncQueuesJournal::open(&path).unwrap();
+            assert!(journal.append(&create_record("table-1")));
+            assert!(journal.append(&create_record("table-2")));
+        }
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        assert_eq!(
+            serialize_recovered(&mut journal),
+            vec![
+                serialize_record(&create_record("table-1")),
+                serialize_record(&create_record("table-2"))
+            ]
+        );
+
+        assert_eq!(journal.get_size(), std::fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn damaged_tail_is_cut_off() {
+        let path = get_journal_path("damaged-tail");
+
+        {
+            let mut journal = SyncQueuesJournal::open(&path).unwrap();
+            journal.append(&create_record("table-1"));
+        }
+
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        assert_eq!(serialize_recovered(&mut journal).len(), 1);
+        assert_eq!(journal.get_size(), size);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+    }
+
+    #[test]
+    fn recovery_stops_at_record_with_wrong_checksum() {
+        let path = get_journal_path("wrong-checksum");
+
+        let mut damaged = serialize_record(&create_record("table-2"));
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+
+        write_file(
+            &path,
+            SYNC_JOURNAL_FILE_VERSION,
+            &[
+                serialize_record(&create_record("table-1")),
+                damaged,
+                serialize_record(&create_record("table-3")),
+            ],
+        );
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        assert_eq!(
+            serialize_recovered(&mut journal),
+            vec![serialize_record(&create_record("table-1"))]
+        );
+    }
+
+    #[test]
+    fn intact_record_of_unknown_type_is_skipped() {
+        let path = get_journal_path("unknown-record");
+
+        let payload = [200u8, 1, 2, 3];
+        let checksum = ChecksumAlgorithm::Crc32c.calculate(&payload).unwrap() as u32;
+
+        let mut unknown = Vec::new();
+        unknown.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        unknown.extend_from_slice(&checksum.to_le_bytes());
+        unknown.extend_from_slice(&payload);
+
+        write_file(
+            &path,
+            SYNC_JOURNAL_FILE_VERSION,
+            &[unknown, serialize_record(&create_record("table-1"))],
+        );
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        assert_eq!(
+            serialize_recovered(&mut journal),
+            vec![serialize_record(&create_record("table-1"))]
+        );
+    }
+
+    #[test]
+    fn journal_of_previous_version_is_recovered() {
+        let path = get_journal_path("previous-version");
+
+        write_file(&path, 0, &[serialize_record(&create_record("table-1"))]);
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        assert_eq!(serialize_recovered(&mut journal).len(), 1);
+    }
+
+    #[test]
+    fn journal_of_newer_version_is_refused_and_kept() {
+        let path = get_journal_path("newer-version");
+
+        write_file(
+            &path,
+            SYNC_JOURNAL_FILE_VERSION + 1,
+            &[serialize_record(&create_record("table-1"))],
+        );
+
+        let content = std::fs::read(&path).unwrap();
+
+        let err = SyncQueuesJournal::open(&path).err().unwrap();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn file_which_is_not_a_journal_is_refused_and_kept() {
+        let path = get_journal_path("not-a-journal");
+
+        std::fs::write(&path, b"some other file").unwrap();
+
+        let err = SyncQueuesJournal::open(&path).err().unwrap();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&path).unwrap(), b"some other file");
+    }
+
+    #[test]
+    fn compacted_journal_keeps_only_given_records() {
+        let path = get_journal_path("compaction");
+
+        {
+            let mut journal = SyncQueuesJournal::open(&path).unwrap();
+            journal.append(&create_record("table-1"));
+            journal.append(&create_record("table-2"));
+            journal.compact([create_record("table-2")].iter());
+        }
+
+        let mut journal = SyncQueuesJournal::open(&path).unwrap();
+
+        assert_eq!(
+            serialize_recovered(&mut journal),
+            vec![serialize_record(&create_record("table-2"))]
+        );
+    }
+
+    #[test]
+    fn overflowed_journal_stops_appending_until_cleared() {
+        let path = get_journal_path("overflow");
+
+        let record_size = serialize_record(&create_record("table-1")).len() as u64;
+
+        let mut journal = SyncQueuesJournal::open(&path)
+            .unwrap()
+            .with_max_size(get_file_size(&[]) + record_size);
+
+        assert!(journal.append(&create_record("table-1")));
+        assert!(!journal.is_overflowed());
+
+        // Journal is compacted just now, so it is not rewritten again
+        assert!(journal.append(&create_record("table-2")));
+        assert!(journal.is_overflowed());
+        assert!(!journal.should_compact());
+
+        let size = journal.get_size();
+        assert!(journal.append(&create_record("table-3")));
+        assert_eq!(journal.get_size(), size);
+
+        journal.clear();
+        assert!(!journal.is_overflowed());
+
+        // Compaction which does not fit marks journal as overflowed
+        journal.compact([create_record("table-1"), create_record("table-2")].iter());
+        assert!(journal.is_overflowed());
+        assert!(journal.get_size() <= get_file_size(&[]) + record_size);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::{
+    PARTITIONS_EXPIRATION_QUEUE_NAME, PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+    ROWS_EXPIRATION_QUEUE_NAME, ROWS_LAST_READ_TIME_QUEUE_NAME,
+};
+
+pub const DEFAULT_SYNC_QUEUE_WEIGHT: usize = 1;
+
+// Order in which strict priority scheduler drains the queues
+pub const SYNC_QUEUES_PRIORITY: [&str; 4] = [
+    PARTITIONS_EXPIRATION_QUEUE_NAME,
+    PARTITIONS_LAST_READ_TIME_QUEUE_NAME,
+    ROWS_EXPIRATION_QUEUE_NAME,
+    ROWS_LAST_READ_TIME_QUEUE_NAME,
+];
+
+pub(crate) struct QueuedEvent<TEvent> {
+    pub event: TEvent,
+    pub enqueued: Instant,
+}
+
+impl<TEvent> QueuedEvent<TEvent> {
+    pub fn new(event: TEvent) -> Self {
+        Self {
+            event,
+            enqueued: Instant::now(),
+        }
+    }
+
+    // Returned event was queued before the events which are still in the queue
+    pub fn new_returned(event: TEvent, queue_front: Option<&Self>) -> Self {
+        Self {
+            event,
+            enqueued: queue_front
+                .map(|itm| itm.enqueued)
+                .unwrap_or_else(Instant::now),
+        }
+    }
+}
+
+// Queue which has an event ready to be delivered
+#[derive(Debug, Clone, Copy)]
+pub struct SyncQueueCandidate {
+    pub queue_name: &'static str,
+    // When the event which is going to be delivered was queued
+    pub enqueued: Instant,
+    pub entries: usize,
+}
+
+impl SyncQueueCandidate {
+    // Event is held back until it is old enough or big enough to be delivered
+    pub fn is_ready(
+        &self,
+        flush_interval: Option<Duration>,
+        max_batch_size: Option<usize>,
+    ) -> bool {
+        let flush_interval = match flush_interval {
+            Some(flush_interval) => flush_interval,
+            None => return true,
+        };
+
+        self.enqueued.elapsed() >= flush_interval
+            || max_batch_size
+                .map(|max_batch_size| self.entries >= max_batch_size)
+                .unwrap_or(false)
+    }
+}
+
+// Picks the queue the next event is delivered from.
+// Candidates are never empty and are ordered by SYNC_QUEUES_PRIORITY
+pub trait SyncQueuesScheduler: Send + Sync + 'static {
+    fn pick_queue(&mut self, candidates: &[SyncQueueCandidate]) -> &'static str;
+}
+
+// Queue is drained only when all the queues with higher priority are empty
+pub struct StrictPriorityScheduler;
+
+impl StrictPriorityScheduler {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl SyncQueuesScheduler for StrictPriorityScheduler {
+    fn pick_queue(&mut self, candidates: &[SyncQueueCandidate]) -> &'static str {
+        candidates[0].queue_name
+    }
+}
+
+// Smooth weighted round robin: queue with weight 3 gets 3 deliveries for every delivery of the queue with weight 1
+pub struct WeightedRoundRobinScheduler {
+    weights: HashMap<&'static str, usize>,
+    current: HashMap<&'static str, i64>,
+}
+
+impl WeightedRoundRobinScheduler {
+    pub fn new() -> Self {
+        Self {
+            weights: HashMap::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    pub fn with_weight(mut self, queue_name: &'static str, weight: usize) -> Self {
+        self.weights.insert(queue_name, weight.max(1));
+        self
+    }
+
+    pub fn get_weight(&self, queue_name: &str) -> usize {
+        self.weights
+            .get(queue_name)
+            .copied()
+            .unwrap_or(DEFAULT_SYNC_QUEUE_WEIGHT)
+    }
+}
+
+impl SyncQueuesScheduler for WeightedRoundRobinScheduler {
+    fn pick_queue(&mut self, candidates: &[SyncQueueCandidate]) -> &'static str {
+        let mut total_weight = 0;
+        let mut result = candidates[0].queue_name;
+        let mut result_current = i64::MIN;
+
+        for candidate in candidates {
+            let weight = self.get_weight(candidate.queue_name) as i64;
+            total_weight += weight;
+
+            let current = self.current.entry(candidate.queue_name).or_default();
+            *current += weight;
+
+            if *current > result_current {
+                result = candidate.queue_name;
+                result_current = *current;
+            }
+        }
+
+        if let Some(current) = self.current.get_mut(result) {
+            *current -= total_weight;
+        }
+
+        result
+    }
+}
+
+// Event which waits the longest is delivered first, regardless of its queue
+pub struct OldestFirstScheduler;
+
+impl OldestFirstScheduler {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl SyncQueuesScheduler for OldestFirstScheduler {
+    fn pick_queue(&mut self, candidates: &[SyncQueueCandidate]) -> &'static str {
+        candidates
+            .iter()
+            .min_by_key(|itm| itm.enqueued)
+            .map(|itm| itm.queue_name)
+            .unwrap_or(candidates[0].queue_name)
+    }
+}
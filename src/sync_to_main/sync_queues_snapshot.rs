@@ -0,0 +1,152 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use super::SyncQueueUsage;
+
+#[derive(Debug, Clone)]
+pub struct SyncTableSnapshot {
+    pub table_name: String,
+    // Row queues count the partitions which have pending rows
+    pub pending_partitions: usize,
+    pub pending_rows: usize,
+    pub oldest_pending_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncQueueSnapshot {
+    pub queue_name: &'static str,
+    pub usage: SyncQueueUsage,
+    pub oldest_pending_age: Option<Duration>,
+    // Ordered by table name
+    pub tables: Vec<SyncTableSnapshot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightEventSnapshot {
+    pub confirmation_id: i64,
+    pub queue_name: &'static str,
+    // Batch is reported by its first table
+    pub table_name: String,
+    pub entries: usize,
+    pub sent_at: DateTimeAsMicroseconds,
+    pub waited: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncQueuesSnapshot {
+    pub queues: Vec<SyncQueueSnapshot>,
+    // Ordered by confirmation id
+    pub in_flight: Vec<InFlightEventSnapshot>,
+    pub last_confirmed_id: Option<i64>,
+    pub connected: bool,
+    pub main_node_supports_batches: bool,
+    pub main_node_supports_read_moments: bool,
+    pub main_node_supports_row_expirations: bool,
+    pub timeouts_in_row: usize,
+}
+
+impl SyncQueuesSnapshot {
+    pub fn get_pending_entries(&self) -> usize {
+        self.queues.iter().map(|itm| itm.usage.entries).sum()
+    }
+
+    // Age of the oldest update which is not delivered yet, in flight updates included
+    pub fn get_oldest_pending_age(&self) -> Option<Duration> {
+        self.queues
+            .iter()
+            .filter_map(|itm| itm.oldest_pending_age)
+            .chain(self.in_flight.iter().map(|itm| itm.waited))
+            .max()
+    }
+}
+
+// Pending entries of the queue per table. Kept up to date on every change of the queue,
+// so the snapshot does not walk the queued events
+#[derive(Debug, Default)]
+pub(crate) struct SyncTablesUsage {
+    tables: BTreeMap<String, SyncTableUsage>,
+}
+
+#[derive(Debug, Default)]
+struct SyncTableUsage {
+    partitions: usize,
+    rows: usize,
+    // Moments the events of the table were queued at and amount of events queued at each
+    enqueued: BTreeMap<Instant, usize>,
+}
+
+impl SyncTablesUsage {
+    pub fn on_added(
+        &mut self,
+        table_name: &str,
+        enqueued: Instant,
+        partitions: usize,
+        rows: usize,
+    ) {
+        if !self.tables.contains_key(table_name) {
+            self.tables
+                .insert(table_name.to_string(), SyncTableUsage::default());
+        }
+
+        let table = self.tables.get_mut(table_name).unwrap();
+
+        table.partitions += partitions;
+        table.rows += rows;
+        *table.enqueued.entry(enqueued).or_default() += 1;
+    }
+
+    // Entries are merged into the event which is already queued
+    pub fn on_entries_added(&mut self, table_name: &str, partitions: usize, rows: usize) {
+        if let Some(table) = self.tables.get_mut(table_name) {
+            table.partitions += partitions;
+            table.rows += rows;
+        }
+    }
+
+    pub fn on_removed(
+        &mut self,
+        table_name: &str,
+        enqueued: Instant,
+        partitions: usize,
+        rows: usize,
+    ) {
+        let Some(table) = self.tables.get_mut(table_name) else {
+            return;
+        };
+
+        table.partitions -= partitions;
+        table.rows -= rows;
+
+        if let Some(amount) = table.enqueued.get_mut(&enqueued) {
+            *amount -= 1;
+
+            if *amount == 0 {
+                table.enqueued.remove(&enqueued);
+            }
+        }
+
+        if table.enqueued.is_empty() {
+            self.tables.remove(table_name);
+        }
+    }
+
+    pub fn get_snapshot(&self) -> Vec<SyncTableSnapshot> {
+        self.tables
+            .iter()
+            .map(|(table_name, table)| SyncTableSnapshot {
+                table_name: table_name.to_string(),
+                pending_partitions: table.partitions,
+                pending_rows: table.rows,
+                oldest_pending_age: table
+                    .enqueued
+                    .keys()
+                    .next()
+                    .map(|enqueued| enqueued.elapsed()),
+            })
+            .collect()
+    }
+}
@@ -1,10 +1,14 @@
 use std::sync::Arc;
 
-use super::DataReaderTcpConnection;
+use super::MainNodeConnection;
 
 pub enum SyncToMainNodeEvent {
-    Connected(Arc<DataReaderTcpConnection>),
-    Disconnected(Arc<DataReaderTcpConnection>),
+    Connected(Arc<dyn MainNodeConnection>),
+    Disconnected(Arc<dyn MainNodeConnection>),
     PingToDeliver,
     Delivered(i64),
+    CheckDeliveryTimeouts,
+    SyncBatchesSupported(bool),
+    ReadMomentsSupported(bool),
+    RowExpirationsSupported(bool),
 }
@@ -1,10 +1,15 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use rust_extensions::{events_loop::EventsLoopTick, ApplicationStates, Logger};
 
-use crate::sync_to_main::DeliverToMainNodeEvent;
+use crate::{
+    metrics::ProtocolMetrics, sync_to_main::DeliverToMainNodeEvent, ExpirationTimeBatchTcpContract,
+    LastReadTimeBatchTcpContract,
+};
 
-use super::{DataReaderTcpConnection, SyncToMainNodeEvent, SyncToMainNodeQueues};
+use super::{MainNodeConnection, SyncToMainNodeEvent, SyncToMainNodeQueues};
+
+const FLUSH_TIMER_IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct SyncToMainNodeHandler {
     pub event_notifier: Arc<SyncToMainNodeQueues>,
@@ -17,12 +22,37 @@ impl SyncToMainNodeHandler {
         }
     }
 
+    pub fn new_with_metrics(metrics: Arc<dyn ProtocolMetrics>) -> Self {
+        Self {
+            event_notifier: Arc::new(SyncToMainNodeQueues::new_with_metrics(metrics)),
+        }
+    }
+
+    pub fn new_with_queues(queues: SyncToMainNodeQueues) -> Self {
+        Self {
+            event_notifier: Arc::new(queues),
+        }
+    }
+
     pub async fn start(
         &self,
         app_states: Arc<impl ApplicationStates + Send + Sync + 'static>,
         logger: Arc<impl Logger + Send + Sync + 'static>,
     ) {
-        let event_loop = SyncToMainNodeEventLoop::new(self.event_notifier.clone());
+        if let Some(confirmation_timeout) = self.event_notifier.get_confirmation_timeout() {
+            tokio::spawn(delivery_timeouts_timer(
+                self.event_notifier.clone(),
+                app_states.clone(),
+                confirmation_timeout,
+            ));
+        }
+
+        if self.event_notifier.get_flush_interval().is_some() {
+            tokio::spawn(flush_timer(self.event_notifier.clone(), app_states.clone()));
+        }
+
+        let event_loop =
+            SyncToMainNodeEventLoop::new(self.event_notifier.clone()).with_logger(logger.clone());
         self.event_notifier
             .event_loop
             .register_event_loop(Arc::new(event_loop))
@@ -35,7 +65,7 @@ impl SyncToMainNodeHandler {
 
     pub fn tcp_events_pusher_new_connection_established(
         &self,
-        connection: Arc<DataReaderTcpConnection>,
+        connection: Arc<dyn MainNodeConnection>,
     ) {
         self.event_notifier
             .event_loop
@@ -44,7 +74,7 @@ impl SyncToMainNodeHandler {
 
     pub fn tcp_events_pusher_connection_disconnected(
         &self,
-        connection: Arc<DataReaderTcpConnection>,
+        connection: Arc<dyn MainNodeConnection>,
     ) {
         self.event_notifier
             .event_loop
@@ -56,15 +86,78 @@ impl SyncToMainNodeHandler {
             .event_loop
             .send(SyncToMainNodeEvent::Delivered(confirmation_id));
     }
+
+    // Main node advertises sync batches with its Capabilities packet. Serializers created
+    // with_sync_to_main_queues report it on their own, so this is needed only for custom transports
+    pub fn tcp_events_pusher_sync_batches_supported(&self, supported: bool) {
+        self.event_notifier
+            .event_loop
+            .send(SyncToMainNodeEvent::SyncBatchesSupported(supported));
+    }
+
+    pub fn tcp_events_pusher_read_moments_supported(&self, supported: bool) {
+        self.event_notifier
+            .event_loop
+            .send(SyncToMainNodeEvent::ReadMomentsSupported(supported));
+    }
+
+    pub fn tcp_events_pusher_row_expirations_supported(&self, supported: bool) {
+        self.event_notifier
+            .event_loop
+            .send(SyncToMainNodeEvent::RowExpirationsSupported(supported));
+    }
 }
 
 pub struct SyncToMainNodeEventLoop {
     queues: Arc<SyncToMainNodeQueues>,
+    logger: Option<Arc<dyn Logger + Send + Sync + 'static>>,
 }
 
 impl SyncToMainNodeEventLoop {
     pub fn new(queues: Arc<SyncToMainNodeQueues>) -> Self {
-        Self { queues }
+        Self {
+            queues,
+            logger: None,
+        }
+    }
+
+    pub fn with_logger(mut self, logger: Arc<dyn Logger + Send + Sync + 'static>) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    fn write_warning(&self, message: String) {
+        match &self.logger {
+            Some(logger) => logger.write_warning("SyncToMainNode".to_string(), message, None),
+            None => println!("{}", message),
+        }
+    }
+
+    async fn check_delivery_timeouts(&self) {
+        let timeouts = self.queues.resend_timed_out_events().await;
+
+        for item in &timeouts.timed_out {
+            let message = format!(
+                "Event {} of queue {} for table {} is not confirmed by main node in {:?}. Event is sent again",
+                item.confirmation_id, item.queue_name, item.table_name, item.waited
+            );
+
+            self.write_warning(message);
+        }
+
+        if let Some(connection) = timeouts.connection_to_drop {
+            let message =
+                "Main node does not confirm sync events. Dropping the connection".to_string();
+
+            self.write_warning(message);
+
+            connection.disconnect().await;
+            return;
+        }
+
+        if !timeouts.timed_out.is_empty() {
+            to_main_node_pusher(&self.queues, None).await;
+        }
     }
 }
 
@@ -85,6 +178,67 @@ impl EventsLoopTick<SyncToMainNodeEvent> for SyncToMainNodeEventLoop {
             SyncToMainNodeEvent::Delivered(confirmation_id) => {
                 to_main_node_pusher(&self.queues, Some(confirmation_id)).await;
             }
+            SyncToMainNodeEvent::CheckDeliveryTimeouts => {
+                self.check_delivery_timeouts().await;
+            }
+            SyncToMainNodeEvent::SyncBatchesSupported(supported) => {
+                self.queues.set_main_node_supports_batches(supported).await;
+                to_main_node_pusher(&self.queues, None).await;
+            }
+            SyncToMainNodeEvent::ReadMomentsSupported(supported) => {
+                self.queues
+                    .set_main_node_supports_read_moments(supported)
+                    .await;
+            }
+            SyncToMainNodeEvent::RowExpirationsSupported(supported) => {
+                self.queues
+                    .set_main_node_supports_row_expirations(supported)
+                    .await;
+            }
+        }
+    }
+}
+
+async fn delivery_timeouts_timer(
+    queues: Arc<SyncToMainNodeQueues>,
+    app_states: Arc<impl ApplicationStates + Send + Sync + 'static>,
+    confirmation_timeout: Duration,
+) {
+    let check_interval = (confirmation_timeout / 4).max(Duration::from_millis(100));
+
+    while !app_states.is_shutting_down() {
+        tokio::time::sleep(check_interval).await;
+        queues
+            .event_loop
+            .send(SyncToMainNodeEvent::CheckDeliveryTimeouts);
+    }
+}
+
+// Delivers the updates which were held back by flush_interval.
+// Timer wakes up at the deadline of the oldest held back event, or when the next event is queued
+async fn flush_timer(
+    queues: Arc<SyncToMainNodeQueues>,
+    app_states: Arc<impl ApplicationStates + Send + Sync + 'static>,
+) {
+    while !app_states.is_shutting_down() {
+        match queues.get_next_flush_deadline().await {
+            Some(deadline) => {
+                tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await;
+                queues.event_loop.send(SyncToMainNodeEvent::PingToDeliver);
+            }
+            None => {
+                // Shutdown is checked from time to time while there is nothing to flush
+                while !app_states.is_shutting_down() {
+                    let wait_for_queued_event = queues.wait_for_queued_event();
+
+                    if tokio::time::timeout(FLUSH_TIMER_IDLE_CHECK_INTERVAL, wait_for_queued_event)
+                        .await
+                        .is_ok()
+                    {
+                        break;
+                    }
+                }
+            }
         }
     }
 }
@@ -93,16 +247,33 @@ pub async fn to_main_node_pusher(
     queues: &Arc<SyncToMainNodeQueues>,
     delivered_confimration_id: Option<i64>,
 ) {
-    use crate::MyNoSqlTcpContract;
-    let next_event = queues
-        .get_next_event_to_deliver(delivered_confimration_id)
-        .await;
+    let mut delivered_confimration_id = delivered_confimration_id;
+
+    let with_read_moments = queues.main_node_supports_read_moments().await;
 
-    if next_event.is_none() {
-        return;
+    // Window is filled up to max_in_flight events
+    while let Some((connection, next_event)) = queues
+        .get_next_event_to_deliver(delivered_confimration_id.take())
+        .await
+    {
+        send_to_main_node(connection.as_ref(), next_event, with_read_moments).await;
     }
+}
 
-    let (connection, next_event) = next_event.unwrap();
+async fn send_to_main_node(
+    connection: &dyn MainNodeConnection,
+    next_event: DeliverToMainNodeEvent,
+    with_read_moments: bool,
+) {
+    use crate::MyNoSqlTcpContract;
+
+    let get_read_moment = |read_moment| {
+        if with_read_moments {
+            Some(read_moment)
+        } else {
+            None
+        }
+    };
 
     match next_event {
         DeliverToMainNodeEvent::UpdatePartitionsExpiration {
@@ -127,58 +298,281 @@ pub async fn to_main_node_pusher(
             event,
             confirmation_id,
         } => {
-            let mut partitions = Vec::with_capacity(event.partitions.len());
-
-            for (partition, _) in event.partitions {
-                partitions.push(partition);
-            }
-
-            connection
-                .send(MyNoSqlTcpContract::UpdatePartitionsLastReadTime {
+            let contract = if with_read_moments {
+                MyNoSqlTcpContract::UpdatePartitionsReadMoments {
                     confirmation_id,
                     table_name: event.table_name,
-                    partitions,
-                })
-                .await;
+                    partitions: event
+                        .partitions
+                        .into_iter()
+                        .map(|(partition, read_moment)| (partition, Some(read_moment)))
+                        .collect(),
+                }
+            } else {
+                MyNoSqlTcpContract::UpdatePartitionsLastReadTime {
+                    confirmation_id,
+                    table_name: event.table_name,
+                    partitions: event.partitions.into_keys().collect(),
+                }
+            };
+
+            connection.send(contract).await;
         }
         DeliverToMainNodeEvent::UpdateRowsExpirationTime {
             event,
             confirmation_id,
         } => {
-            let mut row_keys = Vec::with_capacity(event.row_keys.len());
+            let contract = match event.get_shared_expiration_time() {
+                Some(expiration_time) => MyNoSqlTcpContract::UpdateRowsExpirationTime {
+                    confirmation_id,
+                    table_name: event.table_name,
+                    partition_key: event.partition_key,
+                    row_keys: event.row_keys.into_keys().collect(),
+                    expiration_time,
+                },
+                None => MyNoSqlTcpContract::UpdateRowsExpirationTimePerRow {
+                    confirmation_id,
+                    table_name: event.table_name,
+                    partition_key: event.partition_key,
+                    rows: event.row_keys.into_iter().collect(),
+                },
+            };
 
-            for (row_key, _) in event.row_keys {
-                row_keys.push(row_key);
+            connection.send(contract).await;
+        }
+        DeliverToMainNodeEvent::UpdateRowsLastReadTime {
+            event,
+            confirmation_id,
+        } => {
+            let contract = if with_read_moments {
+                MyNoSqlTcpContract::UpdateRowsReadMoments {
+                    confirmation_id,
+                    table_name: event.table_name,
+                    partition_key: event.partition_key,
+                    rows: event
+                        .row_keys
+                        .into_iter()
+                        .map(|(row_key, read_moment)| (row_key, Some(read_moment)))
+                        .collect(),
+                }
+            } else {
+                MyNoSqlTcpContract::UpdateRowsLastReadTime {
+                    confirmation_id,
+                    table_name: event.table_name,
+                    partition_key: event.partition_key,
+                    row_keys: event.row_keys.into_keys().collect(),
+                }
+            };
+
+            connection.send(contract).await;
+        }
+        DeliverToMainNodeEvent::LastReadTimeBatch {
+            partitions,
+            rows,
+            confirmation_id,
+        } => {
+            let mut tables: Vec<LastReadTimeBatchTcpContract> = Vec::new();
+
+            for event in partitions {
+                let table = get_last_read_time_batch_table(&mut tables, event.table_name);
+
+                for (partition_key, read_moment) in event.partitions {
+                    table.get_partition_mut(&partition_key).partition_read =
+                        Some(get_read_moment(read_moment));
+                }
+            }
+
+            for event in rows {
+                let table = get_last_read_time_batch_table(&mut tables, event.table_name);
+                let partition = table.get_partition_mut(&event.partition_key);
+
+                for (row_key, read_moment) in event.row_keys {
+                    partition.rows.push((row_key, get_read_moment(read_moment)));
+                }
             }
 
             connection
-                .send(MyNoSqlTcpContract::UpdateRowsExpirationTime {
+                .send(MyNoSqlTcpContract::UpdateLastReadTimeBatch {
                     confirmation_id,
-                    table_name: event.table_name,
-                    partition_key: event.partition_key,
-                    row_keys,
-                    expiration_time: event.expiration_time,
+                    tables,
                 })
                 .await;
         }
-        DeliverToMainNodeEvent::UpdateRowsLastReadTime {
-            event,
+        DeliverToMainNodeEvent::ExpirationTimeBatch {
+            partitions,
+            rows,
             confirmation_id,
         } => {
-            let mut row_keys = Vec::with_capacity(event.row_keys.len());
+            let mut tables: Vec<ExpirationTimeBatchTcpContract> = Vec::new();
 
-            for (row_key, _) in event.row_keys {
-                row_keys.push(row_key);
+            // Later event of the same partition overrides the earlier one
+            for event in partitions {
+                let table = get_expiration_time_batch_table(&mut tables, event.table_name);
+
+                for (partition_key, expiration_time) in event.partitions {
+                    table.get_partition_mut(&partition_key).partition_expiration =
+                        Some(expiration_time);
+                }
+            }
+
+            for event in rows {
+                let table = get_expiration_time_batch_table(&mut tables, event.table_name);
+                let partition = table.get_partition_mut(&event.partition_key);
+
+                for (row_key, expiration_time) in event.row_keys {
+                    partition.rows.push((row_key, expiration_time));
+                }
             }
 
             connection
-                .send(MyNoSqlTcpContract::UpdateRowsLastReadTime {
+                .send(MyNoSqlTcpContract::UpdateExpirationTimeBatch {
                     confirmation_id,
-                    table_name: event.table_name,
-                    partition_key: event.partition_key,
-                    row_keys,
+                    tables,
                 })
                 .await;
         }
     }
 }
+
+fn get_last_read_time_batch_table(
+    tables: &mut Vec<LastReadTimeBatchTcpContract>,
+    table_name: String,
+) -> &mut LastReadTimeBatchTcpContract {
+    let index = match tables.iter().position(|itm| itm.table_name == table_name) {
+        Some(index) => index,
+        None => {
+            tables.push(LastReadTimeBatchTcpContract::new(table_name));
+            tables.len() - 1
+        }
+    };
+
+    &mut tables[index]
+}
+
+fn get_expiration_time_batch_table(
+    tables: &mut Vec<ExpirationTimeBatchTcpContract>,
+    table_name: String,
+) -> &mut ExpirationTimeBatchTcpContract {
+    let index = match tables.iter().position(|itm| itm.table_name == table_name) {
+        Some(index) => index,
+        None => {
+            tables.push(ExpirationTimeBatchTcpContract::new(table_name));
+            tables.len() - 1
+        }
+    };
+
+    &mut tables[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+    use super::*;
+    use crate::MyNoSqlTcpContract;
+
+    #[derive(Default)]
+    struct RecordingConnection {
+        sent: Mutex<Vec<MyNoSqlTcpContract>>,
+    }
+
+    #[async_trait::async_trait]
+    impl MainNodeConnection for RecordingConnection {
+        async fn send(&self, contract: MyNoSqlTcpContract) {
+            self.sent.lock().unwrap().push(contract);
+        }
+
+        async fn disconnect(&self) {}
+    }
+
+    async fn send_rows_read(read_moments: bool) -> MyNoSqlTcpContract {
+        let queues = Arc::new(SyncToMainNodeQueues::new());
+        let connection = Arc::new(RecordingConnection::default());
+
+        queues.new_connection(connection.clone()).await;
+        queues.set_main_node_supports_batches(true).await;
+        queues
+            .set_main_node_supports_read_moments(read_moments)
+            .await;
+
+        let row_keys = ["rk".to_string()];
+        queues
+            .update_rows_last_read_time("table", "pk", row_keys.iter())
+            .await;
+
+        to_main_node_pusher(&queues, None).await;
+
+        let mut sent = connection.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        sent.remove(0)
+    }
+
+    async fn send_rows_expiration(row_expirations: bool) -> MyNoSqlTcpContract {
+        let queues = Arc::new(SyncToMainNodeQueues::new());
+        let connection = Arc::new(RecordingConnection::default());
+
+        queues.new_connection(connection.clone()).await;
+        queues.set_main_node_supports_batches(true).await;
+        queues
+            .set_main_node_supports_row_expirations(row_expirations)
+            .await;
+
+        for (row_key, expiration_time) in [("rk-1", None), ("rk-2", Some(1_000_000))] {
+            let row_keys = [row_key.to_string()];
+            queues
+                .update_rows_expiration_time(
+                    "table",
+                    "pk",
+                    row_keys.iter(),
+                    expiration_time.map(DateTimeAsMicroseconds::new),
+                )
+                .await;
+        }
+
+        to_main_node_pusher(&queues, None).await;
+
+        let mut sent = connection.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        sent.remove(0)
+    }
+
+    #[tokio::test]
+    async fn rows_are_split_by_expiration_time_unless_main_node_advertised_row_expirations() {
+        for row_expirations in [false, true] {
+            match send_rows_expiration(row_expirations).await {
+                MyNoSqlTcpContract::UpdateRowsExpirationTime { row_keys, .. } => {
+                    assert!(!row_expirations);
+                    assert_eq!(row_keys.len(), 1);
+                }
+                MyNoSqlTcpContract::UpdateRowsExpirationTimePerRow { rows, .. } => {
+                    assert!(row_expirations);
+                    assert_eq!(rows.len(), 2);
+                }
+                _ => panic!("UpdateRowsExpirationTime is expected"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn read_moments_are_sent_only_if_main_node_advertised_them() {
+        for read_moments in [false, true] {
+            match send_rows_read(read_moments).await {
+                MyNoSqlTcpContract::UpdateRowsReadMoments { rows, .. } => {
+                    assert!(read_moments);
+                    assert_eq!(rows.len(), 1);
+                    assert!(rows[0].1.is_some());
+                }
+                MyNoSqlTcpContract::UpdateRowsLastReadTime { row_keys, .. } => {
+                    assert!(!read_moments);
+                    assert_eq!(row_keys, vec!["rk".to_string()]);
+                }
+                other => panic!(
+                    "Rows last read time is expected. Got {}",
+                    other.get_packet_name()
+                ),
+            }
+        }
+    }
+}
@@ -1,24 +1,73 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
 
 use rust_extensions::date_time::DateTimeAsMicroseconds;
 
+use super::{
+    sync_queue_limits::*,
+    sync_queues_scheduler::QueuedEvent,
+    sync_queues_snapshot::{SyncTableSnapshot, SyncTablesUsage},
+};
+
 #[derive(Debug, Clone)]
 pub struct UpdatePartitionExpirationEvent {
     pub table_name: String,
     pub partitions: HashMap<String, Option<DateTimeAsMicroseconds>>,
 }
 
+impl UpdatePartitionExpirationEvent {
+    pub fn get_entries_amount(&self) -> usize {
+        self.partitions.len()
+    }
+
+    pub fn get_approximate_size(&self) -> usize {
+        QUEUE_EVENT_OVERHEAD
+            + self.table_name.len()
+            + self
+                .partitions
+                .keys()
+                .map(|itm| get_partition_size(itm))
+                .sum::<usize>()
+    }
+}
+
+fn get_partition_size(partition_key: &str) -> usize {
+    get_key_size(partition_key) + std::mem::size_of::<Option<DateTimeAsMicroseconds>>()
+}
+
 pub struct UpdatePartitionsExpirationTimeQueue {
-    queue: VecDeque<UpdatePartitionExpirationEvent>,
+    queue: VecDeque<QueuedEvent<UpdatePartitionExpirationEvent>>,
+    usage: SyncQueueUsage,
+    tables: SyncTablesUsage,
 }
 
 impl UpdatePartitionsExpirationTimeQueue {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            usage: SyncQueueUsage::default(),
+            tables: SyncTablesUsage::default(),
         }
     }
 
+    fn on_added(&mut self, item: &QueuedEvent<UpdatePartitionExpirationEvent>) {
+        let event = &item.event;
+        self.usage.entries += event.get_entries_amount();
+        self.usage.bytes += event.get_approximate_size();
+        self.tables
+            .on_added(&event.table_name, item.enqueued, event.partitions.len(), 0);
+    }
+
+    fn on_removed(&mut self, item: &QueuedEvent<UpdatePartitionExpirationEvent>) {
+        let event = &item.event;
+        self.usage.entries -= event.get_entries_amount();
+        self.usage.bytes -= event.get_approximate_size();
+        self.tables
+            .on_removed(&event.table_name, item.enqueued, event.partitions.len(), 0);
+    }
+
     pub fn add(
         &mut self,
         table_name: &str,
@@ -28,26 +77,91 @@ impl UpdatePartitionsExpirationTimeQueue {
         if let Some(item) = self
             .queue
             .iter_mut()
+            .rev()
+            .map(|itm| &mut itm.event)
             .find(|itm| itm.table_name == table_name)
         {
-            item.partitions.insert(partition_key.to_string(), date_time);
+            if item
+                .partitions
+                .insert(partition_key.to_string(), date_time)
+                .is_none()
+            {
+                self.usage.entries += 1;
+                self.usage.bytes += get_partition_size(partition_key);
+                self.tables.on_entries_added(table_name, 1, 0);
+            }
             return;
         }
 
         let mut partitions = HashMap::new();
         partitions.insert(partition_key.to_string(), date_time);
 
-        self.queue.push_back(UpdatePartitionExpirationEvent {
+        let item = UpdatePartitionExpirationEvent {
             table_name: table_name.to_string(),
             partitions,
-        });
+        };
+
+        let item = QueuedEvent::new(item);
+        self.on_added(&item);
+        self.queue.push_back(item);
+    }
+
+    pub fn get_events_amount(&self) -> usize {
+        self.queue.len()
     }
 
+    pub fn get_usage(&self) -> SyncQueueUsage {
+        self.usage
+    }
+
+    pub fn get_events(&self) -> impl Iterator<Item = &UpdatePartitionExpirationEvent> {
+        self.queue.iter().map(|itm| &itm.event)
+    }
+
+    pub(crate) fn get_tables_snapshot(&self) -> Vec<SyncTableSnapshot> {
+        self.tables.get_snapshot()
+    }
+
+    // Event which was not delivered goes first, so newer events of the table are not overwritten by it
     pub fn return_event(&mut self, event: UpdatePartitionExpirationEvent) {
-        self.queue.push_back(event);
+        let event = QueuedEvent::new_returned(event, self.queue.front());
+        self.on_added(&event);
+        self.queue.push_front(event);
     }
 
     pub fn dequeue(&mut self) -> Option<UpdatePartitionExpirationEvent> {
-        self.queue.pop_front()
+        let result = self.queue.pop_front()?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    pub fn dequeue_if(
+        &mut self,
+        can_dequeue: impl Fn(&str) -> bool,
+    ) -> Option<UpdatePartitionExpirationEvent> {
+        let index = self
+            .queue
+            .iter()
+            .position(|itm| can_dequeue(itm.event.table_name.as_str()))?;
+
+        let result = self.queue.remove(index)?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    // When the event which is dequeued next with the same condition was queued and how many entries it has
+    pub fn get_candidate_if(&self, can_dequeue: impl Fn(&str) -> bool) -> Option<(Instant, usize)> {
+        self.queue
+            .iter()
+            .find(|itm| can_dequeue(itm.event.table_name.as_str()))
+            .map(|itm| (itm.enqueued, itm.event.get_entries_amount()))
+    }
+
+    // Events are ordered by the moment they were queued, so this is the oldest one which matches
+    pub fn get_enqueued_if(&self, condition: impl Fn(Instant) -> bool) -> Option<Instant> {
+        self.queue
+            .iter()
+            .map(|itm| itm.enqueued)
+            .find(|enqueued| condition(*enqueued))
     }
 }
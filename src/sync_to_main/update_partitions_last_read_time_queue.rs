@@ -1,73 +1,220 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use super::{
+    sync_queue_limits::*,
+    sync_queues_scheduler::QueuedEvent,
+    sync_queues_snapshot::{SyncTableSnapshot, SyncTablesUsage},
+};
 
 #[derive(Clone, Debug)]
 pub struct UpdatePartitionsLastReadTimeEvent {
     pub table_name: String,
-    pub partitions: HashMap<String, ()>,
+    // Latest moment every partition was read at
+    pub partitions: HashMap<String, DateTimeAsMicroseconds>,
+}
+
+impl UpdatePartitionsLastReadTimeEvent {
+    pub fn get_entries_amount(&self) -> usize {
+        self.partitions.len()
+    }
+
+    pub fn get_approximate_size(&self) -> usize {
+        QUEUE_EVENT_OVERHEAD
+            + self.table_name.len()
+            + self
+                .partitions
+                .keys()
+                .map(|itm| get_key_size(itm))
+                .sum::<usize>()
+    }
 }
 
 pub struct UpdatePartitionsLastReadTimeQueue {
-    queue: VecDeque<UpdatePartitionsLastReadTimeEvent>,
+    queue: VecDeque<QueuedEvent<UpdatePartitionsLastReadTimeEvent>>,
+    usage: SyncQueueUsage,
+    tables: SyncTablesUsage,
 }
 
 impl UpdatePartitionsLastReadTimeQueue {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            usage: SyncQueueUsage::default(),
+            tables: SyncTablesUsage::default(),
         }
     }
 
+    fn on_added(&mut self, item: &QueuedEvent<UpdatePartitionsLastReadTimeEvent>) {
+        let event = &item.event;
+        self.usage.entries += event.get_entries_amount();
+        self.usage.bytes += event.get_approximate_size();
+        self.tables
+            .on_added(&event.table_name, item.enqueued, event.partitions.len(), 0);
+    }
+
+    fn on_removed(&mut self, item: &QueuedEvent<UpdatePartitionsLastReadTimeEvent>) {
+        let event = &item.event;
+        self.usage.entries -= event.get_entries_amount();
+        self.usage.bytes -= event.get_approximate_size();
+        self.tables
+            .on_removed(&event.table_name, item.enqueued, event.partitions.len(), 0);
+    }
+
     pub fn add<'s, TPartitions: Iterator<Item = &'s String>>(
         &mut self,
         table_name: &str,
         partition_keys: TPartitions,
+        read_moment: DateTimeAsMicroseconds,
     ) {
         if let Some(item) = self
             .queue
             .iter_mut()
+            .rev()
+            .map(|itm| &mut itm.event)
             .find(|itm| itm.table_name == table_name)
         {
             for partition_key in partition_keys {
-                item.partitions.insert(partition_key.to_string(), ());
+                if insert_read_moment(&mut item.partitions, partition_key, read_moment) {
+                    self.usage.entries += 1;
+                    self.usage.bytes += get_key_size(partition_key);
+                    self.tables.on_entries_added(table_name, 1, 0);
+                }
             }
             return;
         }
 
         let mut partitions = HashMap::new();
         for partition_key in partition_keys {
-            partitions.insert(partition_key.to_string(), ());
+            insert_read_moment(&mut partitions, partition_key, read_moment);
         }
 
-        self.queue.push_back(UpdatePartitionsLastReadTimeEvent {
+        let item = UpdatePartitionsLastReadTimeEvent {
             table_name: table_name.to_string(),
             partitions,
-        });
+        };
+
+        let item = QueuedEvent::new(item);
+        self.on_added(&item);
+        self.queue.push_back(item);
     }
 
-    pub fn add_partition(&mut self, table_name: &str, partition_key: &str) {
+    pub fn add_partition(
+        &mut self,
+        table_name: &str,
+        partition_key: &str,
+        read_moment: DateTimeAsMicroseconds,
+    ) {
         if let Some(item) = self
             .queue
             .iter_mut()
+            .rev()
+            .map(|itm| &mut itm.event)
             .find(|itm| itm.table_name == table_name)
         {
-            item.partitions.insert(partition_key.to_string(), ());
+            if insert_read_moment(&mut item.partitions, partition_key, read_moment) {
+                self.usage.entries += 1;
+                self.usage.bytes += get_key_size(partition_key);
+                self.tables.on_entries_added(table_name, 1, 0);
+            }
+            return;
         }
 
         let mut partitions = HashMap::new();
 
-        partitions.insert(partition_key.to_string(), ());
+        partitions.insert(partition_key.to_string(), read_moment);
 
-        self.queue.push_back(UpdatePartitionsLastReadTimeEvent {
+        let item = UpdatePartitionsLastReadTimeEvent {
             table_name: table_name.to_string(),
             partitions,
-        });
+        };
+
+        let item = QueuedEvent::new(item);
+        self.on_added(&item);
+        self.queue.push_back(item);
+    }
+
+    pub fn get_events_amount(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn get_usage(&self) -> SyncQueueUsage {
+        self.usage
+    }
+
+    pub fn get_events(&self) -> impl Iterator<Item = &UpdatePartitionsLastReadTimeEvent> {
+        self.queue.iter().map(|itm| &itm.event)
     }
 
+    pub(crate) fn get_tables_snapshot(&self) -> Vec<SyncTableSnapshot> {
+        self.tables.get_snapshot()
+    }
+
+    // Event which was not delivered goes first, so newer events of the table are not overwritten by it
     pub fn return_event(&mut self, event: UpdatePartitionsLastReadTimeEvent) {
-        self.queue.push_back(event);
+        let event = QueuedEvent::new_returned(event, self.queue.front());
+        self.on_added(&event);
+        self.queue.push_front(event);
     }
 
     pub fn dequeue(&mut self) -> Option<UpdatePartitionsLastReadTimeEvent> {
-        self.queue.pop_front()
+        let result = self.queue.pop_front()?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    pub fn dequeue_if(
+        &mut self,
+        can_dequeue: impl Fn(&str) -> bool,
+    ) -> Option<UpdatePartitionsLastReadTimeEvent> {
+        let index = self
+            .queue
+            .iter()
+            .position(|itm| can_dequeue(itm.event.table_name.as_str()))?;
+
+        let result = self.queue.remove(index)?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    // When the event which is dequeued next with the same condition was queued and how many entries it has
+    pub fn get_candidate_if(&self, can_dequeue: impl Fn(&str) -> bool) -> Option<(Instant, usize)> {
+        self.queue
+            .iter()
+            .find(|itm| can_dequeue(itm.event.table_name.as_str()))
+            .map(|itm| (itm.enqueued, itm.event.get_entries_amount()))
+    }
+
+    // Events are ordered by the moment they were queued, so this is the oldest one which matches
+    pub fn get_enqueued_if(&self, condition: impl Fn(Instant) -> bool) -> Option<Instant> {
+        self.queue
+            .iter()
+            .map(|itm| itm.enqueued)
+            .find(|enqueued| condition(*enqueued))
+    }
+}
+
+// Key keeps the latest read moment. Returns true if the key is new
+pub(crate) fn insert_read_moment(
+    keys: &mut HashMap<String, DateTimeAsMicroseconds>,
+    key: &str,
+    read_moment: DateTimeAsMicroseconds,
+) -> bool {
+    match keys.get_mut(key) {
+        Some(current) => {
+            if read_moment.unix_microseconds > current.unix_microseconds {
+                *current = read_moment;
+            }
+
+            false
+        }
+        None => {
+            keys.insert(key.to_string(), read_moment);
+            true
+        }
     }
 }
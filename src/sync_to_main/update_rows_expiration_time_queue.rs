@@ -1,26 +1,120 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
 
 use rust_extensions::date_time::DateTimeAsMicroseconds;
 
+use super::{
+    sync_queue_limits::*,
+    sync_queues_scheduler::QueuedEvent,
+    sync_queues_snapshot::{SyncTableSnapshot, SyncTablesUsage},
+};
+
 #[derive(Debug, Clone)]
 pub struct UpdateRowsExpirationTimeEvent {
     pub table_name: String,
     pub partition_key: String,
-    pub row_keys: HashMap<String, ()>,
-    pub expiration_time: Option<DateTimeAsMicroseconds>,
+    // Latest expiration time of every row
+    pub row_keys: HashMap<String, Option<DateTimeAsMicroseconds>>,
+}
+
+impl UpdateRowsExpirationTimeEvent {
+    pub fn get_entries_amount(&self) -> usize {
+        self.row_keys.len()
+    }
+
+    pub fn get_approximate_size(&self) -> usize {
+        QUEUE_EVENT_OVERHEAD
+            + self.table_name.len()
+            + self.partition_key.len()
+            + self
+                .row_keys
+                .keys()
+                .map(|itm| get_key_size(itm))
+                .sum::<usize>()
+    }
+
+    // Expiration time shared by all the rows. None - rows have different expiration times
+    pub fn get_shared_expiration_time(&self) -> Option<Option<DateTimeAsMicroseconds>> {
+        let mut values = self.row_keys.values();
+
+        let expiration_time = match values.next() {
+            Some(itm) => *itm,
+            None => return Some(None),
+        };
+
+        let unix_microseconds = expiration_time.map(|itm| itm.unix_microseconds);
+
+        if values.all(|itm| itm.map(|itm| itm.unix_microseconds) == unix_microseconds) {
+            Some(expiration_time)
+        } else {
+            None
+        }
+    }
+
+    // Rows with expiration time other than the one of the first row are moved to the returned event
+    pub fn split_by_expiration_time(&mut self) -> Option<UpdateRowsExpirationTimeEvent> {
+        let expiration_time = self
+            .row_keys
+            .values()
+            .next()
+            .copied()?
+            .map(|itm| itm.unix_microseconds);
+
+        if self
+            .row_keys
+            .values()
+            .all(|itm| itm.map(|itm| itm.unix_microseconds) == expiration_time)
+        {
+            return None;
+        }
+
+        let (row_keys, other_row_keys) = std::mem::take(&mut self.row_keys)
+            .into_iter()
+            .partition(|(_, itm)| itm.map(|itm| itm.unix_microseconds) == expiration_time);
+
+        self.row_keys = row_keys;
+
+        Some(UpdateRowsExpirationTimeEvent {
+            table_name: self.table_name.clone(),
+            partition_key: self.partition_key.clone(),
+            row_keys: other_row_keys,
+        })
+    }
 }
 
 pub struct UpdateRowsExpirationTimeQueue {
-    queue: VecDeque<UpdateRowsExpirationTimeEvent>,
+    queue: VecDeque<QueuedEvent<UpdateRowsExpirationTimeEvent>>,
+    usage: SyncQueueUsage,
+    tables: SyncTablesUsage,
 }
 
 impl UpdateRowsExpirationTimeQueue {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            usage: SyncQueueUsage::default(),
+            tables: SyncTablesUsage::default(),
         }
     }
 
+    fn on_added(&mut self, item: &QueuedEvent<UpdateRowsExpirationTimeEvent>) {
+        let event = &item.event;
+        self.usage.entries += event.get_entries_amount();
+        self.usage.bytes += event.get_approximate_size();
+        self.tables
+            .on_added(&event.table_name, item.enqueued, 1, event.row_keys.len());
+    }
+
+    fn on_removed(&mut self, item: &QueuedEvent<UpdateRowsExpirationTimeEvent>) {
+        let event = &item.event;
+        self.usage.entries -= event.get_entries_amount();
+        self.usage.bytes -= event.get_approximate_size();
+        self.tables
+            .on_removed(&event.table_name, item.enqueued, 1, event.row_keys.len());
+    }
+
     pub fn add<'s, TRowKeys: Iterator<Item = &'s str>>(
         &mut self,
         table_name: &str,
@@ -31,10 +125,20 @@ impl UpdateRowsExpirationTimeQueue {
         if let Some(item) = self
             .queue
             .iter_mut()
+            .rev()
+            .map(|itm| &mut itm.event)
             .find(|itm| itm.table_name == table_name && itm.partition_key == partition_key)
         {
             for row_key in row_keys {
-                item.row_keys.insert(row_key.to_string(), ());
+                if item
+                    .row_keys
+                    .insert(row_key.to_string(), date_time)
+                    .is_none()
+                {
+                    self.usage.entries += 1;
+                    self.usage.bytes += get_key_size(row_key);
+                    self.tables.on_entries_added(table_name, 0, 1);
+                }
             }
 
             return;
@@ -43,17 +147,70 @@ impl UpdateRowsExpirationTimeQueue {
         let item = UpdateRowsExpirationTimeEvent {
             table_name: table_name.to_string(),
             partition_key: partition_key.to_string(),
-            row_keys: row_keys.map(|k| (k.to_string(), ())).collect(),
-            expiration_time: date_time,
+            row_keys: row_keys.map(|k| (k.to_string(), date_time)).collect(),
         };
 
+        let item = QueuedEvent::new(item);
+        self.on_added(&item);
         self.queue.push_back(item);
     }
 
+    pub fn get_events_amount(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn get_usage(&self) -> SyncQueueUsage {
+        self.usage
+    }
+
+    pub fn get_events(&self) -> impl Iterator<Item = &UpdateRowsExpirationTimeEvent> {
+        self.queue.iter().map(|itm| &itm.event)
+    }
+
+    pub(crate) fn get_tables_snapshot(&self) -> Vec<SyncTableSnapshot> {
+        self.tables.get_snapshot()
+    }
+
+    // Event which was not delivered goes first, so newer events of the table are not overwritten by it
     pub fn return_event(&mut self, event: UpdateRowsExpirationTimeEvent) {
-        self.queue.push_back(event);
+        let event = QueuedEvent::new_returned(event, self.queue.front());
+        self.on_added(&event);
+        self.queue.push_front(event);
     }
+
     pub fn dequeue(&mut self) -> Option<UpdateRowsExpirationTimeEvent> {
-        self.queue.pop_front()
+        let result = self.queue.pop_front()?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    pub fn dequeue_if(
+        &mut self,
+        can_dequeue: impl Fn(&str) -> bool,
+    ) -> Option<UpdateRowsExpirationTimeEvent> {
+        let index = self
+            .queue
+            .iter()
+            .position(|itm| can_dequeue(itm.event.table_name.as_str()))?;
+
+        let result = self.queue.remove(index)?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    // When the event which is dequeued next with the same condition was queued and how many entries it has
+    pub fn get_candidate_if(&self, can_dequeue: impl Fn(&str) -> bool) -> Option<(Instant, usize)> {
+        self.queue
+            .iter()
+            .find(|itm| can_dequeue(itm.event.table_name.as_str()))
+            .map(|itm| (itm.enqueued, itm.event.get_entries_amount()))
+    }
+
+    // Events are ordered by the moment they were queued, so this is the oldest one which matches
+    pub fn get_enqueued_if(&self, condition: impl Fn(Instant) -> bool) -> Option<Instant> {
+        self.queue
+            .iter()
+            .map(|itm| itm.enqueued)
+            .find(|enqueued| condition(*enqueued))
     }
 }
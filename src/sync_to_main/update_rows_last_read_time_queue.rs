@@ -1,35 +1,100 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use super::{
+    sync_queue_limits::*,
+    sync_queues_scheduler::QueuedEvent,
+    sync_queues_snapshot::{SyncTableSnapshot, SyncTablesUsage},
+    update_partitions_last_read_time_queue::insert_read_moment,
+};
+
 #[derive(Debug, Clone)]
 pub struct UpdateRowsLastReadTimeEvent {
     pub table_name: String,
     pub partition_key: String,
-    pub row_keys: HashMap<String, ()>,
+    // Latest moment every row was read at
+    pub row_keys: HashMap<String, DateTimeAsMicroseconds>,
+}
+
+impl UpdateRowsLastReadTimeEvent {
+    pub fn get_entries_amount(&self) -> usize {
+        self.row_keys.len()
+    }
+
+    pub fn get_approximate_size(&self) -> usize {
+        QUEUE_EVENT_OVERHEAD
+            + self.table_name.len()
+            + self.partition_key.len()
+            + self
+                .row_keys
+                .keys()
+                .map(|itm| get_key_size(itm))
+                .sum::<usize>()
+    }
+
+    pub fn get_max_read_moment(&self) -> Option<DateTimeAsMicroseconds> {
+        self.row_keys
+            .values()
+            .max_by_key(|itm| itm.unix_microseconds)
+            .copied()
+    }
 }
 
 pub struct UpdateRowsLastReadTimeQueue {
-    queue: VecDeque<UpdateRowsLastReadTimeEvent>,
+    queue: VecDeque<QueuedEvent<UpdateRowsLastReadTimeEvent>>,
+    usage: SyncQueueUsage,
+    tables: SyncTablesUsage,
 }
 
 impl UpdateRowsLastReadTimeQueue {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            usage: SyncQueueUsage::default(),
+            tables: SyncTablesUsage::default(),
         }
     }
 
+    fn on_added(&mut self, item: &QueuedEvent<UpdateRowsLastReadTimeEvent>) {
+        let event = &item.event;
+        self.usage.entries += event.get_entries_amount();
+        self.usage.bytes += event.get_approximate_size();
+        self.tables
+            .on_added(&event.table_name, item.enqueued, 1, event.row_keys.len());
+    }
+
+    fn on_removed(&mut self, item: &QueuedEvent<UpdateRowsLastReadTimeEvent>) {
+        let event = &item.event;
+        self.usage.entries -= event.get_entries_amount();
+        self.usage.bytes -= event.get_approximate_size();
+        self.tables
+            .on_removed(&event.table_name, item.enqueued, 1, event.row_keys.len());
+    }
+
     pub fn add<'s, TRowKeys: Iterator<Item = &'s str>>(
         &mut self,
         table_name: &str,
         partition_key: &str,
         row_keys: TRowKeys,
+        read_moment: DateTimeAsMicroseconds,
     ) {
         if let Some(item) = self
             .queue
             .iter_mut()
+            .rev()
+            .map(|itm| &mut itm.event)
             .find(|itm| itm.table_name == table_name && itm.partition_key == partition_key)
         {
             for row_key in row_keys {
-                item.row_keys.insert(row_key.to_string(), ());
+                if insert_read_moment(&mut item.row_keys, row_key, read_moment) {
+                    self.usage.entries += 1;
+                    self.usage.bytes += get_key_size(row_key);
+                    self.tables.on_entries_added(table_name, 0, 1);
+                }
             }
             return;
         }
@@ -37,17 +102,70 @@ impl UpdateRowsLastReadTimeQueue {
         let item = UpdateRowsLastReadTimeEvent {
             table_name: table_name.to_string(),
             partition_key: partition_key.to_string(),
-            row_keys: row_keys.map(|k| (k.to_string(), ())).collect(),
+            row_keys: row_keys.map(|k| (k.to_string(), read_moment)).collect(),
         };
 
+        let item = QueuedEvent::new(item);
+        self.on_added(&item);
         self.queue.push_back(item);
     }
 
+    pub fn get_events_amount(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn get_usage(&self) -> SyncQueueUsage {
+        self.usage
+    }
+
+    pub fn get_events(&self) -> impl Iterator<Item = &UpdateRowsLastReadTimeEvent> {
+        self.queue.iter().map(|itm| &itm.event)
+    }
+
+    pub(crate) fn get_tables_snapshot(&self) -> Vec<SyncTableSnapshot> {
+        self.tables.get_snapshot()
+    }
+
+    // Event which was not delivered goes first, so newer events of the table are not overwritten by it
     pub fn return_event(&mut self, event: UpdateRowsLastReadTimeEvent) {
-        self.queue.push_back(event);
+        let event = QueuedEvent::new_returned(event, self.queue.front());
+        self.on_added(&event);
+        self.queue.push_front(event);
     }
 
     pub fn dequeue(&mut self) -> Option<UpdateRowsLastReadTimeEvent> {
-        self.queue.pop_front()
+        let result = self.queue.pop_front()?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    pub fn dequeue_if(
+        &mut self,
+        can_dequeue: impl Fn(&str) -> bool,
+    ) -> Option<UpdateRowsLastReadTimeEvent> {
+        let index = self
+            .queue
+            .iter()
+            .position(|itm| can_dequeue(itm.event.table_name.as_str()))?;
+
+        let result = self.queue.remove(index)?;
+        self.on_removed(&result);
+        Some(result.event)
+    }
+
+    // When the event which is dequeued next with the same condition was queued and how many entries it has
+    pub fn get_candidate_if(&self, can_dequeue: impl Fn(&str) -> bool) -> Option<(Instant, usize)> {
+        self.queue
+            .iter()
+            .find(|itm| can_dequeue(itm.event.table_name.as_str()))
+            .map(|itm| (itm.enqueued, itm.event.get_entries_amount()))
+    }
+
+    // Events are ordered by the moment they were queued, so this is the oldest one which matches
+    pub fn get_enqueued_if(&self, condition: impl Fn(Instant) -> bool) -> Option<Instant> {
+        self.queue
+            .iter()
+            .map(|itm| itm.enqueued)
+            .find(|enqueued| condition(*enqueued))
     }
 }
@@ -1,7 +1,25 @@
+use std::{sync::Arc, time::Instant};
+
 use my_tcp_sockets::socket_reader::{ReadingTcpContractFail, SocketReader, SocketReaderInMem};
 use rust_extensions::date_time::DateTimeAsMicroseconds;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    common_serializers::{SerializerBuffer, WriteSerializerBuffer},
+    compression_offload::CompressionResources,
+    metrics::ProtocolMetrics,
+    payload_encryption::ENCRYPTION_NONCE_SIZE,
+    tcp_packets::*,
+    BufferPool, ChecksumAlgorithm, CompressionAlgorithm, CompressionDictionaries,
+    CompressionOffload, CompressionPolicy, DecodeError, DecodeLimits, DeleteRowTcpContract,
+    ExpirationTimeBatchTcpContract, LastReadTimeBatchTcpContract, PeerCapabilities, SerializeError,
+};
 
-use crate::{tcp_packets::*, DeleteRowTcpContract};
+#[cfg(feature = "encryption")]
+use crate::{payload_encryption::ENCRYPTION_KEY_SIZE, KeyProvider};
+
+// Node which sends greeting of this version or later knows the Capabilities packet
+pub const GREETING_WITH_CAPABILITIES_VERSION: u8 = 2;
 
 #[derive(Debug)]
 pub enum MyNoSqlTcpContract {
@@ -43,6 +61,7 @@ pub enum MyNoSqlTcpContract {
     Unsubscribe(String),
     TableNotFound(String),
     CompressedPayload(Vec<u8>),
+    // Main node stamps the moment it gets the keys
     UpdatePartitionsLastReadTime {
         confirmation_id: i64,
         table_name: String,
@@ -54,6 +73,18 @@ pub enum MyNoSqlTcpContract {
         partition_key: String,
         row_keys: Vec<String>,
     },
+    // Version 1 of the last read time packets. Carries the moment every key was read at
+    UpdatePartitionsReadMoments {
+        confirmation_id: i64,
+        table_name: String,
+        partitions: Vec<(String, Option<DateTimeAsMicroseconds>)>,
+    },
+    UpdateRowsReadMoments {
+        confirmation_id: i64,
+        table_name: String,
+        partition_key: String,
+        rows: Vec<(String, Option<DateTimeAsMicroseconds>)>,
+    },
     UpdatePartitionsExpirationTime {
         confirmation_id: i64,
         table_name: String,
@@ -66,44 +97,702 @@ pub enum MyNoSqlTcpContract {
         row_keys: Vec<String>,
         expiration_time: Option<DateTimeAsMicroseconds>,
     },
+    // Version 1 of the rows expiration packet. Sent only to main nodes which advertise
+    // they read expiration time per row
+    UpdateRowsExpirationTimePerRow {
+        confirmation_id: i64,
+        table_name: String,
+        partition_key: String,
+        rows: Vec<(String, Option<DateTimeAsMicroseconds>)>,
+    },
     Confirmation {
         confirmation_id: i64,
     },
+    CompressedPayloadWithAlgorithm {
+        algorithm: CompressionAlgorithm,
+        dictionary_id: Option<u32>,
+        payload: Vec<u8>,
+    },
+    CompressionDictionary {
+        dictionary_id: u32,
+        table_name: String,
+        dictionary: Vec<u8>,
+    },
+    ChecksummedPayload {
+        algorithm: ChecksumAlgorithm,
+        payload: Vec<u8>,
+    },
+    EncryptedPayload {
+        key_id: u32,
+        nonce: [u8; ENCRYPTION_NONCE_SIZE],
+        ciphertext: Vec<u8>,
+    },
+    // Sync updates of several tables confirmed with one confirmation
+    UpdateLastReadTimeBatch {
+        confirmation_id: i64,
+        tables: Vec<LastReadTimeBatchTcpContract>,
+    },
+    UpdateExpirationTimeBatch {
+        confirmation_id: i64,
+        tables: Vec<ExpirationTimeBatchTcpContract>,
+    },
+    Capabilities(PeerCapabilities),
 }
 
 impl MyNoSqlTcpContract {
     pub fn compress_if_make_sence_and_serialize(&self) -> Vec<u8> {
-        if let Self::CompressedPayload(_) = self {
+        self.compress_and_serialize(
+            CompressionAlgorithm::Zip,
+            &CompressionPolicy::legacy(),
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    pub fn compress_if_make_sence_and_serialize_with_metrics(
+        &self,
+        metrics: &dyn ProtocolMetrics,
+    ) -> Vec<u8> {
+        self.compress_and_serialize(
+            CompressionAlgorithm::Zip,
+            &CompressionPolicy::legacy(),
+            None,
+            Some(metrics),
+            None,
+            None,
+        )
+    }
+
+    pub fn compress_with_algorithm_if_make_sence_and_serialize(
+        &self,
+        algorithm: CompressionAlgorithm,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Vec<u8> {
+        self.compress_and_serialize(
+            algorithm,
+            &CompressionPolicy::default(),
+            None,
+            metrics,
+            None,
+            None,
+        )
+    }
+
+    pub fn compress_with_policy_if_make_sence_and_serialize(
+        &self,
+        algorithm: CompressionAlgorithm,
+        policy: &CompressionPolicy,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Vec<u8> {
+        self.compress_and_serialize(algorithm, policy, None, metrics, None, None)
+    }
+
+    pub fn compress_with_dictionary_if_make_sence_and_serialize(
+        &self,
+        policy: &CompressionPolicy,
+        dictionaries: &CompressionDictionaries,
+        dictionary_id: u32,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Vec<u8> {
+        self.compress_and_serialize(
+            CompressionAlgorithm::Zstd,
+            policy,
+            Some((dictionaries, dictionary_id)),
+            metrics,
+            None,
+            None,
+        )
+    }
+
+    pub async fn compress_offloaded_if_make_sence_and_serialize(
+        self,
+        algorithm: CompressionAlgorithm,
+        policy: Arc<CompressionPolicy>,
+        dictionary: Option<(Arc<CompressionDictionaries>, u32)>,
+        offload: &CompressionOffload,
+        metrics: Option<Arc<dyn ProtocolMetrics>>,
+        cancellation: &CancellationToken,
+    ) -> Option<Vec<u8>> {
+        let algorithm = if dictionary.is_some() {
+            CompressionAlgorithm::Zstd
+        } else {
+            algorithm
+        };
+
+        if self.serialized_len() < offload.min_compress_size {
+            let result = self.compress_and_serialize(
+                algorithm,
+                policy.as_ref(),
+                dictionary
+                    .as_ref()
+                    .map(|(dictionaries, id)| (dictionaries.as_ref(), *id)),
+                metrics.as_deref(),
+                None,
+                None,
+            );
+            return Some(result);
+        }
+
+        let task_cancellation = cancellation.clone();
+
+        offload
+            .run(cancellation, move || {
+                self.compress_and_serialize(
+                    algorithm,
+                    policy.as_ref(),
+                    dictionary
+                        .as_ref()
+                        .map(|(dictionaries, id)| (dictionaries.as_ref(), *id)),
+                    metrics.as_deref(),
+                    None,
+                    Some(&task_cancellation),
+                )
+            })
+            .await
+    }
+
+    // With pool the intermediate buffers are reused, so only the resulting frame is allocated
+    pub(crate) fn compress_and_serialize(
+        &self,
+        algorithm: CompressionAlgorithm,
+        policy: &CompressionPolicy,
+        dictionary: Option<(&CompressionDictionaries, u32)>,
+        metrics: Option<&dyn ProtocolMetrics>,
+        pool: Option<&BufferPool>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Vec<u8> {
+        if self.is_compressed() {
             panic!("You can not get compresed payload from compressed payload");
         }
 
-        let non_compressed = self.serialize();
+        if !policy.should_compress(self) {
+            return self.serialize();
+        }
+
+        let mut non_compressed = match pool {
+            Some(pool) => pool.get(self.serialized_len()),
+            None => Vec::new(),
+        };
 
-        let compressed = super::payload_comressor::compress(non_compressed.as_slice()).unwrap();
+        self.serialize_into(&mut non_compressed);
 
-        if compressed.len() + 10 < non_compressed.len() {
-            Self::CompressedPayload(compressed).serialize()
-        } else {
-            non_compressed
+        let result = self.compress_serialized(
+            non_compressed.as_slice(),
+            algorithm,
+            policy,
+            dictionary,
+            metrics,
+            CompressionResources { pool, cancellation },
+        );
+
+        match result {
+            Some(result) => {
+                if let Some(pool) = pool {
+                    pool.put(non_compressed);
+                }
+                result
+            }
+            None => non_compressed,
+        }
+    }
+
+    // Compresses already serialized contract. Returns None if compression failed or does not make sence
+    pub(crate) fn compress_serialized(
+        &self,
+        non_compressed: &[u8],
+        algorithm: CompressionAlgorithm,
+        policy: &CompressionPolicy,
+        dictionary: Option<(&CompressionDictionaries, u32)>,
+        metrics: Option<&dyn ProtocolMetrics>,
+        resources: CompressionResources,
+    ) -> Option<Vec<u8>> {
+        let CompressionResources { pool, cancellation } = resources;
+
+        let mut compressed = match pool {
+            Some(pool) => pool.get(non_compressed.len() / 2),
+            None => Vec::new(),
+        };
+
+        let compress_result = match dictionary {
+            Some((dictionaries, dictionary_id)) => dictionaries.compress_into_cancellable(
+                dictionary_id,
+                non_compressed,
+                &mut compressed,
+                cancellation,
+            ),
+            None => super::payload_comressor::compress_with_algorithm_into_cancellable(
+                non_compressed,
+                algorithm,
+                policy.level,
+                &mut compressed,
+                cancellation,
+            ),
+        };
+
+        if let Err(err) = compress_result {
+            if let Some(pool) = pool {
+                pool.put(compressed);
+            }
+
+            // Nobody waits for the cancelled result
+            if crate::compression_offload::is_cancelled(cancellation) {
+                return None;
+            }
+
+            println!(
+                "Can not compress payload with {}. Sending it uncompressed. Err: {:?}",
+                algorithm.as_str(),
+                err
+            );
+
+            return None;
+        }
+
+        policy.report_result(self, non_compressed.len(), compressed.len());
+
+        let make_sence = policy.compression_makes_sence(non_compressed.len(), compressed.len());
+
+        if let Some(metrics) = metrics {
+            metrics.payload_compressed(non_compressed.len(), compressed.len(), make_sence);
+        }
+
+        if !make_sence {
+            if let Some(pool) = pool {
+                pool.put(compressed);
+            }
+            return None;
+        }
+
+        let dictionary_id = dictionary.map(|(_, dictionary_id)| dictionary_id);
+
+        let mut result = Vec::with_capacity(compressed_payload_len(
+            algorithm,
+            dictionary_id,
+            compressed.len(),
+        ));
+
+        match (algorithm, dictionary_id) {
+            (CompressionAlgorithm::Zip, None) => {
+                serialize_zip_payload(&mut result, compressed.as_slice())
+            }
+            _ => serialize_payload_with_algorithm(
+                &mut result,
+                algorithm,
+                dictionary_id,
+                compressed.as_slice(),
+            ),
+        }
+
+        if let Some(pool) = pool {
+            pool.put(compressed);
+        }
+
+        Some(result)
+    }
+
+    // Contract has payload which does not fit into i32 length of the legacy frames
+    pub fn requires_large_frames(&self) -> bool {
+        let len = match self {
+            Self::InitTable { data, .. } => data.len(),
+            Self::InitPartition { data, .. } => data.len(),
+            Self::UpdateRows { data, .. } => data.len(),
+            Self::CompressedPayload(payload) => payload.len(),
+            Self::CompressedPayloadWithAlgorithm { payload, .. } => payload.len(),
+            Self::ChecksummedPayload { payload, .. } => payload.len(),
+            Self::EncryptedPayload { ciphertext, .. } => ciphertext.len(),
+            Self::CompressionDictionary { dictionary, .. } => dictionary.len(),
+            _ => return false,
+        };
+
+        is_large_array(len)
+    }
+
+    pub fn has_large_frame_encoding(&self) -> bool {
+        matches!(
+            self,
+            Self::InitTable { .. }
+                | Self::CompressedPayload(_)
+                | Self::CompressedPayloadWithAlgorithm { .. }
+                | Self::ChecksummedPayload { .. }
+                | Self::EncryptedPayload { .. }
+        )
+    }
+
+    // Packets which carry table data. Only these are wrapped with checksum
+    pub fn is_checksummed_packet_type(packet_type: u8) -> bool {
+        matches!(
+            packet_type,
+            INIT_TABLE
+                | LARGE_INIT_TABLE
+                | INIT_PARTITION
+                | UPDATE_ROWS
+                | COMPRESSED_PAYLOAD
+                | LARGE_COMPRESSED_PAYLOAD
+                | COMPRESSED_PAYLOAD_WITH_ALGORITHM
+        )
+    }
+
+    // Packets which carry table data or keys of the table. Once keys are configured these are
+    // sent only encrypted, and the reader refuses them in plain text
+    pub fn is_encrypted_packet_type(packet_type: u8) -> bool {
+        matches!(
+            packet_type,
+            INIT_TABLE
+                | LARGE_INIT_TABLE
+                | INIT_PARTITION
+                | UPDATE_ROWS
+                | DELETE_ROWS
+                | COMPRESSED_PAYLOAD
+                | LARGE_COMPRESSED_PAYLOAD
+                | COMPRESSED_PAYLOAD_WITH_ALGORITHM
+                | COMPRESSION_DICTIONARY
+                | UPDATE_PARTITIONS_LAST_READ_TIME
+                | UPDATE_ROWS_LAST_READ_TIME
+                | UPDATE_PARTITIONS_EXPIRATION_TIME
+                | UPDATE_ROWS_EXPIRATION_TIME
+                | UPDATE_LAST_READ_TIME_BATCH
+                | UPDATE_EXPIRATION_TIME_BATCH
+        )
+    }
+
+    // Frame uses varint lengths and can only be sent to peers which support large frames
+    pub fn is_large_frame(frame: &[u8]) -> bool {
+        let protocol_version = frame.get(1).copied().unwrap_or_default();
+
+        match frame.first() {
+            Some(&LARGE_INIT_TABLE) | Some(&LARGE_COMPRESSED_PAYLOAD) => true,
+            Some(&COMPRESSED_PAYLOAD_WITH_ALGORITHM) => protocol_version & 2 > 0,
+            Some(&CHECKSUMMED_PAYLOAD) | Some(&ENCRYPTED_PAYLOAD) => protocol_version > 0,
+            _ => false,
+        }
+    }
+
+    pub fn wrap_frame_with_checksum(
+        frame: &[u8],
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<u8>, SerializeError> {
+        let mut result = Vec::with_capacity(3 + byte_array_len(frame.len()) + 8);
+        Self::wrap_frame_with_checksum_into(frame, algorithm, &mut result)?;
+        Ok(result)
+    }
+
+    pub fn wrap_frame_with_checksum_into(
+        frame: &[u8],
+        algorithm: ChecksumAlgorithm,
+        buffer: &mut impl SerializerBuffer,
+    ) -> Result<(), SerializeError> {
+        let checksum = algorithm
+            .calculate(frame)
+            .ok_or(SerializeError::UnsupportedChecksumAlgorithm(algorithm))?;
+
+        serialize_checksummed_payload(buffer, algorithm, checksum, frame);
+        Ok(())
+    }
+
+    pub async fn unwrap_checksummed_payload(
+        self,
+        limits: &DecodeLimits,
+    ) -> Result<Self, DecodeError> {
+        match self {
+            Self::ChecksummedPayload { payload, .. } => {
+                let mut reader = SocketReaderInMem::new(payload);
+                Self::deserialize_with_limits(&mut reader, limits).await
+            }
+            _ => Ok(self),
+        }
+    }
+
+    // Frame is usually compressed before it is encrypted, since ciphertext does not compress.
+    // Returns None if the cipher refuses the frame
+    #[cfg(feature = "encryption")]
+    pub fn encrypt_frame(
+        frame: &[u8],
+        key_id: u32,
+        key: &[u8; ENCRYPTION_KEY_SIZE],
+    ) -> Option<Vec<u8>> {
+        let (nonce, ciphertext) = crate::payload_encryption::encrypt(key_id, key, frame)?;
+
+        let mut result =
+            Vec::with_capacity(2 + 4 + ENCRYPTION_NONCE_SIZE + byte_array_len(ciphertext.len()));
+        serialize_encrypted_payload(&mut result, key_id, &nonce, ciphertext.as_slice());
+        Some(result)
+    }
+
+    // Data packets which came in plain text are refused, so the peer can not downgrade the connection
+    #[cfg(feature = "encryption")]
+    pub async fn decrypt_if_encrypted(
+        self,
+        limits: &DecodeLimits,
+        key_provider: &dyn KeyProvider,
+    ) -> Result<Self, DecodeError> {
+        match self {
+            Self::EncryptedPayload {
+                key_id,
+                nonce,
+                ciphertext,
+            } => {
+                let key = key_provider
+                    .get_key(key_id)
+                    .ok_or(DecodeError::UnknownEncryptionKey(key_id))?;
+
+                let payload = crate::payload_encryption::decrypt(
+                    key_id,
+                    &key,
+                    &nonce,
+                    ciphertext.as_slice(),
+                )?;
+
+                let mut reader = SocketReaderInMem::new(payload);
+                Self::deserialize_with_limits(&mut reader, limits).await
+            }
+            _ => {
+                if Self::is_encrypted_packet_type(self.get_packet_type()) {
+                    return Err(DecodeError::UnencryptedPayload {
+                        packet_name: self.get_packet_name(),
+                    });
+                }
+
+                Ok(self)
+            }
+        }
+    }
+
+    // Reader without keys can not read encrypted payloads
+    pub fn check_not_encrypted(self) -> Result<Self, DecodeError> {
+        match self {
+            Self::EncryptedPayload { key_id, .. } => {
+                if crate::payload_encryption::is_supported() {
+                    Err(DecodeError::UnknownEncryptionKey(key_id))
+                } else {
+                    Err(DecodeError::UnsupportedEncryption)
+                }
+            }
+            _ => Ok(self),
+        }
+    }
+
+    // Counterpart of checksum and compression the data packet can be wrapped with on the way
+    pub async fn unwrap_envelope(
+        self,
+        limits: &DecodeLimits,
+        dictionaries: Option<&CompressionDictionaries>,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Result<Self, DecodeError> {
+        let contract = self.unwrap_checksummed_payload(limits).await?;
+        let contract = contract.check_not_encrypted()?;
+        contract.decompress(limits, dictionaries, metrics).await
+    }
+
+    // Same as unwrap_envelope, but data packets have to be encrypted with one of the keys
+    #[cfg(feature = "encryption")]
+    pub async fn unwrap_encrypted_envelope(
+        self,
+        limits: &DecodeLimits,
+        key_provider: &dyn KeyProvider,
+        dictionaries: Option<&CompressionDictionaries>,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Result<Self, DecodeError> {
+        let contract = self.unwrap_checksummed_payload(limits).await?;
+        let contract = contract.decrypt_if_encrypted(limits, key_provider).await?;
+        contract.decompress(limits, dictionaries, metrics).await
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        match self {
+            Self::CompressedPayload(_) => true,
+            Self::CompressedPayloadWithAlgorithm { .. } => true,
+            _ => false,
         }
     }
 
     pub async fn decompress_if_compressed(self) -> Result<Self, ReadingTcpContractFail> {
-        if let Self::CompressedPayload(payload) = self {
-            let uncompressed_payload =
-                super::payload_comressor::decompress(payload.as_slice()).unwrap();
+        let result = self
+            .decompress_if_compressed_with_limits(&DecodeLimits::default())
+            .await?;
+        Ok(result)
+    }
 
-            let mut reader = SocketReaderInMem::new(uncompressed_payload);
+    pub async fn decompress_if_compressed_with_limits(
+        self,
+        limits: &DecodeLimits,
+    ) -> Result<Self, DecodeError> {
+        self.decompress(limits, None, None).await
+    }
 
-            Self::deserialize(&mut reader).await
-        } else {
-            Ok(self)
+    pub async fn decompress_if_compressed_with_metrics(
+        self,
+        limits: &DecodeLimits,
+        metrics: &dyn ProtocolMetrics,
+    ) -> Result<Self, DecodeError> {
+        self.decompress(limits, None, Some(metrics)).await
+    }
+
+    pub async fn decompress_if_compressed_with_dictionaries(
+        self,
+        limits: &DecodeLimits,
+        dictionaries: &CompressionDictionaries,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Result<Self, DecodeError> {
+        self.decompress(limits, Some(dictionaries), metrics).await
+    }
+
+    pub async fn decompress_if_compressed_offloaded(
+        self,
+        limits: &DecodeLimits,
+        dictionaries: Option<Arc<CompressionDictionaries>>,
+        offload: &CompressionOffload,
+        metrics: Option<&dyn ProtocolMetrics>,
+        cancellation: &CancellationToken,
+    ) -> Result<Self, DecodeError> {
+        let contract = self.unwrap_checksummed_payload(limits).await?;
+
+        let compressed_size = match &contract {
+            Self::CompressedPayload(payload) => payload.len(),
+            Self::CompressedPayloadWithAlgorithm { payload, .. } => payload.len(),
+            _ => return Ok(contract),
+        };
+
+        if compressed_size < offload.min_decompress_size {
+            return contract
+                .decompress(limits, dictionaries.as_deref(), metrics)
+                .await;
+        }
+
+        let (algorithm, dictionary_id, payload) = contract.into_compressed_parts();
+
+        let started = Instant::now();
+        let max_size = limits.max_decompressed_size;
+
+        let task_cancellation = cancellation.clone();
+
+        let uncompressed_payload = offload
+            .run(cancellation, move || {
+                Self::decompress_payload(
+                    algorithm,
+                    dictionary_id,
+                    payload.as_slice(),
+                    dictionaries.as_deref(),
+                    max_size,
+                    Some(&task_cancellation),
+                )
+            })
+            .await
+            .ok_or(DecodeError::Cancelled)??;
+
+        Self::deserialize_decompressed(
+            uncompressed_payload,
+            compressed_size,
+            started,
+            limits,
+            metrics,
+        )
+        .await
+    }
+
+    async fn decompress(
+        self,
+        limits: &DecodeLimits,
+        dictionaries: Option<&CompressionDictionaries>,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Result<Self, DecodeError> {
+        let contract = self.unwrap_checksummed_payload(limits).await?;
+
+        if !contract.is_compressed() {
+            return Ok(contract);
+        }
+
+        let (algorithm, dictionary_id, payload) = contract.into_compressed_parts();
+
+        let started = Instant::now();
+
+        let uncompressed_payload = Self::decompress_payload(
+            algorithm,
+            dictionary_id,
+            payload.as_slice(),
+            dictionaries,
+            limits.max_decompressed_size,
+            None,
+        )?;
+
+        Self::deserialize_decompressed(
+            uncompressed_payload,
+            payload.len(),
+            started,
+            limits,
+            metrics,
+        )
+        .await
+    }
+
+    fn into_compressed_parts(self) -> (CompressionAlgorithm, Option<u32>, Vec<u8>) {
+        match self {
+            Self::CompressedPayload(payload) => (CompressionAlgorithm::Zip, None, payload),
+            Self::CompressedPayloadWithAlgorithm {
+                algorithm,
+                dictionary_id,
+                payload,
+            } => (algorithm, dictionary_id, payload),
+            _ => panic!("Contract {} is not compressed", self.get_packet_name()),
+        }
+    }
+
+    fn decompress_payload(
+        algorithm: CompressionAlgorithm,
+        dictionary_id: Option<u32>,
+        payload: &[u8],
+        dictionaries: Option<&CompressionDictionaries>,
+        max_size: usize,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, DecodeError> {
+        match (dictionary_id, dictionaries) {
+            (Some(dictionary_id), Some(dictionaries)) => {
+                dictionaries.decompress_cancellable(dictionary_id, payload, max_size, cancellation)
+            }
+            (Some(dictionary_id), None) => {
+                Err(DecodeError::UnknownCompressionDictionary(dictionary_id))
+            }
+            (None, _) => super::payload_comressor::decompress_with_algorithm_cancellable(
+                payload,
+                algorithm,
+                max_size,
+                cancellation,
+            ),
         }
     }
 
+    async fn deserialize_decompressed(
+        uncompressed_payload: Vec<u8>,
+        compressed_size: usize,
+        started: Instant,
+        limits: &DecodeLimits,
+        metrics: Option<&dyn ProtocolMetrics>,
+    ) -> Result<Self, DecodeError> {
+        if let Some(metrics) = metrics {
+            metrics.payload_decompressed(
+                compressed_size,
+                uncompressed_payload.len(),
+                started.elapsed(),
+            );
+        }
+
+        let mut reader = SocketReaderInMem::new(uncompressed_payload);
+
+        Self::deserialize_with_limits(&mut reader, limits).await
+    }
+
     pub async fn deserialize<TSocketReader: SocketReader>(
         socket_reader: &mut TSocketReader,
     ) -> Result<Self, ReadingTcpContractFail> {
+        let result = Self::deserialize_with_limits(socket_reader, &DecodeLimits::default()).await?;
+        Ok(result)
+    }
+
+    pub async fn deserialize_with_limits<TSocketReader: SocketReader>(
+        socket_reader: &mut TSocketReader,
+        limits: &DecodeLimits,
+    ) -> Result<Self, DecodeError> {
         let packet_no = socket_reader.read_byte().await?;
 
         let result = match packet_no {
@@ -121,7 +810,17 @@ impl MyNoSqlTcpContract {
             INIT_TABLE => {
                 let table_name =
                     crate::common_deserializers::read_pascal_string(socket_reader).await?;
-                let data = socket_reader.read_byte_array().await?;
+                let data =
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?;
+                Ok(Self::InitTable { table_name, data })
+            }
+            LARGE_INIT_TABLE => {
+                let _protocol_version = socket_reader.read_byte().await?;
+                let table_name =
+                    crate::common_deserializers::read_pascal_string(socket_reader).await?;
+                let data =
+                    crate::common_deserializers::read_large_byte_array(socket_reader, limits)
+                        .await?;
                 Ok(Self::InitTable { table_name, data })
             }
             INIT_PARTITION => {
@@ -129,7 +828,8 @@ impl MyNoSqlTcpContract {
                     crate::common_deserializers::read_pascal_string(socket_reader).await?;
                 let partition_key =
                     crate::common_deserializers::read_pascal_string(socket_reader).await?;
-                let data = socket_reader.read_byte_array().await?;
+                let data =
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?;
                 Ok(Self::InitPartition {
                     table_name,
                     partition_key,
@@ -140,16 +840,17 @@ impl MyNoSqlTcpContract {
                 let table_name =
                     crate::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                let data = socket_reader.read_byte_array().await?;
+                let data =
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?;
                 Ok(Self::UpdateRows { table_name, data })
             }
             DELETE_ROWS => {
                 let table_name =
                     crate::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                let rows_amount = socket_reader.read_i32().await?;
+                let rows_amount = limits.check_rows_to_delete(socket_reader.read_i32().await?)?;
 
-                let mut rows = Vec::new();
+                let mut rows = crate::common_deserializers::list_with_capacity(rows_amount);
 
                 for _ in 0..rows_amount {
                     let row = DeleteRowTcpContract::deserialize(socket_reader).await?;
@@ -162,16 +863,16 @@ impl MyNoSqlTcpContract {
                 let packet_version = socket_reader.read_byte().await?;
 
                 if packet_version != 0 {
-                    panic!(
-                        "Unexpected packet version. Version is: {}, but expected version is 0",
-                        packet_version
-                    );
+                    return Err(DecodeError::UnsupportedPacketVersion {
+                        packet_no,
+                        version: packet_version,
+                    });
                 }
 
                 let message =
                     crate::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                panic!("TCP protocol error: {}", message);
+                return Err(DecodeError::PeerError(message));
             }
             GREETING_FROM_NODE => {
                 let packet_version = socket_reader.read_byte().await?;
@@ -187,6 +888,7 @@ impl MyNoSqlTcpContract {
                     compress = socket_reader.read_bool().await?;
                 }
 
+                // Version 2 has the body of version 1. It tells that node knows the Capabilities packet
                 Ok(Self::GreetingFromNode {
                     node_location,
                     node_version,
@@ -215,26 +917,53 @@ impl MyNoSqlTcpContract {
                 Ok(Self::Unsubscribe(table_name))
             }
             COMPRESSED_PAYLOAD => {
-                let data = socket_reader.read_byte_array().await?;
+                let data =
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?;
                 Ok(Self::CompressedPayload(data))
             }
-            UPDATE_PARTITIONS_LAST_READ_TIME => {
+            LARGE_COMPRESSED_PAYLOAD => {
                 let _protocol_version = socket_reader.read_byte().await?;
+                let data =
+                    crate::common_deserializers::read_large_byte_array(socket_reader, limits)
+                        .await?;
+                Ok(Self::CompressedPayload(data))
+            }
+            UPDATE_PARTITIONS_LAST_READ_TIME => {
+                let packet_version = socket_reader.read_byte().await?;
                 let confirmation_id = socket_reader.read_i64().await?;
                 let table_name =
                     super::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                let partitions =
-                    super::common_deserializers::read_list_of_pascal_strings(socket_reader).await?;
+                if packet_version == 0 {
+                    let partitions =
+                        super::common_deserializers::read_list_of_pascal_strings_with_limits(
+                            socket_reader,
+                            limits,
+                        )
+                        .await?;
+
+                    return Ok(Self::UpdatePartitionsLastReadTime {
+                        confirmation_id,
+                        table_name,
+                        partitions,
+                    });
+                }
+
+                let partitions = super::common_deserializers::read_list_of_keys_with_moments(
+                    socket_reader,
+                    limits,
+                    true,
+                )
+                .await?;
 
-                Ok(Self::UpdatePartitionsLastReadTime {
+                Ok(Self::UpdatePartitionsReadMoments {
                     confirmation_id,
                     table_name,
                     partitions,
                 })
             }
             UPDATE_ROWS_LAST_READ_TIME => {
-                let _protocol_version = socket_reader.read_byte().await?;
+                let packet_version = socket_reader.read_byte().await?;
                 let confirmation_id = socket_reader.read_i64().await?;
                 let table_name =
                     super::common_deserializers::read_pascal_string(socket_reader).await?;
@@ -242,14 +971,34 @@ impl MyNoSqlTcpContract {
                 let partition_key =
                     super::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                let row_keys =
-                    super::common_deserializers::read_list_of_pascal_strings(socket_reader).await?;
+                if packet_version == 0 {
+                    let row_keys =
+                        super::common_deserializers::read_list_of_pascal_strings_with_limits(
+                            socket_reader,
+                            limits,
+                        )
+                        .await?;
 
-                Ok(Self::UpdateRowsLastReadTime {
+                    return Ok(Self::UpdateRowsLastReadTime {
+                        confirmation_id,
+                        table_name,
+                        partition_key,
+                        row_keys,
+                    });
+                }
+
+                let rows = super::common_deserializers::read_list_of_keys_with_moments(
+                    socket_reader,
+                    limits,
+                    true,
+                )
+                .await?;
+
+                Ok(Self::UpdateRowsReadMoments {
                     confirmation_id,
                     table_name,
                     partition_key,
-                    row_keys,
+                    rows,
                 })
             }
 
@@ -259,9 +1008,9 @@ impl MyNoSqlTcpContract {
                 let table_name =
                     super::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                let amount = socket_reader.read_i32().await? as usize;
+                let amount = limits.check_list_len(socket_reader.read_i32().await?)?;
 
-                let mut partitions = Vec::with_capacity(amount);
+                let mut partitions = crate::common_deserializers::list_with_capacity(amount);
 
                 for _ in 0..amount {
                     let partition_key =
@@ -280,7 +1029,7 @@ impl MyNoSqlTcpContract {
             }
 
             UPDATE_ROWS_EXPIRATION_TIME => {
-                let _protocol_version = socket_reader.read_byte().await?;
+                let packet_version = socket_reader.read_byte().await?;
                 let confirmation_id = socket_reader.read_i64().await?;
                 let table_name =
                     super::common_deserializers::read_pascal_string(socket_reader).await?;
@@ -288,18 +1037,44 @@ impl MyNoSqlTcpContract {
                 let partition_key =
                     super::common_deserializers::read_pascal_string(socket_reader).await?;
 
-                let row_keys =
-                    super::common_deserializers::read_list_of_pascal_strings(socket_reader).await?;
+                if packet_version == 0 {
+                    let row_keys =
+                        super::common_deserializers::read_list_of_pascal_strings_with_limits(
+                            socket_reader,
+                            limits,
+                        )
+                        .await?;
+
+                    let expiration_time =
+                        super::common_deserializers::read_date_time_opt(socket_reader).await?;
+
+                    return Ok(Self::UpdateRowsExpirationTime {
+                        confirmation_id,
+                        table_name,
+                        partition_key,
+                        row_keys,
+                        expiration_time,
+                    });
+                }
+
+                let amount = limits.check_list_len(socket_reader.read_i32().await?)?;
+
+                let mut rows = crate::common_deserializers::list_with_capacity(amount);
 
-                let expiration_time =
-                    super::common_deserializers::read_date_time_opt(socket_reader).await?;
+                for _ in 0..amount {
+                    let row_key =
+                        super::common_deserializers::read_pascal_string(socket_reader).await?;
+                    let expiration_time =
+                        super::common_deserializers::read_date_time_opt(socket_reader).await?;
 
-                Ok(Self::UpdateRowsExpirationTime {
+                    rows.push((row_key, expiration_time));
+                }
+
+                Ok(Self::UpdateRowsExpirationTimePerRow {
                     confirmation_id,
                     table_name,
                     partition_key,
-                    row_keys,
-                    expiration_time: expiration_time,
+                    rows,
                 })
             }
 
@@ -308,39 +1083,433 @@ impl MyNoSqlTcpContract {
                 let confirmation_id = socket_reader.read_i64().await?;
                 Ok(Self::Confirmation { confirmation_id })
             }
-            _ => Err(ReadingTcpContractFail::InvalidPacketId(packet_no)),
-        };
+            UPDATE_LAST_READ_TIME_BATCH => {
+                let packet_version = socket_reader.read_byte().await?;
+                let confirmation_id = socket_reader.read_i64().await?;
+                let amount = limits.check_list_len(socket_reader.read_i32().await?)?;
 
-        return result;
-    }
-    pub fn serialize(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        self.serialize_into(&mut buffer);
-        buffer
-    }
+                let mut tables = crate::common_deserializers::list_with_capacity(amount);
 
-    pub fn serialize_into(&self, buffer: &mut Vec<u8>) {
-        match self {
-            Self::Ping => {
-                buffer.push(PING);
-            }
-            Self::Pong => {
-                buffer.push(PONG);
-            }
-            Self::Greeting { name } => {
-                buffer.push(GREETING);
-                crate::common_serializers::serialize_pascal_string(buffer, name);
+                for _ in 0..amount {
+                    tables.push(
+                        LastReadTimeBatchTcpContract::deserialize(
+                            socket_reader,
+                            limits,
+                            packet_version > 0,
+                        )
+                        .await?,
+                    );
+                }
+
+                Ok(Self::UpdateLastReadTimeBatch {
+                    confirmation_id,
+                    tables,
+                })
             }
-            Self::Subscribe { table_name } => {
-                buffer.push(SUBSCRIBE);
-                crate::common_serializers::serialize_pascal_string(buffer, table_name);
+            UPDATE_EXPIRATION_TIME_BATCH => {
+                let _protocol_version = socket_reader.read_byte().await?;
+                let confirmation_id = socket_reader.read_i64().await?;
+                let amount = limits.check_list_len(socket_reader.read_i32().await?)?;
+
+                let mut tables = crate::common_deserializers::list_with_capacity(amount);
+
+                for _ in 0..amount {
+                    tables.push(
+                        ExpirationTimeBatchTcpContract::deserialize(socket_reader, limits).await?,
+                    );
+                }
+
+                Ok(Self::UpdateExpirationTimeBatch {
+                    confirmation_id,
+                    tables,
+                })
             }
-            Self::InitTable { table_name, data } => {
-                buffer.push(INIT_TABLE);
-                crate::common_serializers::serialize_pascal_string(buffer, table_name);
-                crate::common_serializers::serialize_byte_array(buffer, data.as_slice());
+            COMPRESSED_PAYLOAD_WITH_ALGORITHM => {
+                let protocol_version = socket_reader.read_byte().await?;
+                let algorithm = socket_reader.read_byte().await?;
+
+                let algorithm = match CompressionAlgorithm::from_u8(algorithm) {
+                    Some(algorithm) => algorithm,
+                    None => return Err(DecodeError::UnsupportedCompressionAlgorithm(algorithm)),
+                };
+
+                // Version flags: 1 = payload is compressed with dictionary, 2 = length is varint
+                let dictionary_id = if protocol_version & 1 > 0 {
+                    Some(socket_reader.read_i32().await? as u32)
+                } else {
+                    None
+                };
+
+                let payload = if protocol_version & 2 > 0 {
+                    crate::common_deserializers::read_large_byte_array(socket_reader, limits)
+                        .await?
+                } else {
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?
+                };
+
+                Ok(Self::CompressedPayloadWithAlgorithm {
+                    algorithm,
+                    dictionary_id,
+                    payload,
+                })
             }
-            Self::InitPartition {
+            CHECKSUMMED_PAYLOAD => {
+                let protocol_version = socket_reader.read_byte().await?;
+                let algorithm = socket_reader.read_byte().await?;
+
+                let algorithm = match ChecksumAlgorithm::from_u8(algorithm) {
+                    Some(algorithm) => algorithm,
+                    None => return Err(DecodeError::UnsupportedChecksumAlgorithm(algorithm)),
+                };
+
+                // Version 1 = payload length is varint
+                let payload = if protocol_version > 0 {
+                    crate::common_deserializers::read_large_byte_array(socket_reader, limits)
+                        .await?
+                } else {
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?
+                };
+                let expected = socket_reader.read_i64().await? as u64;
+
+                let actual = match algorithm.calculate(payload.as_slice()) {
+                    Some(actual) => actual,
+                    None => {
+                        return Err(DecodeError::UnsupportedChecksumAlgorithm(algorithm.as_u8()))
+                    }
+                };
+
+                if actual != expected {
+                    return Err(DecodeError::ChecksumMismatch {
+                        algorithm,
+                        expected,
+                        actual,
+                    });
+                }
+
+                Ok(Self::ChecksummedPayload { algorithm, payload })
+            }
+            ENCRYPTED_PAYLOAD => {
+                let protocol_version = socket_reader.read_byte().await?;
+                let key_id = socket_reader.read_i32().await? as u32;
+
+                let mut nonce = [0u8; ENCRYPTION_NONCE_SIZE];
+                socket_reader.read_buf(&mut nonce).await?;
+
+                // Version 1 = ciphertext length is varint
+                let ciphertext = if protocol_version > 0 {
+                    crate::common_deserializers::read_large_byte_array(socket_reader, limits)
+                        .await?
+                } else {
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?
+                };
+
+                Ok(Self::EncryptedPayload {
+                    key_id,
+                    nonce,
+                    ciphertext,
+                })
+            }
+            COMPRESSION_DICTIONARY => {
+                let _protocol_version = socket_reader.read_byte().await?;
+                let dictionary_id = socket_reader.read_i32().await? as u32;
+                let table_name =
+                    crate::common_deserializers::read_pascal_string(socket_reader).await?;
+                let dictionary =
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?;
+                Ok(Self::CompressionDictionary {
+                    dictionary_id,
+                    table_name,
+                    dictionary,
+                })
+            }
+            CAPABILITIES => {
+                let _protocol_version = socket_reader.read_byte().await?;
+                let body =
+                    crate::common_deserializers::read_byte_array(socket_reader, limits).await?;
+
+                match PeerCapabilities::deserialize_body(body.as_slice()) {
+                    Some(capabilities) => Ok(Self::Capabilities(capabilities)),
+                    None => Err(DecodeError::InvalidCapabilities),
+                }
+            }
+            _ => Err(ReadingTcpContractFail::InvalidPacketId(packet_no).into()),
+        };
+
+        return result;
+    }
+    pub fn get_packet_type(&self) -> u8 {
+        match self {
+            Self::Ping => PING,
+            Self::Pong => PONG,
+            Self::Greeting { .. } => GREETING,
+            Self::Subscribe { .. } => SUBSCRIBE,
+            Self::InitTable { .. } => INIT_TABLE,
+            Self::InitPartition { .. } => INIT_PARTITION,
+            Self::UpdateRows { .. } => UPDATE_ROWS,
+            Self::DeleteRows { .. } => DELETE_ROWS,
+            Self::Error { .. } => ERROR,
+            Self::GreetingFromNode { .. } => GREETING_FROM_NODE,
+            Self::SubscribeAsNode(_) => SUBSCRIBE_AS_NODE,
+            Self::Unsubscribe(_) => UNSUBSCRIBE,
+            Self::TableNotFound(_) => TABLES_NOT_FOUND,
+            Self::CompressedPayload(_) => COMPRESSED_PAYLOAD,
+            Self::UpdatePartitionsLastReadTime { .. } => UPDATE_PARTITIONS_LAST_READ_TIME,
+            Self::UpdateRowsLastReadTime { .. } => UPDATE_ROWS_LAST_READ_TIME,
+            Self::UpdatePartitionsReadMoments { .. } => UPDATE_PARTITIONS_LAST_READ_TIME,
+            Self::UpdateRowsReadMoments { .. } => UPDATE_ROWS_LAST_READ_TIME,
+            Self::UpdatePartitionsExpirationTime { .. } => UPDATE_PARTITIONS_EXPIRATION_TIME,
+            Self::UpdateRowsExpirationTime { .. } => UPDATE_ROWS_EXPIRATION_TIME,
+            Self::UpdateRowsExpirationTimePerRow { .. } => UPDATE_ROWS_EXPIRATION_TIME,
+            Self::Confirmation { .. } => CONFIRMATION,
+            Self::CompressedPayloadWithAlgorithm { .. } => COMPRESSED_PAYLOAD_WITH_ALGORITHM,
+            Self::CompressionDictionary { .. } => COMPRESSION_DICTIONARY,
+            Self::ChecksummedPayload { .. } => CHECKSUMMED_PAYLOAD,
+            Self::EncryptedPayload { .. } => ENCRYPTED_PAYLOAD,
+            Self::UpdateLastReadTimeBatch { .. } => UPDATE_LAST_READ_TIME_BATCH,
+            Self::UpdateExpirationTimeBatch { .. } => UPDATE_EXPIRATION_TIME_BATCH,
+            Self::Capabilities(_) => CAPABILITIES,
+        }
+    }
+
+    pub fn get_packet_name(&self) -> &'static str {
+        match self {
+            Self::Ping => "Ping",
+            Self::Pong => "Pong",
+            Self::Greeting { .. } => "Greeting",
+            Self::Subscribe { .. } => "Subscribe",
+            Self::InitTable { .. } => "InitTable",
+            Self::InitPartition { .. } => "InitPartition",
+            Self::UpdateRows { .. } => "UpdateRows",
+            Self::DeleteRows { .. } => "DeleteRows",
+            Self::Error { .. } => "Error",
+            Self::GreetingFromNode { .. } => "GreetingFromNode",
+            Self::SubscribeAsNode(_) => "SubscribeAsNode",
+            Self::Unsubscribe(_) => "Unsubscribe",
+            Self::TableNotFound(_) => "TableNotFound",
+            Self::CompressedPayload(_) => "CompressedPayload",
+            Self::UpdatePartitionsLastReadTime { .. } => "UpdatePartitionsLastReadTime",
+            Self::UpdateRowsLastReadTime { .. } => "UpdateRowsLastReadTime",
+            Self::UpdatePartitionsReadMoments { .. } => "UpdatePartitionsReadMoments",
+            Self::UpdateRowsReadMoments { .. } => "UpdateRowsReadMoments",
+            Self::UpdatePartitionsExpirationTime { .. } => "UpdatePartitionsExpirationTime",
+            Self::UpdateRowsExpirationTime { .. } => "UpdateRowsExpirationTime",
+            Self::UpdateRowsExpirationTimePerRow { .. } => "UpdateRowsExpirationTimePerRow",
+            Self::Confirmation { .. } => "Confirmation",
+            Self::CompressedPayloadWithAlgorithm { .. } => "CompressedPayloadWithAlgorithm",
+            Self::CompressionDictionary { .. } => "CompressionDictionary",
+            Self::ChecksummedPayload { .. } => "ChecksummedPayload",
+            Self::EncryptedPayload { .. } => "EncryptedPayload",
+            Self::UpdateLastReadTimeBatch { .. } => "UpdateLastReadTimeBatch",
+            Self::UpdateExpirationTimeBatch { .. } => "UpdateExpirationTimeBatch",
+            Self::Capabilities(_) => "Capabilities",
+        }
+    }
+
+    pub fn get_table_name(&self) -> Option<&str> {
+        match self {
+            Self::Subscribe { table_name } => Some(table_name),
+            Self::InitTable { table_name, .. } => Some(table_name),
+            Self::InitPartition { table_name, .. } => Some(table_name),
+            Self::UpdateRows { table_name, .. } => Some(table_name),
+            Self::DeleteRows { table_name, .. } => Some(table_name),
+            Self::SubscribeAsNode(table_name) => Some(table_name),
+            Self::Unsubscribe(table_name) => Some(table_name),
+            Self::TableNotFound(table_name) => Some(table_name),
+            Self::UpdatePartitionsLastReadTime { table_name, .. } => Some(table_name),
+            Self::UpdateRowsLastReadTime { table_name, .. } => Some(table_name),
+            Self::UpdatePartitionsReadMoments { table_name, .. } => Some(table_name),
+            Self::UpdateRowsReadMoments { table_name, .. } => Some(table_name),
+            Self::UpdatePartitionsExpirationTime { table_name, .. } => Some(table_name),
+            Self::UpdateRowsExpirationTime { table_name, .. } => Some(table_name),
+            Self::UpdateRowsExpirationTimePerRow { table_name, .. } => Some(table_name),
+            Self::CompressionDictionary { table_name, .. } => Some(table_name),
+            _ => None,
+        }
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            Self::Ping => 1,
+            Self::Pong => 1,
+            Self::Greeting { name } => 1 + pascal_string_len(name),
+            Self::Subscribe { table_name } => 1 + pascal_string_len(table_name),
+            Self::InitTable { table_name, data } => {
+                if is_large_array(data.len()) {
+                    2 + pascal_string_len(table_name) + byte_array_len(data.len())
+                } else {
+                    1 + pascal_string_len(table_name) + byte_array_len(data.len())
+                }
+            }
+            Self::InitPartition {
+                table_name,
+                partition_key,
+                data,
+            } => {
+                1 + pascal_string_len(table_name)
+                    + pascal_string_len(partition_key)
+                    + 4
+                    + data.len()
+            }
+            Self::UpdateRows { table_name, data } => {
+                1 + pascal_string_len(table_name) + 4 + data.len()
+            }
+            Self::DeleteRows { table_name, rows } => {
+                1 + pascal_string_len(table_name)
+                    + 4
+                    + rows.iter().map(|row| row.serialized_len()).sum::<usize>()
+            }
+            Self::Error { message } => 2 + pascal_string_len(message),
+            Self::GreetingFromNode {
+                node_location,
+                node_version,
+                ..
+            } => 3 + pascal_string_len(node_location) + pascal_string_len(node_version),
+            Self::SubscribeAsNode(table_name) => 2 + pascal_string_len(table_name),
+            Self::TableNotFound(table_name) => 2 + pascal_string_len(table_name),
+            Self::Unsubscribe(table_name) => 2 + pascal_string_len(table_name),
+            Self::CompressedPayload(payload) => {
+                compressed_payload_len(CompressionAlgorithm::Zip, None, payload.len())
+            }
+            Self::UpdatePartitionsLastReadTime {
+                table_name,
+                partitions,
+                ..
+            } => 10 + pascal_string_len(table_name) + list_of_pascal_strings_len(partitions),
+            Self::UpdateRowsLastReadTime {
+                table_name,
+                partition_key,
+                row_keys,
+                ..
+            } => {
+                10 + pascal_string_len(table_name)
+                    + pascal_string_len(partition_key)
+                    + list_of_pascal_strings_len(row_keys)
+            }
+            Self::UpdatePartitionsReadMoments {
+                table_name,
+                partitions,
+                ..
+            } => 10 + pascal_string_len(table_name) + list_of_keys_with_moments_len(partitions),
+            Self::UpdateRowsReadMoments {
+                table_name,
+                partition_key,
+                rows,
+                ..
+            } => {
+                10 + pascal_string_len(table_name)
+                    + pascal_string_len(partition_key)
+                    + list_of_keys_with_moments_len(rows)
+            }
+            Self::UpdatePartitionsExpirationTime {
+                table_name,
+                partitions,
+                ..
+            } => {
+                10 + pascal_string_len(table_name)
+                    + 4
+                    + partitions
+                        .iter()
+                        .map(|(partition_key, _)| pascal_string_len(partition_key) + 8)
+                        .sum::<usize>()
+            }
+            Self::UpdateRowsExpirationTime {
+                table_name,
+                partition_key,
+                row_keys,
+                ..
+            } => {
+                10 + pascal_string_len(table_name)
+                    + pascal_string_len(partition_key)
+                    + list_of_pascal_strings_len(row_keys)
+                    + 8
+            }
+            Self::UpdateRowsExpirationTimePerRow {
+                table_name,
+                partition_key,
+                rows,
+                ..
+            } => {
+                10 + pascal_string_len(table_name)
+                    + pascal_string_len(partition_key)
+                    + list_of_keys_with_moments_len(rows)
+            }
+            Self::Confirmation { .. } => 10,
+            Self::CompressedPayloadWithAlgorithm {
+                algorithm,
+                dictionary_id,
+                payload,
+            } => compressed_payload_len(*algorithm, *dictionary_id, payload.len()),
+            Self::CompressionDictionary {
+                table_name,
+                dictionary,
+                ..
+            } => 2 + 4 + pascal_string_len(table_name) + 4 + dictionary.len(),
+            Self::ChecksummedPayload { payload, .. } => 3 + byte_array_len(payload.len()) + 8,
+            Self::EncryptedPayload { ciphertext, .. } => {
+                2 + 4 + ENCRYPTION_NONCE_SIZE + byte_array_len(ciphertext.len())
+            }
+            Self::UpdateLastReadTimeBatch { tables, .. } => {
+                let with_moments = tables.iter().any(|itm| itm.has_read_moments());
+
+                10 + 4
+                    + tables
+                        .iter()
+                        .map(|itm| itm.serialized_len(with_moments))
+                        .sum::<usize>()
+            }
+            Self::UpdateExpirationTimeBatch { tables, .. } => {
+                10 + 4 + tables.iter().map(|itm| itm.serialized_len()).sum::<usize>()
+            }
+            Self::Capabilities(capabilities) => 2 + 4 + capabilities.body_len(),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.serialize_into(&mut buffer);
+        buffer
+    }
+
+    pub fn serialize_to_writer<TWrite: std::io::Write>(
+        &self,
+        writer: &mut TWrite,
+    ) -> std::io::Result<()> {
+        let mut buffer = WriteSerializerBuffer::new(writer);
+        self.serialize_into(&mut buffer);
+        buffer.finish()
+    }
+
+    pub fn serialize_into(&self, buffer: &mut impl SerializerBuffer) {
+        buffer.reserve(self.serialized_len());
+
+        match self {
+            Self::Ping => {
+                buffer.push(PING);
+            }
+            Self::Pong => {
+                buffer.push(PONG);
+            }
+            Self::Greeting { name } => {
+                buffer.push(GREETING);
+                crate::common_serializers::serialize_pascal_string(buffer, name);
+            }
+            Self::Subscribe { table_name } => {
+                buffer.push(SUBSCRIBE);
+                crate::common_serializers::serialize_pascal_string(buffer, table_name);
+            }
+            Self::InitTable { table_name, data } => {
+                if is_large_array(data.len()) {
+                    buffer.push(LARGE_INIT_TABLE);
+                    buffer.push(0); // Protocol version
+                    crate::common_serializers::serialize_pascal_string(buffer, table_name);
+                    crate::common_serializers::serialize_large_byte_array(buffer, data.as_slice());
+                } else {
+                    buffer.push(INIT_TABLE);
+                    crate::common_serializers::serialize_pascal_string(buffer, table_name);
+                    crate::common_serializers::serialize_byte_array(buffer, data.as_slice());
+                }
+            }
+            Self::InitPartition {
                 table_name,
                 partition_key,
                 data,
@@ -376,18 +1545,12 @@ impl MyNoSqlTcpContract {
                 node_version,
                 compress,
             } => {
-                if *compress {
-                    buffer.push(GREETING_FROM_NODE);
-                    buffer.push(1);
-                    crate::common_serializers::serialize_pascal_string(buffer, node_location);
-                    crate::common_serializers::serialize_pascal_string(buffer, node_version);
-                    buffer.push(1);
-                } else {
-                    buffer.push(GREETING_FROM_NODE);
-                    buffer.push(0);
-                    crate::common_serializers::serialize_pascal_string(buffer, node_location);
-                    crate::common_serializers::serialize_pascal_string(buffer, node_version);
-                }
+                // Body of version 1 is read by main nodes which do not know the Capabilities packet
+                buffer.push(GREETING_FROM_NODE);
+                buffer.push(GREETING_WITH_CAPABILITIES_VERSION);
+                crate::common_serializers::serialize_pascal_string(buffer, node_location);
+                crate::common_serializers::serialize_pascal_string(buffer, node_version);
+                crate::common_serializers::serialize_bool(buffer, *compress);
             }
 
             Self::SubscribeAsNode(table_name) => {
@@ -411,8 +1574,7 @@ impl MyNoSqlTcpContract {
                 crate::common_serializers::serialize_pascal_string(buffer, table_name.as_str());
             }
             Self::CompressedPayload(payload) => {
-                buffer.push(COMPRESSED_PAYLOAD);
-                crate::common_serializers::serialize_byte_array(buffer, payload.as_slice());
+                serialize_zip_payload(buffer, payload.as_slice());
             }
             Self::UpdatePartitionsLastReadTime {
                 table_name,
@@ -438,6 +1600,32 @@ impl MyNoSqlTcpContract {
                 crate::common_serializers::serialize_pascal_string(buffer, &partition_key.as_str());
                 crate::common_serializers::serialize_list_of_pascal_strings(buffer, row_keys);
             }
+            Self::UpdatePartitionsReadMoments {
+                table_name,
+                confirmation_id,
+                partitions,
+            } => {
+                buffer.push(UPDATE_PARTITIONS_LAST_READ_TIME);
+                crate::common_serializers::serialize_byte(buffer, 1); // Protocol version
+                crate::common_serializers::serialize_i64(buffer, *confirmation_id);
+                crate::common_serializers::serialize_pascal_string(buffer, table_name.as_str());
+                crate::common_serializers::serialize_list_of_keys_with_moments(
+                    buffer, partitions, true,
+                );
+            }
+            Self::UpdateRowsReadMoments {
+                table_name,
+                confirmation_id,
+                partition_key,
+                rows,
+            } => {
+                buffer.push(UPDATE_ROWS_LAST_READ_TIME);
+                crate::common_serializers::serialize_byte(buffer, 1); // Protocol version
+                crate::common_serializers::serialize_i64(buffer, *confirmation_id);
+                crate::common_serializers::serialize_pascal_string(buffer, table_name.as_str());
+                crate::common_serializers::serialize_pascal_string(buffer, partition_key.as_str());
+                crate::common_serializers::serialize_list_of_keys_with_moments(buffer, rows, true);
+            }
 
             Self::UpdatePartitionsExpirationTime {
                 table_name,
@@ -478,16 +1666,232 @@ impl MyNoSqlTcpContract {
                 crate::common_serializers::serialize_list_of_pascal_strings(buffer, row_keys);
                 crate::common_serializers::serialize_date_time_opt(buffer, *expiration_time);
             }
+            Self::UpdateRowsExpirationTimePerRow {
+                table_name,
+                confirmation_id,
+                partition_key,
+                rows,
+            } => {
+                buffer.push(UPDATE_ROWS_EXPIRATION_TIME);
+                crate::common_serializers::serialize_byte(buffer, 1); // Protocol version
+                crate::common_serializers::serialize_i64(buffer, *confirmation_id);
+                crate::common_serializers::serialize_pascal_string(buffer, table_name.as_str());
+                crate::common_serializers::serialize_pascal_string(buffer, partition_key.as_str());
+                crate::common_serializers::serialize_list_of_keys_with_moments(buffer, rows, true);
+            }
 
             Self::Confirmation { confirmation_id } => {
                 buffer.push(CONFIRMATION);
                 crate::common_serializers::serialize_byte(buffer, 0); // Protocol version
                 crate::common_serializers::serialize_i64(buffer, *confirmation_id);
             }
+
+            Self::UpdateLastReadTimeBatch {
+                confirmation_id,
+                tables,
+            } => {
+                let with_moments = tables.iter().any(|itm| itm.has_read_moments());
+
+                buffer.push(UPDATE_LAST_READ_TIME_BATCH);
+                // Protocol version
+                crate::common_serializers::serialize_byte(buffer, with_moments as u8);
+                crate::common_serializers::serialize_i64(buffer, *confirmation_id);
+                crate::common_serializers::serialize_i32(buffer, tables.len() as i32);
+
+                for table in tables {
+                    table.serialize(buffer, with_moments);
+                }
+            }
+
+            Self::UpdateExpirationTimeBatch {
+                confirmation_id,
+                tables,
+            } => {
+                buffer.push(UPDATE_EXPIRATION_TIME_BATCH);
+                crate::common_serializers::serialize_byte(buffer, 0); // Protocol version
+                crate::common_serializers::serialize_i64(buffer, *confirmation_id);
+                crate::common_serializers::serialize_i32(buffer, tables.len() as i32);
+
+                for table in tables {
+                    table.serialize(buffer);
+                }
+            }
+
+            Self::Capabilities(capabilities) => {
+                buffer.push(CAPABILITIES);
+                crate::common_serializers::serialize_byte(buffer, 0); // Protocol version
+                crate::common_serializers::serialize_i32(buffer, capabilities.body_len() as i32);
+                capabilities.serialize_body(buffer);
+            }
+
+            Self::CompressedPayloadWithAlgorithm {
+                algorithm,
+                dictionary_id,
+                payload,
+            } => {
+                serialize_payload_with_algorithm(buffer, *algorithm, *dictionary_id, payload);
+            }
+
+            Self::ChecksummedPayload { algorithm, payload } => {
+                // Frame with the algorithm which is not compiled in is rejected by the peer as corrupted
+                let checksum = algorithm.calculate(payload.as_slice()).unwrap_or_default();
+                serialize_checksummed_payload(buffer, *algorithm, checksum, payload.as_slice());
+            }
+
+            Self::EncryptedPayload {
+                key_id,
+                nonce,
+                ciphertext,
+            } => {
+                serialize_encrypted_payload(buffer, *key_id, nonce, ciphertext.as_slice());
+            }
+
+            Self::CompressionDictionary {
+                dictionary_id,
+                table_name,
+                dictionary,
+            } => {
+                buffer.push(COMPRESSION_DICTIONARY);
+                crate::common_serializers::serialize_byte(buffer, 0); // Protocol version
+                crate::common_serializers::serialize_i32(buffer, *dictionary_id as i32);
+                crate::common_serializers::serialize_pascal_string(buffer, table_name.as_str());
+                crate::common_serializers::serialize_byte_array(buffer, dictionary.as_slice());
+            }
         }
     }
 }
 
+// Arrays which do not fit into i32 length are written with varint length.
+// Such frames can only be sent to peers which support large frames.
+fn is_large_array(len: usize) -> bool {
+    len > i32::MAX as usize
+}
+
+fn byte_array_len(len: usize) -> usize {
+    if is_large_array(len) {
+        crate::common_serializers::get_var_u64_len(len as u64) + len
+    } else {
+        4 + len
+    }
+}
+
+fn serialize_byte_array_of_any_size(buffer: &mut impl SerializerBuffer, payload: &[u8]) {
+    if is_large_array(payload.len()) {
+        crate::common_serializers::serialize_large_byte_array(buffer, payload);
+    } else {
+        crate::common_serializers::serialize_byte_array(buffer, payload);
+    }
+}
+
+fn serialize_checksummed_payload(
+    buffer: &mut impl SerializerBuffer,
+    algorithm: ChecksumAlgorithm,
+    checksum: u64,
+    payload: &[u8],
+) {
+    // Version 1 = payload length is varint
+    let protocol_version = if is_large_array(payload.len()) { 1 } else { 0 };
+
+    buffer.push(CHECKSUMMED_PAYLOAD);
+    crate::common_serializers::serialize_byte(buffer, protocol_version);
+    crate::common_serializers::serialize_byte(buffer, algorithm.as_u8());
+    serialize_byte_array_of_any_size(buffer, payload);
+    crate::common_serializers::serialize_i64(buffer, checksum as i64);
+}
+
+fn serialize_encrypted_payload(
+    buffer: &mut impl SerializerBuffer,
+    key_id: u32,
+    nonce: &[u8; ENCRYPTION_NONCE_SIZE],
+    ciphertext: &[u8],
+) {
+    // Version 1 = ciphertext length is varint
+    let protocol_version = if is_large_array(ciphertext.len()) {
+        1
+    } else {
+        0
+    };
+
+    buffer.push(ENCRYPTED_PAYLOAD);
+    crate::common_serializers::serialize_byte(buffer, protocol_version);
+    crate::common_serializers::serialize_i32(buffer, key_id as i32);
+    buffer.extend_from_slice(nonce);
+    serialize_byte_array_of_any_size(buffer, ciphertext);
+}
+
+fn serialize_zip_payload(buffer: &mut impl SerializerBuffer, payload: &[u8]) {
+    if is_large_array(payload.len()) {
+        buffer.push(LARGE_COMPRESSED_PAYLOAD);
+        buffer.push(0); // Protocol version
+        crate::common_serializers::serialize_large_byte_array(buffer, payload);
+    } else {
+        buffer.push(COMPRESSED_PAYLOAD);
+        crate::common_serializers::serialize_byte_array(buffer, payload);
+    }
+}
+
+fn compressed_payload_len(
+    algorithm: CompressionAlgorithm,
+    dictionary_id: Option<u32>,
+    payload_len: usize,
+) -> usize {
+    match (algorithm, dictionary_id) {
+        (CompressionAlgorithm::Zip, None) => {
+            if is_large_array(payload_len) {
+                2 + byte_array_len(payload_len)
+            } else {
+                1 + byte_array_len(payload_len)
+            }
+        }
+        (_, None) => 3 + byte_array_len(payload_len),
+        (_, Some(_)) => 3 + 4 + byte_array_len(payload_len),
+    }
+}
+
+// Protocol version is a set of flags: 1 = payload is compressed with dictionary,
+// 2 = payload length is varint
+fn serialize_payload_with_algorithm(
+    buffer: &mut impl SerializerBuffer,
+    algorithm: CompressionAlgorithm,
+    dictionary_id: Option<u32>,
+    payload: &[u8],
+) {
+    let mut protocol_version = 0;
+
+    if dictionary_id.is_some() {
+        protocol_version |= 1;
+    }
+
+    if is_large_array(payload.len()) {
+        protocol_version |= 2;
+    }
+
+    buffer.push(COMPRESSED_PAYLOAD_WITH_ALGORITHM);
+    crate::common_serializers::serialize_byte(buffer, protocol_version);
+    crate::common_serializers::serialize_byte(buffer, algorithm.as_u8());
+
+    if let Some(dictionary_id) = dictionary_id {
+        crate::common_serializers::serialize_i32(buffer, dictionary_id as i32);
+    }
+
+    serialize_byte_array_of_any_size(buffer, payload);
+}
+
+fn pascal_string_len(src: &str) -> usize {
+    1 + src.len()
+}
+
+fn list_of_pascal_strings_len(src: &[String]) -> usize {
+    4 + src.iter().map(|itm| pascal_string_len(itm)).sum::<usize>()
+}
+
+fn list_of_keys_with_moments_len(src: &[(String, Option<DateTimeAsMicroseconds>)]) -> usize {
+    4 + src
+        .iter()
+        .map(|(key, _)| pascal_string_len(key) + 8)
+        .sum::<usize>()
+}
+
 impl my_tcp_sockets::tcp_connection::TcpContract for MyNoSqlTcpContract {
     fn is_pong(&self) -> bool {
         match self {
@@ -496,3 +1900,436 @@ impl my_tcp_sockets::tcp_connection::TcpContract for MyNoSqlTcpContract {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::socket_reader::SocketReaderInMem;
+    use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+    use super::*;
+
+    async fn deserialize(payload: Vec<u8>) -> Result<MyNoSqlTcpContract, DecodeError> {
+        let mut reader = SocketReaderInMem::new(payload);
+        MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &DecodeLimits::default()).await
+    }
+
+    fn moment(unix_microseconds: i64) -> Option<DateTimeAsMicroseconds> {
+        Some(DateTimeAsMicroseconds::new(unix_microseconds))
+    }
+
+    fn keys(src: &[(String, Option<DateTimeAsMicroseconds>)]) -> Vec<(String, Option<i64>)> {
+        src.iter()
+            .map(|(key, moment)| (key.clone(), moment.map(|itm| itm.unix_microseconds)))
+            .collect()
+    }
+
+    // Large frames are chosen by payload size only, so they are built by hand with small payloads
+    #[tokio::test]
+    async fn large_frames_with_small_payloads_are_read() {
+        let mut frame = vec![LARGE_INIT_TABLE, 0];
+        crate::common_serializers::serialize_pascal_string(&mut frame, "table");
+        crate::common_serializers::serialize_large_byte_array(&mut frame, &[1, 2, 3]);
+        assert!(MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+
+        match deserialize(frame).await.unwrap() {
+            MyNoSqlTcpContract::InitTable { table_name, data } => {
+                assert_eq!(table_name, "table");
+                assert_eq!(data, vec![1, 2, 3]);
+            }
+            _ => panic!("InitTable is expected"),
+        }
+
+        let mut frame = vec![LARGE_COMPRESSED_PAYLOAD, 0];
+        crate::common_serializers::serialize_large_byte_array(&mut frame, &[4, 5]);
+        assert!(MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+
+        match deserialize(frame).await.unwrap() {
+            MyNoSqlTcpContract::CompressedPayload(payload) => assert_eq!(payload, vec![4, 5]),
+            _ => panic!("CompressedPayload is expected"),
+        }
+
+        let mut frame = vec![
+            COMPRESSED_PAYLOAD_WITH_ALGORITHM,
+            1 | 2,
+            CompressionAlgorithm::Zip.as_u8(),
+        ];
+        crate::common_serializers::serialize_i32(&mut frame, 7);
+        crate::common_serializers::serialize_large_byte_array(&mut frame, &[6]);
+        assert!(MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+
+        match deserialize(frame).await.unwrap() {
+            MyNoSqlTcpContract::CompressedPayloadWithAlgorithm {
+                algorithm,
+                dictionary_id,
+                payload,
+            } => {
+                assert_eq!(algorithm, CompressionAlgorithm::Zip);
+                assert_eq!(dictionary_id, Some(7));
+                assert_eq!(payload, vec![6]);
+            }
+            _ => panic!("CompressedPayloadWithAlgorithm is expected"),
+        }
+
+        let payload = MyNoSqlTcpContract::Ping.serialize();
+        let mut frame = vec![CHECKSUMMED_PAYLOAD, 1, ChecksumAlgorithm::Crc32c.as_u8()];
+        crate::common_serializers::serialize_large_byte_array(&mut frame, payload.as_slice());
+        let checksum = ChecksumAlgorithm::Crc32c
+            .calculate(payload.as_slice())
+            .unwrap();
+        crate::common_serializers::serialize_i64(&mut frame, checksum as i64);
+        assert!(MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+
+        let contract = deserialize(frame)
+            .await
+            .unwrap()
+            .unwrap_checksummed_payload(&DecodeLimits::default())
+            .await
+            .unwrap();
+        assert!(matches!(contract, MyNoSqlTcpContract::Ping));
+    }
+
+    #[test]
+    fn small_payloads_are_written_with_legacy_frames() {
+        let contracts = [
+            MyNoSqlTcpContract::InitTable {
+                table_name: "table".to_string(),
+                data: vec![1, 2, 3],
+            },
+            MyNoSqlTcpContract::CompressedPayload(vec![1, 2, 3]),
+            MyNoSqlTcpContract::CompressedPayloadWithAlgorithm {
+                algorithm: CompressionAlgorithm::Zip,
+                dictionary_id: Some(1),
+                payload: vec![1, 2, 3],
+            },
+        ];
+
+        for contract in contracts {
+            assert!(!contract.requires_large_frames());
+
+            let frame = contract.serialize();
+            assert!(!MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+            assert_eq!(frame.len(), contract.serialized_len());
+        }
+    }
+
+    #[tokio::test]
+    async fn checksummed_frame_round_trip() {
+        let contract = MyNoSqlTcpContract::UpdateRows {
+            table_name: "table".to_string(),
+            data: vec![1, 2, 3, 4],
+        };
+
+        let frame = MyNoSqlTcpContract::wrap_frame_with_checksum(
+            contract.serialize().as_slice(),
+            ChecksumAlgorithm::Crc32c,
+        )
+        .unwrap();
+        assert!(!MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+
+        let result = deserialize(frame)
+            .await
+            .unwrap()
+            .unwrap_checksummed_payload(&DecodeLimits::default())
+            .await
+            .unwrap();
+
+        match result {
+            MyNoSqlTcpContract::UpdateRows { table_name, data } => {
+                assert_eq!(table_name, "table");
+                assert_eq!(data, vec![1, 2, 3, 4]);
+            }
+            _ => panic!("UpdateRows is expected"),
+        }
+    }
+
+    #[cfg(not(feature = "xxhash"))]
+    #[test]
+    fn frame_is_not_wrapped_with_checksum_which_is_not_compiled_in() {
+        let result = MyNoSqlTcpContract::wrap_frame_with_checksum(
+            MyNoSqlTcpContract::Ping.serialize().as_slice(),
+            ChecksumAlgorithm::XxHash64,
+        );
+
+        assert!(matches!(
+            result,
+            Err(SerializeError::UnsupportedChecksumAlgorithm(
+                ChecksumAlgorithm::XxHash64
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn corrupted_checksummed_frame_is_rejected() {
+        let mut frame = MyNoSqlTcpContract::wrap_frame_with_checksum(
+            MyNoSqlTcpContract::Ping.serialize().as_slice(),
+            ChecksumAlgorithm::Crc32c,
+        )
+        .unwrap();
+
+        // Flip a bit of the wrapped frame which starts after id, version, algorithm and length
+        frame[7] ^= 1;
+
+        match deserialize(frame).await {
+            Err(DecodeError::ChecksumMismatch { .. }) => {}
+            result => panic!("ChecksumMismatch is expected. Got: {:?}", result),
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn encrypted_frame_round_trip() {
+        let key_provider = crate::PreSharedKeys::new(1, [7; ENCRYPTION_KEY_SIZE]);
+
+        let frame = MyNoSqlTcpContract::Subscribe {
+            table_name: "table".to_string(),
+        }
+        .serialize();
+
+        let frame =
+            MyNoSqlTcpContract::encrypt_frame(frame.as_slice(), 1, &[7; ENCRYPTION_KEY_SIZE])
+                .unwrap();
+        assert!(!MyNoSqlTcpContract::is_large_frame(frame.as_slice()));
+
+        let result = deserialize(frame)
+            .await
+            .unwrap()
+            .decrypt_if_encrypted(&DecodeLimits::default(), &key_provider)
+            .await
+            .unwrap();
+
+        match result {
+            MyNoSqlTcpContract::Subscribe { table_name } => assert_eq!(table_name, "table"),
+            _ => panic!("Subscribe is expected"),
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn tampered_or_foreign_encrypted_frame_is_rejected() {
+        let frame = MyNoSqlTcpContract::encrypt_frame(
+            MyNoSqlTcpContract::Ping.serialize().as_slice(),
+            1,
+            &[7; ENCRYPTION_KEY_SIZE],
+        )
+        .unwrap();
+
+        let mut tampered = frame.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+
+        let key_provider = crate::PreSharedKeys::new(1, [7; ENCRYPTION_KEY_SIZE]);
+        let result = deserialize(tampered)
+            .await
+            .unwrap()
+            .decrypt_if_encrypted(&DecodeLimits::default(), &key_provider)
+            .await;
+        assert!(matches!(result, Err(DecodeError::DecryptionFailed { .. })));
+
+        let wrong_keys = crate::PreSharedKeys::new(1, [8; ENCRYPTION_KEY_SIZE]);
+        let result = deserialize(frame.clone())
+            .await
+            .unwrap()
+            .decrypt_if_encrypted(&DecodeLimits::default(), &wrong_keys)
+            .await;
+        assert!(matches!(result, Err(DecodeError::DecryptionFailed { .. })));
+
+        let unknown_key = crate::PreSharedKeys::new(2, [7; ENCRYPTION_KEY_SIZE]);
+        let result = deserialize(frame)
+            .await
+            .unwrap()
+            .decrypt_if_encrypted(&DecodeLimits::default(), &unknown_key)
+            .await;
+        assert!(matches!(result, Err(DecodeError::UnknownEncryptionKey(1))));
+    }
+
+    // Frames below are written the way the baseline protocol writes them
+
+    fn baseline_partitions_last_read_time(partition_keys: &Vec<String>) -> Vec<u8> {
+        let mut frame = vec![UPDATE_PARTITIONS_LAST_READ_TIME, 0];
+        crate::common_serializers::serialize_i64(&mut frame, 5);
+        crate::common_serializers::serialize_pascal_string(&mut frame, "table");
+        crate::common_serializers::serialize_list_of_pascal_strings(&mut frame, partition_keys);
+        frame
+    }
+
+    fn baseline_rows_last_read_time(row_keys: &Vec<String>) -> Vec<u8> {
+        let mut frame = vec![UPDATE_ROWS_LAST_READ_TIME, 0];
+        crate::common_serializers::serialize_i64(&mut frame, 5);
+        crate::common_serializers::serialize_pascal_string(&mut frame, "table");
+        crate::common_serializers::serialize_pascal_string(&mut frame, "pk");
+        crate::common_serializers::serialize_list_of_pascal_strings(&mut frame, row_keys);
+        frame
+    }
+
+    fn baseline_rows_expiration_time(
+        row_keys: &Vec<String>,
+        expiration_time: Option<DateTimeAsMicroseconds>,
+    ) -> Vec<u8> {
+        let mut frame = vec![UPDATE_ROWS_EXPIRATION_TIME, 0];
+        crate::common_serializers::serialize_i64(&mut frame, 5);
+        crate::common_serializers::serialize_pascal_string(&mut frame, "table");
+        crate::common_serializers::serialize_pascal_string(&mut frame, "pk");
+        crate::common_serializers::serialize_list_of_pascal_strings(&mut frame, row_keys);
+        crate::common_serializers::serialize_date_time_opt(&mut frame, expiration_time);
+        frame
+    }
+
+    #[tokio::test]
+    async fn baseline_sync_frames_are_read() {
+        let row_keys = vec!["r1".to_string(), "r2".to_string()];
+
+        match deserialize(baseline_partitions_last_read_time(&row_keys))
+            .await
+            .unwrap()
+        {
+            MyNoSqlTcpContract::UpdatePartitionsLastReadTime {
+                confirmation_id,
+                table_name,
+                partitions,
+            } => {
+                assert_eq!(confirmation_id, 5);
+                assert_eq!(table_name, "table");
+                assert_eq!(partitions, row_keys);
+            }
+            _ => panic!("UpdatePartitionsLastReadTime is expected"),
+        }
+
+        match deserialize(baseline_rows_last_read_time(&row_keys))
+            .await
+            .unwrap()
+        {
+            MyNoSqlTcpContract::UpdateRowsLastReadTime {
+                partition_key,
+                row_keys: result,
+                ..
+            } => {
+                assert_eq!(partition_key, "pk");
+                assert_eq!(result, row_keys);
+            }
+            _ => panic!("UpdateRowsLastReadTime is expected"),
+        }
+
+        match deserialize(baseline_rows_expiration_time(&row_keys, moment(100)))
+            .await
+            .unwrap()
+        {
+            MyNoSqlTcpContract::UpdateRowsExpirationTime {
+                partition_key,
+                row_keys: result,
+                expiration_time,
+                ..
+            } => {
+                assert_eq!(partition_key, "pk");
+                assert_eq!(result, row_keys);
+                assert_eq!(expiration_time.map(|itm| itm.unix_microseconds), Some(100));
+            }
+            _ => panic!("UpdateRowsExpirationTime is expected"),
+        }
+    }
+
+    // Main node of the baseline protocol ignores the version byte, so frames without moments
+    // and with shared expiration time must stay byte identical to the baseline ones
+    #[test]
+    fn sync_frames_without_extensions_match_baseline() {
+        let row_keys = vec!["r1".to_string(), "r2".to_string()];
+
+        let contract = MyNoSqlTcpContract::UpdatePartitionsLastReadTime {
+            confirmation_id: 5,
+            table_name: "table".to_string(),
+            partitions: row_keys.clone(),
+        };
+        assert_eq!(
+            contract.serialize(),
+            baseline_partitions_last_read_time(&row_keys)
+        );
+        assert_eq!(contract.serialized_len(), contract.serialize().len());
+
+        let contract = MyNoSqlTcpContract::UpdateRowsLastReadTime {
+            confirmation_id: 5,
+            table_name: "table".to_string(),
+            partition_key: "pk".to_string(),
+            row_keys: row_keys.clone(),
+        };
+        assert_eq!(
+            contract.serialize(),
+            baseline_rows_last_read_time(&row_keys)
+        );
+        assert_eq!(contract.serialized_len(), contract.serialize().len());
+
+        for expiration_time in [None, moment(100)] {
+            let contract = MyNoSqlTcpContract::UpdateRowsExpirationTime {
+                confirmation_id: 5,
+                table_name: "table".to_string(),
+                partition_key: "pk".to_string(),
+                row_keys: row_keys.clone(),
+                expiration_time,
+            };
+            assert_eq!(
+                contract.serialize(),
+                baseline_rows_expiration_time(&row_keys, expiration_time)
+            );
+            assert_eq!(contract.serialized_len(), contract.serialize().len());
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_frames_with_extensions_round_trip() {
+        let rows = vec![
+            ("r1".to_string(), moment(100)),
+            ("r2".to_string(), moment(200)),
+        ];
+
+        let contract = MyNoSqlTcpContract::UpdateRowsReadMoments {
+            confirmation_id: 5,
+            table_name: "table".to_string(),
+            partition_key: "pk".to_string(),
+            rows: rows.clone(),
+        };
+        let frame = contract.serialize();
+        assert_eq!(frame[1], 1);
+        assert_eq!(frame.len(), contract.serialized_len());
+
+        match deserialize(frame).await.unwrap() {
+            MyNoSqlTcpContract::UpdateRowsReadMoments { rows: result, .. } => {
+                assert_eq!(keys(&result), keys(&rows))
+            }
+            _ => panic!("UpdateRowsReadMoments is expected"),
+        }
+
+        let partitions = vec![("p1".to_string(), moment(300))];
+
+        let contract = MyNoSqlTcpContract::UpdatePartitionsReadMoments {
+            confirmation_id: 5,
+            table_name: "table".to_string(),
+            partitions: partitions.clone(),
+        };
+        let frame = contract.serialize();
+        assert_eq!(frame[1], 1);
+        assert_eq!(frame.len(), contract.serialized_len());
+
+        match deserialize(frame).await.unwrap() {
+            MyNoSqlTcpContract::UpdatePartitionsReadMoments {
+                partitions: result, ..
+            } => assert_eq!(keys(&result), keys(&partitions)),
+            _ => panic!("UpdatePartitionsReadMoments is expected"),
+        }
+
+        let rows = vec![("r1".to_string(), None), ("r2".to_string(), moment(200))];
+
+        let contract = MyNoSqlTcpContract::UpdateRowsExpirationTimePerRow {
+            confirmation_id: 5,
+            table_name: "table".to_string(),
+            partition_key: "pk".to_string(),
+            rows: rows.clone(),
+        };
+        let frame = contract.serialize();
+        assert_eq!(frame[1], 1);
+        assert_eq!(frame.len(), contract.serialized_len());
+
+        match deserialize(frame).await.unwrap() {
+            MyNoSqlTcpContract::UpdateRowsExpirationTimePerRow { rows: result, .. } => {
+                assert_eq!(keys(&result), keys(&rows))
+            }
+            _ => panic!("UpdateRowsExpirationTimePerRow is expected"),
+        }
+    }
+}
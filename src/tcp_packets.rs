@@ -17,3 +17,12 @@ pub const UPDATE_ROWS_LAST_READ_TIME: u8 = 15;
 pub const UPDATE_PARTITIONS_EXPIRATION_TIME: u8 = 16;
 pub const UPDATE_ROWS_EXPIRATION_TIME: u8 = 17;
 pub const CONFIRMATION: u8 = 18;
+pub const COMPRESSED_PAYLOAD_WITH_ALGORITHM: u8 = 19;
+pub const COMPRESSION_DICTIONARY: u8 = 20;
+pub const CHECKSUMMED_PAYLOAD: u8 = 21;
+pub const LARGE_INIT_TABLE: u8 = 22;
+pub const LARGE_COMPRESSED_PAYLOAD: u8 = 23;
+pub const ENCRYPTED_PAYLOAD: u8 = 24;
+pub const UPDATE_LAST_READ_TIME_BATCH: u8 = 25;
+pub const UPDATE_EXPIRATION_TIME_BATCH: u8 = 26;
+pub const CAPABILITIES: u8 = 27;
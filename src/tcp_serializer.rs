@@ -1,34 +1,842 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
 use my_tcp_sockets::{
     socket_reader::{ReadingTcpContractFail, SocketReader},
     TcpSocketSerializer,
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::MyNoSqlTcpContract;
+use crate::{
+    capture::{CaptureDirection, CaptureWriter},
+    metrics::{PacketDirection, ProtocolMetrics},
+    sync_to_main::{SyncToMainNodeEvent, SyncToMainNodeQueues},
+    BufferPool, ChecksumAlgorithm, CompressionAlgorithm, CompressionDictionaries,
+    CompressionOffload, CompressionPolicy, DecodeErrorHandler, DecodeLimits, MyNoSqlTcpContract,
+    PeerCapabilities, PooledBuffer, PreparedContract, RecordingSocketReader, SerializeError,
+    GREETING_WITH_CAPABILITIES_VERSION,
+};
 
-pub struct MyNoSqlReaderTcpSerializer {}
+#[cfg(feature = "encryption")]
+use crate::KeyProvider;
+
+pub struct MyNoSqlReaderTcpSerializer {
+    limits: DecodeLimits,
+    capture: Option<(Arc<CaptureWriter>, i32)>,
+    metrics: Option<Arc<dyn ProtocolMetrics>>,
+    compression_algorithms: Vec<CompressionAlgorithm>,
+    negotiated_compression: CompressionAlgorithm,
+    compression_enabled: bool,
+    compression_policy: Arc<CompressionPolicy>,
+    dictionaries: Arc<CompressionDictionaries>,
+    received_dictionaries: Arc<CompressionDictionaries>,
+    shipped_dictionaries: Mutex<HashSet<u32>>,
+    compression_offload: CompressionOffload,
+    buffer_pool: Arc<BufferPool>,
+    checksum_algorithms: Vec<ChecksumAlgorithm>,
+    negotiated_checksum: Option<ChecksumAlgorithm>,
+    supports_sync_batches: bool,
+    supports_read_moments: bool,
+    supports_row_expirations: bool,
+    peer_capabilities: Option<PeerCapabilities>,
+    capabilities_pending: AtomicBool,
+    capabilities_sent: AtomicBool,
+    sync_to_main: Option<Arc<SyncToMainNodeQueues>>,
+    #[cfg(feature = "encryption")]
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    decode_error_handler: Option<Arc<dyn DecodeErrorHandler>>,
+}
 
 impl MyNoSqlReaderTcpSerializer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            limits: DecodeLimits::default(),
+            capture: None,
+            metrics: None,
+            compression_algorithms: CompressionAlgorithm::get_supported(),
+            negotiated_compression: CompressionAlgorithm::Zip,
+            compression_enabled: false,
+            compression_policy: Arc::new(CompressionPolicy::default()),
+            dictionaries: Arc::new(CompressionDictionaries::new()),
+            received_dictionaries: Arc::new(CompressionDictionaries::new()),
+            shipped_dictionaries: Mutex::new(HashSet::new()),
+            compression_offload: CompressionOffload::default(),
+            buffer_pool: Arc::new(BufferPool::default()),
+            checksum_algorithms: ChecksumAlgorithm::get_supported(),
+            negotiated_checksum: None,
+            supports_sync_batches: false,
+            supports_read_moments: false,
+            supports_row_expirations: false,
+            peer_capabilities: None,
+            capabilities_pending: AtomicBool::new(false),
+            capabilities_sent: AtomicBool::new(false),
+            sync_to_main: None,
+            #[cfg(feature = "encryption")]
+            key_provider: None,
+            decode_error_handler: None,
+        }
+    }
+
+    pub fn new_with_limits(limits: DecodeLimits) -> Self {
+        Self {
+            limits,
+            capture: None,
+            metrics: None,
+            compression_algorithms: CompressionAlgorithm::get_supported(),
+            negotiated_compression: CompressionAlgorithm::Zip,
+            compression_enabled: false,
+            compression_policy: Arc::new(CompressionPolicy::default()),
+            dictionaries: Arc::new(CompressionDictionaries::new()),
+            received_dictionaries: Arc::new(CompressionDictionaries::new()),
+            shipped_dictionaries: Mutex::new(HashSet::new()),
+            compression_offload: CompressionOffload::default(),
+            buffer_pool: Arc::new(BufferPool::default()),
+            checksum_algorithms: ChecksumAlgorithm::get_supported(),
+            negotiated_checksum: None,
+            supports_sync_batches: false,
+            supports_read_moments: false,
+            supports_row_expirations: false,
+            peer_capabilities: None,
+            capabilities_pending: AtomicBool::new(false),
+            capabilities_sent: AtomicBool::new(false),
+            sync_to_main: None,
+            #[cfg(feature = "encryption")]
+            key_provider: None,
+            decode_error_handler: None,
+        }
+    }
+
+    pub fn with_capture(mut self, capture: Arc<CaptureWriter>) -> Self {
+        let connection_id = capture.register_connection();
+        self.capture = Some((capture, connection_id));
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<dyn ProtocolMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn with_compression_algorithms(mut self, algorithms: Vec<CompressionAlgorithm>) -> Self {
+        self.compression_algorithms = algorithms;
+        self
+    }
+
+    pub fn with_compression_policy(mut self, compression_policy: Arc<CompressionPolicy>) -> Self {
+        self.compression_policy = compression_policy;
+        self
+    }
+
+    pub fn with_dictionaries(mut self, dictionaries: Arc<CompressionDictionaries>) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+
+    // Dictionaries we compress outgoing packets with
+    pub fn get_dictionaries(&self) -> &Arc<CompressionDictionaries> {
+        &self.dictionaries
+    }
+
+    // Dictionaries the peer compresses packets with. Kept apart, so the peer can not replace ours
+    pub fn get_received_dictionaries(&self) -> &Arc<CompressionDictionaries> {
+        &self.received_dictionaries
+    }
+
+    pub fn with_compression_offload(mut self, compression_offload: CompressionOffload) -> Self {
+        self.compression_offload = compression_offload;
+        self
+    }
+
+    pub fn with_buffer_pool(mut self, buffer_pool: Arc<BufferPool>) -> Self {
+        self.buffer_pool = buffer_pool;
+        self
+    }
+
+    // Algorithms which are not compiled in are not advertised to the peer
+    pub fn with_checksum_algorithms(mut self, algorithms: Vec<ChecksumAlgorithm>) -> Self {
+        self.checksum_algorithms = algorithms
+            .into_iter()
+            .filter(|itm| itm.is_supported())
+            .collect();
+        self
+    }
+
+    pub fn with_decode_error_handler(mut self, handler: Arc<dyn DecodeErrorHandler>) -> Self {
+        self.decode_error_handler = Some(handler);
+        self
+    }
+
+    // Data packets are compressed first and then encrypted with the current key of the provider.
+    // Data packets of the peer are accepted only encrypted from then on
+    #[cfg(feature = "encryption")]
+    pub fn with_key_provider(mut self, key_provider: Arc<dyn KeyProvider>) -> Self {
+        self.key_provider = Some(key_provider);
+        self
+    }
+
+    // Main node advertises it handles UpdateLastReadTimeBatch and UpdateExpirationTimeBatch packets
+    pub fn with_sync_batches(mut self, supported: bool) -> Self {
+        self.supports_sync_batches = supported;
+        self
+    }
+
+    // Node reports capabilities of the main node to the sync queues as soon as they arrive
+    pub fn with_sync_to_main_queues(mut self, queues: Arc<SyncToMainNodeQueues>) -> Self {
+        self.sync_to_main = Some(queues);
+        self
+    }
+
+    // Main node advertises it uses the moments keys were read at instead of the moment it gets them
+    pub fn with_read_moments(mut self, supported: bool) -> Self {
+        self.supports_read_moments = supported;
+        self
+    }
+
+    // Main node advertises it reads expiration time per row, so rows of one partition are not split by it
+    pub fn with_row_expirations(mut self, supported: bool) -> Self {
+        self.supports_row_expirations = supported;
+        self
+    }
+
+    pub fn get_capabilities(&self) -> PeerCapabilities {
+        PeerCapabilities {
+            compression_algorithms: self.compression_algorithms.clone(),
+            checksum_algorithms: self.checksum_algorithms.clone(),
+            supports_large_frames: true,
+            supports_sync_batches: self.supports_sync_batches,
+            supports_read_moments: self.supports_read_moments,
+            supports_encryption: self.has_encryption_keys(),
+            supports_row_expirations: self.supports_row_expirations,
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    fn has_encryption_keys(&self) -> bool {
+        self.key_provider.is_some()
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn has_encryption_keys(&self) -> bool {
+        false
+    }
+
+    // None if peer has not sent its capabilities. Peers of the baseline protocol never do
+    pub fn get_peer_capabilities(&self) -> Option<&PeerCapabilities> {
+        self.peer_capabilities.as_ref()
+    }
+
+    pub fn get_negotiated_checksum(&self) -> Option<ChecksumAlgorithm> {
+        self.negotiated_checksum
+    }
+
+    pub fn get_negotiated_compression(&self) -> CompressionAlgorithm {
+        self.negotiated_compression
+    }
+
+    pub fn is_compression_enabled(&self) -> bool {
+        self.compression_enabled
+    }
+
+    pub fn peer_supports_large_frames(&self) -> bool {
+        match &self.peer_capabilities {
+            Some(capabilities) => capabilities.supports_large_frames,
+            None => false,
+        }
+    }
+
+    pub fn peer_supports_read_moments(&self) -> bool {
+        match &self.peer_capabilities {
+            Some(capabilities) => capabilities.supports_read_moments,
+            None => false,
+        }
+    }
+
+    pub fn peer_supports_row_expirations(&self) -> bool {
+        match &self.peer_capabilities {
+            Some(capabilities) => capabilities.supports_row_expirations,
+            None => false,
+        }
+    }
+
+    pub fn peer_supports_encryption(&self) -> bool {
+        match &self.peer_capabilities {
+            Some(capabilities) => capabilities.supports_encryption,
+            None => false,
+        }
+    }
+
+    pub fn peer_supports_sync_batches(&self) -> bool {
+        match &self.peer_capabilities {
+            Some(capabilities) => capabilities.supports_sync_batches,
+            None => false,
+        }
+    }
+
+    // Payloads over 2 GiB do not fit into the legacy frames and are sent only to peers which support large frames
+    pub fn can_serialize(&self, contract: &MyNoSqlTcpContract) -> bool {
+        if !contract.requires_large_frames() {
+            return true;
+        }
+
+        self.peer_supports_large_frames() && contract.has_large_frame_encoding()
+    }
+
+    pub fn serialize_prepared(
+        &self,
+        prepared: &PreparedContract,
+    ) -> Result<Arc<[u8]>, SerializeError> {
+        let frame = if self.compression_enabled {
+            prepared.get_frame(Some(self.negotiated_compression))
+        } else {
+            prepared.get_uncompressed_frame()
+        };
+
+        if !prepared.has_large_frame_encoding() || !self.can_send_frame(frame.as_ref()) {
+            return Err(frame_too_large(
+                prepared.get_packet_name(),
+                prepared.get_table_name(),
+            ));
+        }
+
+        let wrapped = self.wrap_frame(
+            frame.as_ref(),
+            prepared.get_packet_name(),
+            prepared.get_table_name(),
+        )?;
+
+        let result = match wrapped {
+            Some(result) => {
+                let frame = result.as_slice().into();
+                self.buffer_pool.put(result);
+                frame
+            }
+            None => frame.clone(),
+        };
+
+        self.on_packet_serialized(
+            prepared.get_packet_name(),
+            prepared.get_table_name(),
+            result.as_ref(),
+        );
+
+        let result = match self.take_capabilities_frame() {
+            Some(mut capabilities) => {
+                capabilities.extend_from_slice(result.as_ref());
+                capabilities.into()
+            }
+            None => result,
+        };
+
+        Ok(result)
+    }
+
+    pub fn compress_if_make_sence_and_serialize(
+        &self,
+        contract: &MyNoSqlTcpContract,
+    ) -> Result<Vec<u8>, SerializeError> {
+        if contract.requires_large_frames() && !contract.has_large_frame_encoding() {
+            return Err(frame_too_large(
+                contract.get_packet_name(),
+                contract.get_table_name(),
+            ));
+        }
+
+        let dictionary_id = self.get_shipped_dictionary_id(contract);
+
+        let algorithm = if dictionary_id.is_some() {
+            CompressionAlgorithm::Zstd
+        } else {
+            self.negotiated_compression
+        };
+
+        let result = contract.compress_and_serialize(
+            algorithm,
+            self.compression_policy.as_ref(),
+            dictionary_id.map(|dictionary_id| (self.dictionaries.as_ref(), dictionary_id)),
+            self.metrics.as_deref(),
+            Some(self.buffer_pool.as_ref()),
+            None,
+        );
+
+        // Compressed payload can fit into the legacy frame even if the original one does not
+        if !self.can_send_frame(result.as_slice()) {
+            self.buffer_pool.put(result);
+            return Err(frame_too_large(
+                contract.get_packet_name(),
+                contract.get_table_name(),
+            ));
+        }
+
+        let result = self.seal_frame(
+            result,
+            contract.get_packet_name(),
+            contract.get_table_name(),
+        )?;
+        self.on_serialized(contract, result.as_slice());
+        Ok(self.prepend_capabilities(result))
+    }
+
+    // Returns Cancelled if the connection is dropped while the payload is being compressed.
+    // Compression which has already started on the blocking pool stops at the next chunk
+    pub async fn compress_if_make_sence_and_serialize_offloaded(
+        &self,
+        contract: MyNoSqlTcpContract,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<u8>, SerializeError> {
+        let packet_name = contract.get_packet_name();
+        let table_name = contract.get_table_name().map(|itm| itm.to_string());
+
+        if contract.requires_large_frames() && !contract.has_large_frame_encoding() {
+            return Err(frame_too_large(packet_name, table_name.as_deref()));
+        }
+
+        let dictionary = self
+            .get_shipped_dictionary_id(&contract)
+            .map(|dictionary_id| (self.dictionaries.clone(), dictionary_id));
+
+        let result = contract
+            .compress_offloaded_if_make_sence_and_serialize(
+                self.negotiated_compression,
+                self.compression_policy.clone(),
+                dictionary,
+                &self.compression_offload,
+                self.metrics.clone(),
+                cancellation,
+            )
+            .await
+            .ok_or(SerializeError::Cancelled)?;
+
+        if !self.can_send_frame(result.as_slice()) {
+            return Err(frame_too_large(packet_name, table_name.as_deref()));
+        }
+
+        let result = self.seal_frame(result, packet_name, table_name.as_deref())?;
+        self.on_packet_serialized(packet_name, table_name.as_deref(), result.as_slice());
+        Ok(self.prepend_capabilities(result))
+    }
+
+    pub async fn unwrap_envelope(
+        &self,
+        contract: MyNoSqlTcpContract,
+        cancellation: &CancellationToken,
+    ) -> Result<MyNoSqlTcpContract, crate::DecodeError> {
+        let contract = contract
+            .unwrap_checksummed_payload(&self.limits)
+            .await
+            .map_err(|err| self.on_decode_error(err))?;
+
+        let contract = self
+            .decrypt_if_encrypted(contract)
+            .await
+            .map_err(|err| self.on_decode_error(err))?;
+
+        self.decompress_if_compressed(contract, cancellation).await
+    }
+
+    #[cfg(feature = "encryption")]
+    async fn decrypt_if_encrypted(
+        &self,
+        contract: MyNoSqlTcpContract,
+    ) -> Result<MyNoSqlTcpContract, crate::DecodeError> {
+        match &self.key_provider {
+            Some(key_provider) => {
+                contract
+                    .decrypt_if_encrypted(&self.limits, key_provider.as_ref())
+                    .await
+            }
+            None => contract.check_not_encrypted(),
+        }
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    async fn decrypt_if_encrypted(
+        &self,
+        contract: MyNoSqlTcpContract,
+    ) -> Result<MyNoSqlTcpContract, crate::DecodeError> {
+        contract.check_not_encrypted()
+    }
+
+    // Decompression is recorded to the metrics of the serializer
+    pub async fn decompress_if_compressed(
+        &self,
+        contract: MyNoSqlTcpContract,
+        cancellation: &CancellationToken,
+    ) -> Result<MyNoSqlTcpContract, crate::DecodeError> {
+        contract
+            .decompress_if_compressed_offloaded(
+                &self.limits,
+                Some(self.received_dictionaries.clone()),
+                &self.compression_offload,
+                self.metrics.as_deref(),
+                cancellation,
+            )
+            .await
+            .map_err(|err| self.on_decode_error(err))
+    }
+
+    fn on_decode_error(&self, err: crate::DecodeError) -> crate::DecodeError {
+        if let crate::DecodeError::Cancelled = err {
+            return err;
+        }
+
+        println!(
+            "Can not decode packet. Kind: {}. Err: {:?}",
+            err.get_kind(),
+            err
+        );
+
+        if let Some(handler) = &self.decode_error_handler {
+            handler.on_decode_error(&err);
+        }
+
+        err
+    }
+
+    pub fn get_limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    pub fn get_metrics(&self) -> Option<&Arc<dyn ProtocolMetrics>> {
+        self.metrics.as_ref()
+    }
+
+    fn can_send_frame(&self, frame: &[u8]) -> bool {
+        self.peer_supports_large_frames() || !MyNoSqlTcpContract::is_large_frame(frame)
+    }
+
+    pub fn try_serialize(&self, contract: &MyNoSqlTcpContract) -> Result<Vec<u8>, SerializeError> {
+        self.try_serialize_pooled(contract)
+            .map(|frame| frame.into_vec())
+    }
+
+    // Frame goes back to the pool once the caller has written it and drops it
+    pub fn try_serialize_pooled(
+        &self,
+        contract: &MyNoSqlTcpContract,
+    ) -> Result<PooledBuffer, SerializeError> {
+        if !self.can_serialize(contract) {
+            return Err(frame_too_large(
+                contract.get_packet_name(),
+                contract.get_table_name(),
+            ));
+        }
+
+        let result = self.seal_frame(
+            self.serialize_pooled(contract),
+            contract.get_packet_name(),
+            contract.get_table_name(),
+        )?;
+        self.on_serialized(contract, result.as_slice());
+
+        Ok(PooledBuffer::new(
+            self.prepend_capabilities(result),
+            self.buffer_pool.clone(),
+        ))
+    }
+
+    fn get_checksum_for_frame(&self, frame: &[u8]) -> Option<ChecksumAlgorithm> {
+        let algorithm = self.negotiated_checksum?;
+
+        // Envelope of the frame this size would need varint length
+        if !self.peer_supports_large_frames() && frame.len() > i32::MAX as usize {
+            return None;
+        }
+
+        if MyNoSqlTcpContract::is_checksummed_packet_type(*frame.first()?) {
+            Some(algorithm)
+        } else {
+            None
+        }
+    }
+
+    // Frames given away to the socket layer come back only through recycle_buffer.
+    // Frames of try_serialize_pooled come back by themselves
+    pub fn recycle_buffer(&self, buffer: Vec<u8>) {
+        self.buffer_pool.put(buffer);
+    }
+
+    fn serialize_pooled(&self, contract: &MyNoSqlTcpContract) -> Vec<u8> {
+        let mut result = self.buffer_pool.get(contract.serialized_len());
+        contract.serialize_into(&mut result);
+        result
+    }
+
+    // Frame goes back to the pool if it can not be sent
+    fn seal_frame(
+        &self,
+        frame: Vec<u8>,
+        packet_name: &'static str,
+        table_name: Option<&str>,
+    ) -> Result<Vec<u8>, SerializeError> {
+        match self.wrap_frame(frame.as_slice(), packet_name, table_name) {
+            Ok(Some(result)) => {
+                self.buffer_pool.put(frame);
+                Ok(result)
+            }
+            Ok(None) => Ok(frame),
+            Err(err) => {
+                self.buffer_pool.put(frame);
+                Err(err)
+            }
+        }
+    }
+
+    // Data packets are encrypted if key provider is configured.
+    // Otherwise they get checksum trailer if peer has negotiated it - encrypted payload is authenticated anyway
+    fn wrap_frame(
+        &self,
+        frame: &[u8],
+        packet_name: &'static str,
+        table_name: Option<&str>,
+    ) -> Result<Option<Vec<u8>>, SerializeError> {
+        #[cfg(feature = "encryption")]
+        if let Some(key_provider) = self.get_key_provider_for_frame(frame) {
+            return self
+                .encrypt_frame(frame, key_provider, packet_name, table_name)
+                .map(Some);
+        }
+
+        #[cfg(not(feature = "encryption"))]
+        let _ = (packet_name, table_name);
+
+        let algorithm = match self.get_checksum_for_frame(frame) {
+            Some(algorithm) => algorithm,
+            None => return Ok(None),
+        };
+
+        // Envelope adds id, version, algorithm, length and checksum
+        let mut result = self.buffer_pool.get(frame.len() + 24);
+
+        match MyNoSqlTcpContract::wrap_frame_with_checksum_into(frame, algorithm, &mut result) {
+            Ok(()) => Ok(Some(result)),
+            Err(err) => {
+                self.buffer_pool.put(result);
+                Err(err)
+            }
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    fn get_key_provider_for_frame(&self, frame: &[u8]) -> Option<&dyn KeyProvider> {
+        let key_provider = self.key_provider.as_deref()?;
+
+        if MyNoSqlTcpContract::is_encrypted_packet_type(*frame.first()?) {
+            Some(key_provider)
+        } else {
+            None
+        }
+    }
+
+    // Data must never leave in plain text if encryption is configured, so the frame is refused if it fails
+    #[cfg(feature = "encryption")]
+    fn encrypt_frame(
+        &self,
+        frame: &[u8],
+        key_provider: &dyn KeyProvider,
+        packet_name: &'static str,
+        table_name: Option<&str>,
+    ) -> Result<Vec<u8>, SerializeError> {
+        // Ciphertext is longer than the frame by the authentication tag
+        if !self.peer_supports_large_frames() && frame.len() + 16 > i32::MAX as usize {
+            return Err(frame_too_large(packet_name, table_name));
+        }
+
+        let key_id = key_provider.get_encryption_key_id();
+
+        let key = key_provider
+            .get_key(key_id)
+            .ok_or(SerializeError::UnknownEncryptionKey(key_id))?;
+
+        MyNoSqlTcpContract::encrypt_frame(frame, key_id, &key)
+            .ok_or(SerializeError::EncryptionFailed { key_id })
+    }
+
+    fn apply_peer_capabilities(&mut self, capabilities: &PeerCapabilities) {
+        self.negotiated_checksum = ChecksumAlgorithm::negotiate(
+            self.checksum_algorithms.as_slice(),
+            capabilities.checksum_algorithms.as_slice(),
+        );
+        self.negotiated_compression = CompressionAlgorithm::negotiate(
+            self.compression_algorithms.as_slice(),
+            capabilities.compression_algorithms.as_slice(),
+        );
+        self.peer_capabilities = Some(capabilities.clone());
+
+        // Mismatch is not fatal here. Data packets of the peer are refused by the reader later
+        if capabilities.supports_encryption != self.has_encryption_keys() {
+            println!(
+                "Encryption is not configured on both sides. Ours: {}. Peer: {}. Data packets are going to be refused",
+                self.has_encryption_keys(),
+                capabilities.supports_encryption
+            );
+        }
+
+        // Read moments go first, since sync batches event delivers what is queued
+        if let Some(queues) = &self.sync_to_main {
+            queues
+                .event_loop
+                .send(SyncToMainNodeEvent::ReadMomentsSupported(
+                    capabilities.supports_read_moments,
+                ));
+            queues
+                .event_loop
+                .send(SyncToMainNodeEvent::RowExpirationsSupported(
+                    capabilities.supports_row_expirations,
+                ));
+            queues
+                .event_loop
+                .send(SyncToMainNodeEvent::SyncBatchesSupported(
+                    capabilities.supports_sync_batches,
+                ));
+        }
+
+        // Peer which has sent its capabilities first waits for ours
+        self.schedule_capabilities();
+    }
+
+    fn schedule_capabilities(&self) {
+        if !self.capabilities_sent.load(Ordering::SeqCst) {
+            self.capabilities_pending.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Capabilities go in front of the next frame we send. Serializer can not send packets on its own
+    fn take_capabilities_frame(&self) -> Option<Vec<u8>> {
+        if !self.capabilities_pending.swap(false, Ordering::SeqCst) {
+            return None;
+        }
+
+        if self.capabilities_sent.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let contract = MyNoSqlTcpContract::Capabilities(self.get_capabilities());
+        let result = contract.serialize();
+        self.on_serialized(&contract, result.as_slice());
+        Some(result)
+    }
+
+    fn prepend_capabilities(&self, frame: Vec<u8>) -> Vec<u8> {
+        match self.take_capabilities_frame() {
+            Some(mut result) => {
+                result.extend_from_slice(frame.as_slice());
+                self.buffer_pool.put(frame);
+                result
+            }
+            None => frame,
+        }
+    }
+
+    // Dictionary is used only after the peer has received it through this serializer
+    fn get_shipped_dictionary_id(&self, contract: &MyNoSqlTcpContract) -> Option<u32> {
+        if self.negotiated_compression != CompressionAlgorithm::Zstd {
+            return None;
+        }
+
+        let dictionary_id = self
+            .dictionaries
+            .get_dictionary_id(contract.get_table_name()?)?;
+
+        if self
+            .shipped_dictionaries
+            .lock()
+            .unwrap()
+            .contains(&dictionary_id)
+        {
+            Some(dictionary_id)
+        } else {
+            None
+        }
+    }
+
+    fn on_serialized(&self, contract: &MyNoSqlTcpContract, payload: &[u8]) {
+        if let MyNoSqlTcpContract::CompressionDictionary { dictionary_id, .. } = contract {
+            self.shipped_dictionaries
+                .lock()
+                .unwrap()
+                .insert(*dictionary_id);
+        }
+
+        self.on_packet_serialized(
+            contract.get_packet_name(),
+            contract.get_table_name(),
+            payload,
+        );
+    }
+
+    fn on_packet_serialized(
+        &self,
+        packet_name: &'static str,
+        table_name: Option<&str>,
+        payload: &[u8],
+    ) {
+        if let Some((capture, connection_id)) = &self.capture {
+            capture.write_payload(*connection_id, CaptureDirection::Outgoing, payload.to_vec());
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.packet(
+                PacketDirection::Outgoing,
+                packet_name,
+                table_name,
+                payload.len(),
+            );
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl TcpSocketSerializer<MyNoSqlTcpContract> for MyNoSqlReaderTcpSerializer {
     const PING_PACKET_IS_SINGLETONE: bool = true;
+    // Socket layer can not be told about the failure. Callers which have to handle it use try_serialize
     fn serialize(&self, contract: MyNoSqlTcpContract) -> Vec<u8> {
-        contract.serialize()
+        serialized_or_nothing(self.try_serialize(&contract))
     }
 
     fn serialize_ref(&self, contract: &MyNoSqlTcpContract) -> Vec<u8> {
-        contract.serialize()
+        serialized_or_nothing(self.try_serialize(contract))
     }
 
     fn get_ping(&self) -> MyNoSqlTcpContract {
         MyNoSqlTcpContract::Ping
     }
 
-    fn apply_packet(&mut self, _: &MyNoSqlTcpContract) -> bool {
+    fn apply_packet(&mut self, contract: &MyNoSqlTcpContract) -> bool {
+        if let MyNoSqlTcpContract::GreetingFromNode { compress, .. } = contract {
+            self.compression_enabled = *compress;
+        }
+
+        if let MyNoSqlTcpContract::Capabilities(capabilities) = contract {
+            self.apply_peer_capabilities(capabilities);
+        }
+
+        if let MyNoSqlTcpContract::CompressionDictionary {
+            dictionary_id,
+            table_name,
+            dictionary,
+        } = contract
+        {
+            let result = self.received_dictionaries.register_with_limits(
+                *dictionary_id,
+                table_name,
+                dictionary.clone(),
+                &self.limits,
+            );
+
+            // Packets compressed with the dictionary fail to decompress then
+            if let Err(err) = result {
+                println!(
+                    "Compression dictionary {} for table {} is rejected. Err: {:?}",
+                    dictionary_id, table_name, err
+                );
+            }
+        }
+
         false
     }
 
@@ -36,6 +844,412 @@ impl TcpSocketSerializer<MyNoSqlTcpContract> for MyNoSqlReaderTcpSerializer {
         &mut self,
         socket_reader: &mut TSocketReader,
     ) -> Result<MyNoSqlTcpContract, ReadingTcpContractFail> {
-        MyNoSqlTcpContract::deserialize(socket_reader).await
+        let mut reader = RecordingSocketReader::new(socket_reader, self.capture.is_some());
+
+        let result = MyNoSqlTcpContract::deserialize_with_limits(&mut reader, &self.limits)
+            .await
+            .map_err(|err| self.on_decode_error(err))?;
+
+        // Node which knows the Capabilities packet gets ours with the next frame we send
+        if let MyNoSqlTcpContract::GreetingFromNode { .. } = &result {
+            if reader.get_packet_version() >= Some(GREETING_WITH_CAPABILITIES_VERSION) {
+                self.schedule_capabilities();
+            }
+        }
+
+        if let Some((capture, connection_id)) = &self.capture {
+            if let Some(payload) = reader.take_recorded() {
+                capture.write_payload(*connection_id, CaptureDirection::Incoming, payload);
+            }
+        }
+
+        // Size of the frame as it came from the wire, envelopes included
+        if let Some(metrics) = &self.metrics {
+            metrics.packet(
+                PacketDirection::Incoming,
+                result.get_packet_name(),
+                result.get_table_name(),
+                reader.get_consumed(),
+            );
+        }
+
+        Ok(result)
+    }
+}
+
+fn frame_too_large(packet_name: &'static str, table_name: Option<&str>) -> SerializeError {
+    SerializeError::FrameTooLarge {
+        packet_name,
+        table_name: table_name.map(|itm| itm.to_string()),
+    }
+}
+
+// Nothing is sent instead of the frame the peer would read as corrupted
+fn serialized_or_nothing(result: Result<Vec<u8>, SerializeError>) -> Vec<u8> {
+    match result {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Can not serialize packet. Nothing is sent. Err: {:?}", err);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::{socket_reader::SocketReaderInMem, TcpSocketSerializer};
+
+    use super::*;
+
+    async fn receive(
+        serializer: &mut MyNoSqlReaderTcpSerializer,
+        payload: Vec<u8>,
+    ) -> Vec<MyNoSqlTcpContract> {
+        let len = payload.len();
+        let mut reader = SocketReaderInMem::new(payload);
+        let mut result = Vec::new();
+        let mut consumed = 0;
+
+        while consumed < len {
+            let mut recording = RecordingSocketReader::new(&mut reader, false);
+            let contract = MyNoSqlTcpContract::deserialize(&mut recording)
+                .await
+                .unwrap();
+            consumed += recording.get_consumed();
+
+            serializer.apply_packet(&contract);
+            result.push(contract);
+        }
+
+        result
+    }
+
+    async fn receive_greeting(serializer: &mut MyNoSqlReaderTcpSerializer, payload: Vec<u8>) {
+        let mut reader = SocketReaderInMem::new(payload);
+        let contract = TcpSocketSerializer::deserialize(serializer, &mut reader)
+            .await
+            .unwrap();
+        serializer.apply_packet(&contract);
+    }
+
+    fn greeting() -> MyNoSqlTcpContract {
+        MyNoSqlTcpContract::GreetingFromNode {
+            node_location: "loc".to_string(),
+            node_version: "1.0".to_string(),
+            compress: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn capabilities_are_exchanged_after_greeting() {
+        let mut main_node = MyNoSqlReaderTcpSerializer::new().with_sync_batches(true);
+        let mut node = MyNoSqlReaderTcpSerializer::new();
+
+        receive_greeting(&mut main_node, node.serialize(greeting())).await;
+
+        let received = receive(&mut node, main_node.serialize(MyNoSqlTcpContract::Pong)).await;
+        assert_eq!(received.len(), 2);
+        assert!(matches!(received[0], MyNoSqlTcpContract::Capabilities(_)));
+        assert!(matches!(received[1], MyNoSqlTcpContract::Pong));
+        assert!(node.peer_supports_sync_batches());
+        assert!(node.peer_supports_large_frames());
+
+        let received = receive(&mut main_node, node.serialize(MyNoSqlTcpContract::Ping)).await;
+        assert_eq!(received.len(), 2);
+        assert!(main_node.get_peer_capabilities().is_some());
+        assert!(!main_node.peer_supports_sync_batches());
+
+        // Capabilities are sent once per connection
+        assert_eq!(
+            main_node.serialize(MyNoSqlTcpContract::Pong),
+            MyNoSqlTcpContract::Pong.serialize()
+        );
+        assert_eq!(
+            node.serialize(MyNoSqlTcpContract::Ping),
+            MyNoSqlTcpContract::Ping.serialize()
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_record_wire_size_and_decompression() {
+        let metrics = Arc::new(crate::metrics::AtomicProtocolMetrics::new());
+        let mut main_node = MyNoSqlReaderTcpSerializer::new().with_metrics(metrics.clone());
+        let node = MyNoSqlReaderTcpSerializer::new();
+
+        let init_table = MyNoSqlTcpContract::InitTable {
+            table_name: "table".to_string(),
+            data: vec![0; 4096],
+        };
+        let frame = node
+            .compress_if_make_sence_and_serialize(&init_table)
+            .unwrap();
+
+        let mut reader = SocketReaderInMem::new(frame.clone());
+        let contract = TcpSocketSerializer::deserialize(&mut main_node, &mut reader)
+            .await
+            .unwrap();
+        assert!(contract.is_compressed());
+
+        let contract = main_node
+            .unwrap_envelope(contract, &CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(matches!(contract, MyNoSqlTcpContract::InitTable { .. }));
+
+        let snapshot = metrics.get_snapshot();
+        assert_eq!(snapshot.packets.len(), 1);
+        assert_eq!(snapshot.packets[0].bytes, frame.len() as u64);
+        assert_eq!(snapshot.decompressions, 1);
+        assert_eq!(
+            snapshot.decompression_uncompressed_bytes,
+            init_table.serialized_len() as u64
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingErrorHandler {
+        kinds: Mutex<Vec<&'static str>>,
+    }
+
+    impl DecodeErrorHandler for RecordingErrorHandler {
+        fn on_decode_error(&self, err: &crate::DecodeError) {
+            self.kinds.lock().unwrap().push(err.get_kind());
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_error_is_reported_before_connection_is_dropped() {
+        let handler = Arc::new(RecordingErrorHandler::default());
+        let mut limits = DecodeLimits::strict();
+        limits.max_byte_array_size = 16;
+
+        let mut serializer = MyNoSqlReaderTcpSerializer::new_with_limits(limits)
+            .with_decode_error_handler(handler.clone());
+
+        let payload = MyNoSqlTcpContract::InitTable {
+            table_name: "table".to_string(),
+            data: vec![0; 17],
+        }
+        .serialize();
+
+        let mut reader = SocketReaderInMem::new(payload);
+        let result = TcpSocketSerializer::deserialize(&mut serializer, &mut reader).await;
+
+        assert!(result.is_err());
+        assert_eq!(*handler.kinds.lock().unwrap(), vec!["ByteArrayTooLarge"]);
+    }
+
+    #[test]
+    fn checksum_algorithms_which_are_not_compiled_in_are_not_advertised() {
+        let serializer = MyNoSqlReaderTcpSerializer::new()
+            .with_checksum_algorithms(vec![ChecksumAlgorithm::XxHash64, ChecksumAlgorithm::Crc32c]);
+
+        assert_eq!(
+            serializer.get_capabilities().checksum_algorithms,
+            ChecksumAlgorithm::get_supported()
+        );
+    }
+
+    #[test]
+    fn packets_are_serialized_into_pooled_buffers() {
+        let serializer = MyNoSqlReaderTcpSerializer::new();
+
+        let frame = serializer.serialize(MyNoSqlTcpContract::Ping);
+        let ptr = frame.as_ptr();
+        serializer.recycle_buffer(frame);
+
+        let frame = serializer.serialize(MyNoSqlTcpContract::Pong);
+        assert_eq!(frame.as_ptr(), ptr);
+        assert_eq!(frame, MyNoSqlTcpContract::Pong.serialize());
+    }
+
+    #[tokio::test]
+    async fn written_frames_go_back_to_the_pool() {
+        use tokio::io::AsyncWriteExt;
+
+        let pool = Arc::new(BufferPool::default());
+        let mut main_node = MyNoSqlReaderTcpSerializer::new().with_buffer_pool(pool.clone());
+        let mut node = MyNoSqlReaderTcpSerializer::new();
+
+        // Checksum envelope is negotiated, so data packets are wrapped as well
+        receive_greeting(&mut main_node, node.serialize(greeting())).await;
+        receive(&mut node, main_node.serialize(MyNoSqlTcpContract::Pong)).await;
+        receive(&mut main_node, node.serialize(MyNoSqlTcpContract::Ping)).await;
+        assert!(main_node.get_negotiated_checksum().is_some());
+
+        let contract = MyNoSqlTcpContract::UpdateRows {
+            table_name: "table".to_string(),
+            data: vec![1; 1024],
+        };
+
+        let (mut socket, _peer) = tokio::io::duplex(1024 * 1024);
+
+        for _ in 0..2 {
+            let frame = main_node.try_serialize_pooled(&contract).unwrap();
+            socket.write_all(frame.as_ref()).await.unwrap();
+        }
+
+        let allocated = pool.get_allocated_amount();
+
+        for _ in 0..100 {
+            let frame = main_node.try_serialize_pooled(&contract).unwrap();
+            socket.write_all(frame.as_ref()).await.unwrap();
+        }
+
+        assert_eq!(pool.get_allocated_amount(), allocated);
+    }
+
+    #[test]
+    fn received_dictionary_does_not_replace_ours() {
+        let dictionaries = Arc::new(CompressionDictionaries::new());
+        dictionaries.register(1, "table", vec![1; 8]);
+
+        let mut serializer = MyNoSqlReaderTcpSerializer::new().with_dictionaries(dictionaries);
+
+        serializer.apply_packet(&MyNoSqlTcpContract::CompressionDictionary {
+            dictionary_id: 2,
+            table_name: "table".to_string(),
+            dictionary: vec![2; 8],
+        });
+
+        assert_eq!(
+            serializer.get_dictionaries().get_dictionary_id("table"),
+            Some(1)
+        );
+        assert_eq!(
+            serializer
+                .get_received_dictionaries()
+                .get_dictionary_id("table"),
+            Some(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn baseline_node_does_not_get_capabilities() {
+        let mut main_node = MyNoSqlReaderTcpSerializer::new().with_sync_batches(true);
+
+        let mut payload = vec![crate::tcp_packets::GREETING_FROM_NODE, 1];
+        crate::common_serializers::serialize_pascal_string(&mut payload, "loc");
+        crate::common_serializers::serialize_pascal_string(&mut payload, "1.0");
+        payload.push(1);
+
+        receive_greeting(&mut main_node, payload).await;
+
+        assert!(main_node.is_compression_enabled());
+        assert_eq!(
+            main_node.serialize(MyNoSqlTcpContract::Pong),
+            MyNoSqlTcpContract::Pong.serialize()
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    fn delete_rows() -> MyNoSqlTcpContract {
+        MyNoSqlTcpContract::DeleteRows {
+            table_name: "table".to_string(),
+            rows: vec![crate::DeleteRowTcpContract {
+                partition_key: "pk".to_string(),
+                row_key: "rk".to_string(),
+            }],
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[tokio::test]
+    async fn data_packets_are_encrypted_and_plain_ones_are_refused() {
+        let keys = Arc::new(crate::PreSharedKeys::new(1, [7; 32]));
+        let node = MyNoSqlReaderTcpSerializer::new().with_key_provider(keys.clone());
+        let main_node = MyNoSqlReaderTcpSerializer::new().with_key_provider(keys);
+
+        assert!(node.get_capabilities().supports_encryption);
+
+        let update_last_read_time = MyNoSqlTcpContract::UpdateRowsLastReadTime {
+            confirmation_id: 1,
+            table_name: "table".to_string(),
+            partition_key: "pk".to_string(),
+            row_keys: vec!["rk".to_string()],
+        };
+
+        for contract in [delete_rows(), update_last_read_time] {
+            let frame = node.try_serialize(&contract).unwrap();
+            assert_eq!(frame[0], crate::tcp_packets::ENCRYPTED_PAYLOAD);
+
+            let mut reader = SocketReaderInMem::new(frame);
+            let received = MyNoSqlTcpContract::deserialize(&mut reader).await.unwrap();
+            let received = main_node
+                .unwrap_envelope(received, &CancellationToken::new())
+                .await
+                .unwrap();
+            assert_eq!(received.serialize(), contract.serialize());
+        }
+
+        // Control packets are not encrypted
+        let frame = node.try_serialize(&MyNoSqlTcpContract::Ping).unwrap();
+        assert_eq!(frame, MyNoSqlTcpContract::Ping.serialize());
+
+        let result = main_node
+            .unwrap_envelope(delete_rows(), &CancellationToken::new())
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::DecodeError::UnencryptedPayload {
+                packet_name: "DeleteRows"
+            })
+        ));
+    }
+
+    #[cfg(feature = "encryption")]
+    struct KeysWithoutCurrentKey;
+
+    #[cfg(feature = "encryption")]
+    impl KeyProvider for KeysWithoutCurrentKey {
+        fn get_encryption_key_id(&self) -> u32 {
+            2
+        }
+
+        fn get_key(&self, _key_id: u32) -> Option<[u8; 32]> {
+            None
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn data_packet_is_refused_if_it_can_not_be_encrypted() {
+        let serializer =
+            MyNoSqlReaderTcpSerializer::new().with_key_provider(Arc::new(KeysWithoutCurrentKey));
+
+        assert!(matches!(
+            serializer.try_serialize(&delete_rows()),
+            Err(SerializeError::UnknownEncryptionKey(2))
+        ));
+
+        // Nothing is written instead of the plain text frame
+        assert!(serializer.serialize(delete_rows()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn encrypted_payload_is_refused_without_keys() {
+        let serializer = MyNoSqlReaderTcpSerializer::new();
+
+        let contract = MyNoSqlTcpContract::EncryptedPayload {
+            key_id: 1,
+            nonce: [0; crate::payload_encryption::ENCRYPTION_NONCE_SIZE],
+            ciphertext: vec![1, 2, 3],
+        };
+
+        let result = serializer
+            .unwrap_envelope(contract, &CancellationToken::new())
+            .await;
+
+        if crate::payload_encryption::is_supported() {
+            assert!(matches!(
+                result,
+                Err(crate::DecodeError::UnknownEncryptionKey(1))
+            ));
+        } else {
+            assert!(matches!(
+                result,
+                Err(crate::DecodeError::UnsupportedEncryption)
+            ));
+        }
     }
 }
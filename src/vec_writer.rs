@@ -6,11 +6,9 @@ pub struct VecWriter {
 }
 
 impl VecWriter {
-    pub fn new() -> Self {
-        Self {
-            buf: Vec::new(),
-            pos: 0,
-        }
+    pub fn from_vec(mut buf: Vec<u8>) -> Self {
+        buf.clear();
+        Self { buf, pos: 0 }
     }
 }
 